@@ -0,0 +1,51 @@
+// A looped chord progression on a pad, with its roman numeral analysis.
+//
+//   cargo run --example chord_pad -- FLUID
+
+use avril::chord::{analyze, Quality};
+use avril::event;
+use avril::midi::gm::GmProgram;
+use avril::prelude::*;
+use avril::transport::Output;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let port = std::env::args()
+    .nth(1)
+    .unwrap_or_else(|| "FLUID".to_string());
+  let bar = Duration::from_secs(2);
+  let key = Key::major(Note::new(PitchClass::F, 3));
+  let progression = vec![
+    Chord::new(PitchClass::G, Quality::Minor7),
+    Chord::new(PitchClass::C, Quality::Dominant7),
+    Chord::new(PitchClass::F, Quality::Major7),
+    Chord::new(PitchClass::D, Quality::Minor7),
+  ];
+  let numerals: Vec<_> = analyze(&key, &progression)
+    .iter()
+    .map(|numeral| numeral.to_string())
+    .collect();
+  println!("{}", numerals.join(" - "));
+
+  let length = bar * progression.len() as u32;
+  let chords = Stream::from_timestamps(
+    progression
+      .into_iter()
+      .enumerate()
+      .map(|(i, chord)| (bar * i as u32, chord)),
+  );
+  let notes = Pattern::from_stream(chords, length)
+    .looped()
+    .flat_map(move |chord| {
+      chord
+        .voice(Note::new(PitchClass::C, 3))
+        .into_iter()
+        .map(|note| NoteEvent::new(note, 60, bar))
+        .collect::<Vec<_>>()
+    })
+    .take(length * 4);
+  let program = GmProgram::Pad2Warm.into();
+  let messages = Stream::immediate(Message::ProgramChange(Channel::Ch1, program))
+    .chain(event::lower(Channel::Ch1, notes));
+  Output::connect(&port)?.play(messages)
+}
@@ -0,0 +1,39 @@
+// A one bar drum pattern on the General MIDI percussion channel, looped with
+// swing and a little human timing.
+//
+//   cargo run --example drum_machine -- FLUID
+
+use avril::event;
+use avril::midi::gm::drums;
+use avril::prelude::*;
+use avril::transport::Output;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let port = std::env::args()
+    .nth(1)
+    .unwrap_or_else(|| "FLUID".to_string());
+  let step = Duration::from_millis(125);
+  let mut hits = Vec::new();
+  for i in 0..16 {
+    let time = step * i;
+    match i % 8 {
+      0 => hits.push((time, (drums::BASS_DRUM_1, 110))),
+      4 => hits.push((time, (drums::ACOUSTIC_SNARE, 100))),
+      _ => (),
+    }
+    if i == 14 {
+      hits.push((time, (drums::OPEN_HI_HAT, 80)));
+    } else if i % 2 == 0 {
+      hits.push((time, (drums::CLOSED_HI_HAT, 70)));
+    }
+  }
+  let bar = Pattern::from_stream(Stream::from_timestamps(hits), step * 16);
+  let notes = bar
+    .looped()
+    .swing(step, 0.6)
+    .humanize(Seed::new("drum machine"), Duration::from_millis(4))
+    .map(move |(drum, velocity)| NoteEvent::new(Note::from_midi(drum), velocity, step / 2))
+    .take(step * 16 * 16);
+  Output::connect(&port)?.play(event::lower(Channel::Ch10, notes))
+}
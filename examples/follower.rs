@@ -0,0 +1,39 @@
+// Records a few bars from a MIDI input, then loops them while following a
+// key change each time round, as an accompanist reading a chart might.
+//
+//   cargo run --example follower -- "MIDI keyboard" FLUID
+
+use avril::capture::{follow_key, Capture, Quantize, Split};
+use avril::prelude::*;
+use avril::transport::Output;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let mut args = std::env::args().skip(1);
+  let input = args.next().unwrap_or_default();
+  let output = args.next().unwrap_or_else(|| "FLUID".to_string());
+  let beat = Duration::from_millis(500);
+  let length = beat * 8;
+
+  let capture = Capture::start(&input)?;
+  println!("recording for {} seconds in C major", length.as_secs());
+  std::thread::sleep(length);
+  let take = capture.stop_quantized(&Quantize::new(beat / 4));
+
+  let from = Key::major(Note::new(PitchClass::C, 4));
+  let keys = || {
+    let changes = [PitchClass::F, PitchClass::G, PitchClass::C]
+      .iter()
+      .map(|&tonic| (length, Key::major(Note::new(tonic, 4))))
+      .collect();
+    Var::from_updates(from.clone(), Pattern::new(changes, length * 3).looped())
+  };
+  let parts = take.quantized.split(Split::Channel);
+  let messages = Stream::merge_all(
+    parts
+      .iter()
+      .map(|(_, pattern)| follow_key(pattern, from.clone(), keys())),
+  )
+  .take(length * 6);
+  Output::connect(&output)?.play(messages)
+}
@@ -0,0 +1,31 @@
+// A single generated melody. Plays on the first MIDI output port whose name
+// starts with the given prefix, FluidSynth's by default:
+//
+//   cargo run --example minimal -- FLUID
+
+use avril::event;
+use avril::generator::Phrases;
+use avril::prelude::*;
+use avril::transport::Output;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let port = std::env::args()
+    .nth(1)
+    .unwrap_or_else(|| "FLUID".to_string());
+  let ctx = GenCtx::new(
+    Key::major(Note::new(PitchClass::C, 4)),
+    Duration::from_millis(250),
+  );
+  let phrases = Phrases {
+    params: Params {
+      range: Range::new(Note::new(PitchClass::C, 4), Note::new(PitchClass::C, 6)),
+      ..Params::new(ctx.beat)
+    },
+    ..Phrases::default()
+  };
+  let notes = phrases
+    .generate(&ctx, Seed::new("minimal"))
+    .take(Duration::from_secs(30));
+  Output::connect(&port)?.play(event::lower(Channel::Ch1, notes))
+}
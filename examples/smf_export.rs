@@ -0,0 +1,41 @@
+// Writes a generated melody, with solfege lyrics, to a Standard MIDI File.
+//
+//   cargo run --example smf_export -- melody.mid
+
+use avril::event;
+use avril::generator::Phrases;
+use avril::midi::smf::{lyrics, Event, Lyrics, Smf};
+use avril::prelude::*;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let path = std::env::args()
+    .nth(1)
+    .unwrap_or_else(|| "avril.mid".to_string());
+  let quarter = Duration::from_millis(500);
+  let length = quarter * 32;
+  let ctx = GenCtx::new(Key::major(Note::new(PitchClass::G, 4)), quarter);
+  let phrases = Phrases {
+    params: Params {
+      range: Range::new(Note::new(PitchClass::D, 4), Note::new(PitchClass::G, 5)),
+      ..Params::new(quarter / 2)
+    },
+    ..Phrases::default()
+  };
+
+  // The same seed gives the same notes, once for the lyrics and once to play.
+  let seed = || Seed::new("smf export");
+  let words = lyrics(
+    avril::melody::phrases(&ctx.key, &phrases.params, &phrases.phrasing, seed()),
+    Lyrics::Solfege,
+  );
+  let notes = phrases.generate(&ctx, seed()).take(length);
+  let track = Stream::immediate(Event::TrackName("melody".to_string())).chain(
+    words
+      .take(length)
+      .merge(event::lower(Channel::Ch1, notes).map(Event::Message)),
+  );
+  std::fs::write(&path, Smf::new(quarter).write(vec![track]))?;
+  println!("wrote {}", path);
+  Ok(())
+}
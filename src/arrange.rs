@@ -0,0 +1,233 @@
+use crate::melody::{self, Durations, Params};
+use crate::seed::Seed;
+use crate::stream::Stream;
+use crate::theory::{Key, Note, NoteInKey, PitchClass, Range};
+use crate::var::Var;
+use std::time::Duration;
+
+// How busy a section's voices are.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Density {
+  Sparse,
+  Moderate,
+  Dense,
+}
+
+impl Density {
+  // Note values in quanta, leaning towards long notes when sparse and short
+  // ones when dense.
+  fn durations(self, bar: u32) -> Durations {
+    let values = match self {
+      Self::Sparse => vec![(2, 1.0), (4, 3.0), (6, 2.0), (8, 3.0)],
+      Self::Moderate => vec![(1, 2.0), (2, 4.0), (3, 2.0), (4, 2.0)],
+      Self::Dense => vec![(1, 6.0), (2, 3.0), (3, 1.0)],
+    };
+    Durations::Weighted { values, bar }
+  }
+}
+
+// The part of the pitch range a section's voices share.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Register {
+  Low,
+  Middle,
+  High,
+  Full,
+}
+
+impl Register {
+  pub fn range(self) -> Range {
+    let (lowest, highest) = match self {
+      Self::Low => (2, 4),
+      Self::Middle => (3, 5),
+      Self::High => (4, 6),
+      Self::Full => (2, 6),
+    };
+    Range::new(
+      Note::new(PitchClass::C, lowest),
+      Note::new(PitchClass::C, highest),
+    )
+  }
+}
+
+// A stretch of the piece described by its texture rather than its notes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Section {
+  pub length: Duration,
+  pub density: Density,
+  pub register: Register,
+  pub voices: usize,
+}
+
+impl Section {
+  // Melody parameters for one voice, or None if the section has fewer voices.
+  // The register is shared out between the voices, the first taking the top,
+  // and each keeps at least an octave.
+  pub fn voice_params(&self, base: &Params, voice: usize, bar: u32) -> Option<Params> {
+    if voice >= self.voices {
+      return None;
+    }
+    let Range { lowest, highest } = self.register.range();
+    let spare = highest.midi() as i64 - lowest.midi() as i64 - 12;
+    let drop = match self.voices {
+      1 => 0,
+      n => spare * voice as i64 / (n as i64 - 1),
+    };
+    let top = highest.offset(-drop);
+    Some(Params {
+      durations: self.density.durations(bar),
+      range: Range::new(
+        top.offset(-12 - spare / self.voices as i64).max(lowest),
+        top,
+      ),
+      ..base.clone()
+    })
+  }
+  // How big a change moving on to the next section is, from 0 (none) to 1,
+  // counting changes of density, voices and register equally.
+  pub fn change(&self, next: &Section) -> f64 {
+    let density = (self.density as i64 - next.density as i64).abs() as f64 / 2.0;
+    let voices = (self.voices as f64 - next.voices as f64).abs().min(3.0) / 3.0;
+    let register = if self.register == next.register {
+      0.0
+    } else {
+      1.0
+    };
+    (density + voices + register) / 3.0
+  }
+}
+
+// A piece sketched as a sequence of sections, which each voice follows with
+// its own melody. `bar` is the number of quanta to a bar.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arrangement {
+  pub params: Params,
+  pub bar: u32,
+  pub sections: Vec<Section>,
+}
+
+impl Arrangement {
+  pub fn bar_length(&self) -> Duration {
+    self.params.quantum * self.bar
+  }
+  // Start time of each section after the first, with the size of the change
+  // there.
+  pub fn boundaries(&self) -> Vec<(Duration, f64)> {
+    let mut start = Duration::from_secs(0);
+    self
+      .sections
+      .windows(2)
+      .map(|pair| {
+        start += pair[0].length;
+        (start, pair[0].change(&pair[1]))
+      })
+      .collect()
+  }
+  // The notes of one voice, None while it rests. Each section starts a fresh
+  // melody on the tonic, and the voice falls silent after the last section.
+  pub fn voice<'k>(
+    &self,
+    key: &'k Key,
+    voice: usize,
+    seed: Seed,
+  ) -> Var<'k, Option<NoteInKey<'k>>> {
+    let mut parts = Vec::new();
+    let mut delay = Duration::from_secs(0);
+    for (i, section) in self.sections.iter().enumerate() {
+      let part = match section.voice_params(&self.params, voice, self.bar) {
+        Some(params) => {
+          let first_note = params.range.fold_in_key(key.at(0));
+          melody::melody(key, first_note, &params, seed.fork(i).fork(voice)).map(Some)
+        }
+        None => Var::constant(None),
+      };
+      parts.push((delay, part));
+      delay = section.length;
+    }
+    parts.push((delay, Var::constant(None)));
+    let mut parts = parts.into_iter();
+    let (_, first) = parts.next().unwrap();
+    Var::from_updates(first, Stream::from_iter(parts)).sequence()
+  }
+}
+
+#[test]
+fn test_arrangement() {
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let section = Duration::from_secs(8);
+  let arrangement = Arrangement {
+    params: Params::new(Duration::from_millis(125)),
+    bar: 8,
+    sections: vec![
+      Section {
+        length: section,
+        density: Density::Sparse,
+        register: Register::Low,
+        voices: 2,
+      },
+      Section {
+        length: section,
+        density: Density::Dense,
+        register: Register::Full,
+        voices: 4,
+      },
+    ],
+  };
+  let notes = |voice| {
+    arrangement
+      .voice(&key, voice, Seed::new("test"))
+      .updates()
+      .timestamps()
+      .take_while(|(time, _)| *time <= section * 2)
+      .collect::<Vec<_>>()
+  };
+  let first = |notes: &[(Duration, Option<NoteInKey>)]| {
+    notes.iter().filter(|(time, _)| *time < section).count()
+  };
+
+  let low = Register::Low.range();
+  let top = notes(0);
+  for (time, note) in &top {
+    if *time < section {
+      assert!(low.contains(note.unwrap().note()));
+    }
+  }
+  assert_eq!(top.last().unwrap(), &(section * 2, None));
+  let sparse = first(&top);
+  assert!(top.len() - sparse > sparse * 2);
+
+  let inner = notes(2);
+  assert_eq!(inner[0], (Duration::from_secs(0), None));
+  assert_eq!(first(&inner), 1);
+  assert!(inner.len() > 10);
+  assert!(notes(4).iter().all(|(_, note)| note.is_none()));
+
+  for register in [
+    Register::Low,
+    Register::Middle,
+    Register::High,
+    Register::Full,
+  ] {
+    let Range { lowest, highest } = register.range();
+    for voices in 1..=6 {
+      let section = Section {
+        voices,
+        register,
+        ..arrangement.sections[0]
+      };
+      for voice in 0..voices {
+        let range = section
+          .voice_params(&arrangement.params, voice, 8)
+          .unwrap()
+          .range;
+        assert!(lowest <= range.lowest && range.highest <= highest);
+        assert!(range.highest.midi() - range.lowest.midi() >= 12);
+      }
+    }
+  }
+
+  let change = arrangement.sections[0].change(&arrangement.sections[1]);
+  assert!((change - (1.0 + 2.0 / 3.0 + 1.0) / 3.0).abs() < 1e-9);
+  assert_eq!(arrangement.boundaries(), vec![(section, change)]);
+  assert_eq!(arrangement.bar_length(), Duration::from_secs(1));
+}
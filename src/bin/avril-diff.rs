@@ -0,0 +1,63 @@
+// Compares two rendered performances, each a Standard MIDI File or a stream
+// recording, and lists the notes added, removed and retimed in each track.
+//
+//   cargo run --bin avril-diff -- before.mid after.mid
+
+use avril::midi::diff::diff;
+use avril::midi::smf::{Event, Smf};
+use avril::midi::Message;
+use avril::stream::Stream;
+use std::error::Error;
+use std::time::Duration;
+
+fn tracks(path: &str) -> Result<Vec<Stream<'static, Message>>, Box<dyn Error>> {
+  let bytes = std::fs::read(path)?;
+  if !bytes.starts_with(b"MThd") {
+    return Ok(vec![Stream::replay_from(path)?]);
+  }
+  let (_, tracks) = Smf::read(&bytes)?;
+  Ok(
+    tracks
+      .into_iter()
+      .map(|track| {
+        track.filter_map(|event| match event {
+          Event::Message(message) => Some(message),
+          _ => None,
+        })
+      })
+      .collect(),
+  )
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if args.len() != 2 {
+    return Err("usage: avril-diff BEFORE AFTER".into());
+  }
+  let mut before = tracks(&args[0])?;
+  let mut after = tracks(&args[1])?;
+  let count = before.len().max(after.len());
+  before.resize_with(count, Stream::empty);
+  after.resize_with(count, Stream::empty);
+  let mut changed = false;
+  for (i, (before, after)) in before.into_iter().zip(after).enumerate() {
+    let changes = diff(
+      before,
+      after,
+      Duration::from_millis(2),
+      Duration::from_millis(250),
+    );
+    if changes.is_empty() {
+      continue;
+    }
+    changed = true;
+    println!("track {}:", i + 1);
+    for change in changes {
+      println!("  {}", change);
+    }
+  }
+  if !changed {
+    println!("no differences");
+  }
+  Ok(())
+}
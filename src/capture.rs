@@ -0,0 +1,483 @@
+use crate::midi::{Channel, Message, MessageExt};
+use crate::pattern::Pattern;
+use crate::stream::Stream;
+use crate::theory::{Key, Note, Range};
+use crate::var::Var;
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+// How a recording is divided into separately looped parts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Split {
+  // One part per MIDI channel.
+  Channel,
+  // Notes below the given note form the lower part, the rest the upper part.
+  Register(Note),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Part {
+  Channel(Channel),
+  Lower,
+  Upper,
+}
+
+// Records incoming MIDI messages from an input port until stopped.
+pub struct Capture {
+  connection: MidiInputConnection<()>,
+  received: Receiver<(Duration, Vec<u8>)>,
+  start: Instant,
+}
+
+impl Capture {
+  pub fn start(port_prefix: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    let mut input = MidiInput::new("avril")?;
+    input.ignore(Ignore::All);
+    let ports = input.ports();
+    let port = ports
+      .iter()
+      .find(|port| {
+        input
+          .port_name(port)
+          .unwrap_or_default()
+          .starts_with(port_prefix)
+      })
+      .ok_or("no matching MIDI input port")?;
+    let (sender, received) = channel();
+    let start = Instant::now();
+    let connection = input.connect(
+      port,
+      "avril_capture",
+      move |_, bytes, _| {
+        let _ = sender.send((start.elapsed(), bytes.to_vec()));
+      },
+      (),
+    )?;
+    Ok(Self {
+      connection,
+      received,
+      start,
+    })
+  }
+  // Stops recording, keeping both the raw recording and a quantized copy.
+  pub fn stop_quantized(self, quantize: &Quantize) -> Take {
+    let raw = self.stop();
+    Take {
+      quantized: raw.quantized(quantize),
+      raw,
+    }
+  }
+  pub fn stop(self) -> Recording {
+    // Closed first, so no message can arrive after the end of the recording.
+    self.connection.close();
+    let length = self.start.elapsed();
+    Recording {
+      messages: self
+        .received
+        .try_iter()
+        .filter_map(|(time, bytes)| Some((time, Message::decode(&bytes)?)))
+        .collect(),
+      length,
+    }
+  }
+}
+
+// Input quantization settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantize {
+  pub grid: Duration,
+  // How far towards the grid to move each note, from 0 (not at all) to 1.
+  pub strength: f64,
+  // Position of every second grid point within each pair, as a proportion of
+  // the pair: 0.5 is straight time and about 0.67 a triplet swing.
+  pub swing: f64,
+}
+
+impl Quantize {
+  pub fn new(grid: Duration) -> Self {
+    Self {
+      grid,
+      strength: 1.0,
+      swing: 0.5,
+    }
+  }
+  // Seconds to add to the given time to move it towards the grid.
+  fn shift(&self, time: f64) -> f64 {
+    let pair = self.grid.as_secs_f64() * 2.0;
+    let start = (time / pair).floor() * pair;
+    let target = [start, start + pair * self.swing, start + pair]
+      .iter()
+      .copied()
+      .min_by(|a, b| (a - time).abs().partial_cmp(&(b - time).abs()).unwrap())
+      .unwrap();
+    (target - time) * self.strength
+  }
+}
+
+// A recording kept both as played and quantized, for comparison.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Take {
+  pub raw: Recording,
+  pub quantized: Recording,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+  // Timestamps are measured from the start of the recording.
+  pub messages: Vec<(Duration, Message)>,
+  pub length: Duration,
+}
+
+impl Recording {
+  // Moves each note on towards the nearest grid point, and its note off by the
+  // same amount so that note lengths are preserved. Other messages keep their
+  // original timing.
+  pub fn quantized(&self, quantize: &Quantize) -> Recording {
+    let mut shifts: Vec<((Channel, u8), f64)> = Vec::new();
+    let length = self.length.as_secs_f64();
+    let mut messages: Vec<(Duration, Message)> = self
+      .messages
+      .iter()
+      .map(|(time, message)| {
+        let time = time.as_secs_f64();
+        let shift = match *message {
+          Message::NoteOn(ch, note, _) => {
+            let shift = quantize.shift(time);
+            shifts.retain(|&(key, _)| key != (ch, note));
+            shifts.push(((ch, note), shift));
+            shift
+          }
+          Message::NoteOff(ch, note, _) => shifts
+            .iter()
+            .position(|&(key, _)| key == (ch, note))
+            .map(|i| shifts.remove(i).1)
+            .unwrap_or(0.0),
+          _ => 0.0,
+        };
+        let time = (time + shift).max(0.0).min(length);
+        (Duration::from_secs_f64(time), message.clone())
+      })
+      .collect();
+    messages.sort_by_key(|(time, _)| *time);
+    Recording {
+      messages,
+      length: self.length,
+    }
+  }
+  // Divides the recording into loopable parts, each as long as the whole
+  // recording. Messages that aren't tied to a note (controllers, pitch bend
+  // and so on) are copied into every part of a register split.
+  pub fn split(&self, split: Split) -> Vec<(Part, Pattern<Message>)> {
+    let part_of = |message: &Message| -> Vec<Part> {
+      match (split, message) {
+        (Split::Channel, message) => message.channel().map(Part::Channel).into_iter().collect(),
+        (Split::Register(boundary), Message::NoteOn(_, note, _))
+        | (Split::Register(boundary), Message::NoteOff(_, note, _))
+        | (Split::Register(boundary), Message::PolyphonicPressure(_, note, _)) => {
+          if Note::from_midi(*note) < boundary {
+            vec![Part::Lower]
+          } else {
+            vec![Part::Upper]
+          }
+        }
+        (Split::Register(_), _) => vec![Part::Lower, Part::Upper],
+      }
+    };
+    let mut parts: Vec<(Part, Vec<(Duration, Message)>)> = Vec::new();
+    for (time, message) in &self.messages {
+      for part in part_of(message) {
+        match parts.iter_mut().find(|(p, _)| *p == part) {
+          Some((_, messages)) => messages.push((*time, message.clone())),
+          None => parts.push((part, vec![(*time, message.clone())])),
+        }
+      }
+    }
+    parts.sort_by_key(|(part, _)| *part);
+    parts
+      .into_iter()
+      .map(|(part, messages)| {
+        let events = Stream::from_timestamps(messages).into_iter().collect();
+        (part, Pattern::new(events, self.length))
+      })
+      .collect()
+  }
+}
+
+// Loops a pattern recorded in one key, transposing its notes diatonically
+// into whichever key is current. Notes keep sounding as they were played
+// until they are released, even if the key changes in the meantime. Notes
+// transposed beyond the MIDI range are folded back into it by octaves.
+pub fn follow_key<'a>(
+  pattern: &Pattern<Message>,
+  from: Key,
+  keys: Var<'a, Key>,
+) -> Stream<'a, Message> {
+  enum Follow {
+    Key(Key),
+    Message(Message),
+    End,
+  }
+  let mut key = from.clone();
+  let mut sounding: Vec<((Channel, u8), u8)> = Vec::new();
+  keys
+    .updates()
+    .map(Follow::Key)
+    .merge(
+      pattern
+        .looped()
+        .map(Follow::Message)
+        .chain(Stream::immediate(Follow::End)),
+    )
+    // Ends with the pattern, which only happens if it is empty, however long
+    // the keys go on.
+    .take_while(|event| !matches!(event, Follow::End))
+    .filter_map(move |event| {
+      let message = match event {
+        Follow::Key(new_key) => {
+          key = new_key;
+          return None;
+        }
+        Follow::Message(message) => message,
+        Follow::End => return None,
+      };
+      Some(match message {
+        Message::NoteOn(ch, note, velocity) => {
+          let transposed = Range::midi()
+            .fold(key.transpose_from(&from, Note::from_midi(note)))
+            .midi();
+          sounding.push(((ch, note), transposed));
+          Message::NoteOn(ch, transposed, velocity)
+        }
+        Message::NoteOff(ch, note, velocity) => {
+          let transposed = match sounding.iter().position(|&(n, _)| n == (ch, note)) {
+            Some(i) => sounding.remove(i).1,
+            None => note,
+          };
+          Message::NoteOff(ch, transposed, velocity)
+        }
+        Message::PolyphonicPressure(ch, note, pressure) => {
+          let transposed = sounding
+            .iter()
+            .find(|&&(n, _)| n == (ch, note))
+            .map_or(note, |&(_, t)| t);
+          Message::PolyphonicPressure(ch, transposed, pressure)
+        }
+        message => message,
+      })
+    })
+}
+
+// Running average of note-on velocities, updated with each note on.
+// `smoothing` is the weight kept by the previous average, between 0 (follow
+// each note exactly) and 1 (never change).
+pub fn velocity_average<'a>(
+  messages: Stream<'a, Message>,
+  initial: f64,
+  smoothing: f64,
+) -> Var<'a, f64> {
+  let mut average = initial;
+  Var::from_updates(
+    initial,
+    messages.filter_map(move |message| match message {
+      Message::NoteOn(_, _, velocity) => {
+        average = smoothing * average + (1.0 - smoothing) * velocity as f64;
+        Some(average)
+      }
+      _ => None,
+    }),
+  )
+}
+
+impl<'a> Var<'a, f64> {
+  // Follows a controller on one channel of recorded messages, such as those
+  // of a Recording or a MIDI file, scaled from 0 to 1. Holds `initial` until
+  // the controller first moves.
+  //
+  // Live input isn't supported, since playback would stall waiting for the
+  // next incoming message before it could merge it with anything else.
+  pub fn from_cc(
+    messages: Stream<'a, Message>,
+    channel: Channel,
+    controller: u8,
+    initial: f64,
+  ) -> Self {
+    Var::from_updates(
+      initial,
+      messages.filter_map(move |message| match message {
+        Message::ControlChange(ch, cc, value) if ch == channel && cc == controller => {
+          Some(value as f64 / 127.0)
+        }
+        _ => None,
+      }),
+    )
+  }
+}
+
+#[test]
+fn test_split() {
+  use crate::theory::PitchClass;
+  let ms = Duration::from_millis;
+  let recording = Recording {
+    messages: vec![
+      (ms(0), Message::NoteOn(Channel::Ch1, 40, 90)),
+      (ms(10), Message::NoteOn(Channel::Ch2, 72, 90)),
+      (ms(20), Message::ControlChange(Channel::Ch1, 64, 127)),
+      (ms(30), Message::NoteOff(Channel::Ch1, 40, 0)),
+      (ms(40), Message::NoteOff(Channel::Ch2, 72, 0)),
+    ],
+    length: ms(50),
+  };
+  let by_channel = recording.split(Split::Channel);
+  assert_eq!(by_channel.len(), 2);
+  assert_eq!(by_channel[0].0, Part::Channel(Channel::Ch1));
+  assert_eq!(
+    by_channel[0].1.events(),
+    &[
+      (ms(0), Message::NoteOn(Channel::Ch1, 40, 90)),
+      (ms(20), Message::ControlChange(Channel::Ch1, 64, 127)),
+      (ms(10), Message::NoteOff(Channel::Ch1, 40, 0)),
+    ]
+  );
+  let by_register = recording.split(Split::Register(Note::new(PitchClass::C, 4)));
+  assert_eq!(by_register[0].0, Part::Lower);
+  assert_eq!(by_register[0].1.events().len(), 3);
+  assert_eq!(by_register[1].0, Part::Upper);
+  assert_eq!(
+    by_register[1].1.events(),
+    &[
+      (ms(10), Message::NoteOn(Channel::Ch2, 72, 90)),
+      (ms(10), Message::ControlChange(Channel::Ch1, 64, 127)),
+      (ms(20), Message::NoteOff(Channel::Ch2, 72, 0)),
+    ]
+  );
+  assert_eq!(by_register[1].1.length(), ms(50));
+}
+
+#[test]
+fn test_quantize() {
+  let ms = Duration::from_millis;
+  let recording = Recording {
+    messages: vec![
+      (ms(10), Message::NoteOn(Channel::Ch1, 60, 90)),
+      (ms(90), Message::NoteOff(Channel::Ch1, 60, 0)),
+      (ms(95), Message::ControlChange(Channel::Ch1, 1, 10)),
+      (ms(160), Message::NoteOn(Channel::Ch1, 62, 90)),
+      (ms(390), Message::NoteOn(Channel::Ch1, 64, 90)),
+    ],
+    length: ms(400),
+  };
+  let times = |quantize: Quantize| {
+    recording
+      .quantized(&quantize)
+      .messages
+      .iter()
+      .map(|(time, _)| time.as_millis())
+      .collect::<Vec<_>>()
+  };
+  let grid = Quantize::new(ms(100));
+  assert_eq!(times(grid), vec![0, 80, 95, 200, 400]);
+  assert_eq!(
+    times(Quantize {
+      strength: 0.5,
+      ..grid
+    }),
+    vec![5, 85, 95, 180, 395]
+  );
+  assert_eq!(
+    times(Quantize {
+      swing: 2.0 / 3.0,
+      ..grid
+    }),
+    vec![0, 80, 95, 133, 400]
+  );
+}
+
+#[test]
+fn test_follow_key() {
+  use crate::theory::PitchClass;
+  let ms = Duration::from_millis;
+  let c_major = Key::major(Note::new(PitchClass::C, 4));
+  let f_major = Key::major(Note::new(PitchClass::F, 4));
+  let pattern = Pattern::new(
+    vec![
+      (ms(0), Message::NoteOn(Channel::Ch1, 71, 90)),
+      (ms(50), Message::NoteOff(Channel::Ch1, 71, 0)),
+    ],
+    ms(100),
+  );
+  let keys = Var::from_updates(c_major.clone(), Stream::from_iter(vec![(ms(30), f_major)]));
+  assert_eq!(
+    follow_key(&pattern, c_major, keys)
+      .into_iter()
+      .take(4)
+      .collect::<Vec<_>>(),
+    vec![
+      (ms(0), Message::NoteOn(Channel::Ch1, 71, 90)),
+      (ms(50), Message::NoteOff(Channel::Ch1, 71, 0)),
+      (ms(50), Message::NoteOn(Channel::Ch1, 76, 90)),
+      (ms(50), Message::NoteOff(Channel::Ch1, 76, 0)),
+    ]
+  );
+  let silent = Pattern::new(Vec::new(), ms(100));
+  let changing = Var::from_fn(ms(10), |_| Key::major(Note::new(PitchClass::G, 4)));
+  assert_eq!(
+    follow_key(&silent, Key::major(Note::new(PitchClass::C, 4)), changing).count_events(),
+    0
+  );
+  let high = Pattern::new(
+    vec![
+      (ms(0), Message::NoteOn(Channel::Ch1, 127, 90)),
+      (ms(50), Message::NoteOff(Channel::Ch1, 127, 0)),
+    ],
+    ms(100),
+  );
+  let d_major = Var::constant(Key::major(Note::new(PitchClass::D, 4)));
+  assert_eq!(
+    follow_key(&high, Key::major(Note::new(PitchClass::C, 4)), d_major)
+      .into_iter()
+      .take(2)
+      .collect::<Vec<_>>(),
+    vec![
+      (ms(0), Message::NoteOn(Channel::Ch1, 117, 90)),
+      (ms(50), Message::NoteOff(Channel::Ch1, 117, 0)),
+    ]
+  );
+}
+
+#[test]
+fn test_velocity_average() {
+  let ms = Duration::from_millis;
+  let ch = Channel::Ch1;
+  let messages = Stream::from_iter(vec![
+    (ms(0), Message::NoteOn(ch, 60, 100)),
+    (ms(10), Message::NoteOff(ch, 60, 0)),
+    (ms(10), Message::NoteOn(ch, 60, 20)),
+  ]);
+  assert_eq!(
+    velocity_average(messages, 60.0, 0.5)
+      .updates()
+      .into_iter()
+      .collect::<Vec<_>>(),
+    vec![(ms(0), 60.0), (ms(0), 80.0), (ms(20), 50.0)]
+  );
+}
+
+#[test]
+fn test_from_cc() {
+  let ms = Duration::from_millis;
+  let ch = Channel::Ch1;
+  let messages = Stream::from_iter(vec![
+    (ms(0), Message::NoteOn(ch, 60, 100)),
+    (ms(10), Message::ControlChange(ch, 74, 127)),
+    (ms(10), Message::ControlChange(Channel::Ch2, 74, 0)),
+    (ms(10), Message::ControlChange(ch, 1, 0)),
+    (ms(10), Message::ControlChange(ch, 74, 0)),
+  ]);
+  assert_eq!(
+    Var::from_cc(messages, ch, 74, 0.5)
+      .updates()
+      .collect_timed(),
+    vec![(ms(0), 0.5), (ms(10), 1.0), (ms(30), 0.0)]
+  );
+}
@@ -0,0 +1,398 @@
+use crate::theory::{Direction, Key, Note, PitchClass, Scale};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Quality {
+  Major,
+  Minor,
+  Diminished,
+  Augmented,
+  Sus2,
+  Sus4,
+  Dominant7,
+  Major7,
+  Minor7,
+  HalfDiminished7,
+  Diminished7,
+}
+
+impl Quality {
+  // In order of preference when several qualities match equally well.
+  pub const ALL: [Quality; 11] = [
+    Self::Major,
+    Self::Minor,
+    Self::Dominant7,
+    Self::Minor7,
+    Self::Major7,
+    Self::Diminished,
+    Self::HalfDiminished7,
+    Self::Diminished7,
+    Self::Augmented,
+    Self::Sus4,
+    Self::Sus2,
+  ];
+  // Semitones above the root of each chord tone, root first.
+  pub fn intervals(self) -> &'static [i64] {
+    match self {
+      Self::Major => &[0, 4, 7],
+      Self::Minor => &[0, 3, 7],
+      Self::Diminished => &[0, 3, 6],
+      Self::Augmented => &[0, 4, 8],
+      Self::Sus2 => &[0, 2, 7],
+      Self::Sus4 => &[0, 5, 7],
+      Self::Dominant7 => &[0, 4, 7, 10],
+      Self::Major7 => &[0, 4, 7, 11],
+      Self::Minor7 => &[0, 3, 7, 10],
+      Self::HalfDiminished7 => &[0, 3, 6, 10],
+      Self::Diminished7 => &[0, 3, 6, 9],
+    }
+  }
+  fn suffix(self) -> &'static str {
+    match self {
+      Self::Major => "",
+      Self::Minor => "m",
+      Self::Diminished => "°",
+      Self::Augmented => "+",
+      Self::Sus2 => "sus2",
+      Self::Sus4 => "sus4",
+      Self::Dominant7 => "7",
+      Self::Major7 => "maj7",
+      Self::Minor7 => "m7",
+      Self::HalfDiminished7 => "ø7",
+      Self::Diminished7 => "°7",
+    }
+  }
+  pub fn is_minor(self) -> bool {
+    self.intervals()[1] == 3
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Chord {
+  pub root: PitchClass,
+  pub quality: Quality,
+  // Index into the chord tones of the tone in the bass; 0 for root position.
+  pub inversion: usize,
+}
+
+impl Chord {
+  pub fn new(root: PitchClass, quality: Quality) -> Self {
+    Self {
+      root,
+      quality,
+      inversion: 0,
+    }
+  }
+  pub fn pitch_classes(&self) -> Vec<PitchClass> {
+    self
+      .quality
+      .intervals()
+      .iter()
+      .map(|&i| PitchClass::from_ordinal(self.root.ordinal() + i))
+      .collect()
+  }
+  pub fn bass(&self) -> PitchClass {
+    self.pitch_classes()[self.inversion]
+  }
+  // Close position voicing, stacking the chord tones upwards from the bass at
+  // or above the given note.
+  pub fn voice(&self, lowest: Note) -> Vec<Note> {
+    let mut tones = self.pitch_classes();
+    tones.rotate_left(self.inversion);
+    let mut notes: Vec<Note> = Vec::new();
+    for pitch_class in tones {
+      let floor = notes.last().map_or(lowest, |&last| last.offset(1));
+      let mut note = Note::new(pitch_class, floor.octave());
+      while note < floor {
+        note = note.offset(12);
+      }
+      notes.push(note);
+    }
+    notes
+  }
+  // The chord that best explains a set of simultaneously sounding notes:
+  // fewest non-chord tones first, then fewest missing chord tones, preferring
+  // simpler qualities and then root position on ties. At least two distinct pitch
+  // classes are needed to name a chord.
+  pub fn recognize(notes: &[Note]) -> Option<Chord> {
+    let mut sounding: Vec<PitchClass> = notes.iter().map(|n| n.pitch_class()).collect();
+    sounding.sort_by_key(|pc| pc.ordinal());
+    sounding.dedup();
+    if sounding.len() < 2 {
+      return None;
+    }
+    let bass = notes.iter().min()?.pitch_class();
+    sounding
+      .iter()
+      .flat_map(|&root| {
+        Quality::ALL
+          .iter()
+          .map(move |&quality| Chord::new(root, quality))
+      })
+      .enumerate()
+      .map(|(preference, chord)| {
+        let tones = chord.pitch_classes();
+        let extra = sounding.iter().filter(|pc| !tones.contains(pc)).count();
+        let missing = tones.iter().filter(|pc| !sounding.contains(pc)).count();
+        let inversion = tones.iter().position(|&pc| pc == bass);
+        let cost = (extra, missing, preference, inversion != Some(0));
+        (
+          cost,
+          Chord {
+            inversion: inversion.unwrap_or(0),
+            ..chord
+          },
+        )
+      })
+      .min_by_key(|&(cost, _)| cost)
+      .map(|(_, chord)| chord)
+  }
+}
+
+impl std::fmt::Display for Chord {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}{}", self.root, self.quality.suffix())?;
+    if self.inversion != 0 {
+      write!(f, "/{}", self.bass())?;
+    }
+    Ok(())
+  }
+}
+
+// A chord's function relative to a key, e.g. "ii°6" or "bVII".
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RomanNumeral {
+  // 1-based scale degree of the root.
+  pub degree: i64,
+  // Chromatic alteration of the root relative to the key; -1 for a flat.
+  pub alteration: i64,
+  pub quality: Quality,
+  pub inversion: usize,
+  // Degree and quality of the chord a secondary dominant resolves to, as in
+  // "V7/ii". The rest of the numeral is then relative to that chord.
+  pub tonicizes: Option<(i64, Quality)>,
+  // Whether the chord is borrowed from the parallel major or minor key.
+  pub borrowed: bool,
+}
+
+impl RomanNumeral {
+  // Roots outside the key's scale are described as degrees of the major
+  // scale on the same tonic, altered if necessary with a preference for
+  // flats. Keys whose scales don't have seven notes are analysed as if they
+  // were major.
+  pub fn new(key: &Key, chord: &Chord) -> Self {
+    let major = Scale::major();
+    let scale = if key.scale().num_intervals() == 7 {
+      key.scale()
+    } else {
+      &major
+    };
+    let semitones = (chord.root.ordinal() - key.tonic().pitch_class().ordinal()).rem_euclid(12);
+    let step = |scale: &Scale, alteration: i64| {
+      (0..7).find(|&step| scale.semitones(step, Direction::Ascending) + alteration == semitones)
+    };
+    let (degree, alteration) = step(scale, 0)
+      .or_else(|| step(&major, 0))
+      .map(|step| (step, 0))
+      .or_else(|| step(&major, -1).map(|step| (step, -1)))
+      .or_else(|| step(&major, 1).map(|step| (step, 1)))
+      .unwrap();
+    let fits = |key: &Key| {
+      let pitch_classes = key.pitch_classes();
+      chord
+        .pitch_classes()
+        .iter()
+        .all(|pc| pitch_classes.contains(pc))
+    };
+    Self {
+      degree: degree + 1,
+      alteration,
+      quality: chord.quality,
+      inversion: chord.inversion,
+      tonicizes: None,
+      borrowed: !fits(key) && key.parallel().is_some_and(|parallel| fits(&parallel)),
+    }
+  }
+  // Describes a chord as the secondary dominant of the one following it, if it
+  // is a chromatic major or dominant seventh chord resolving down a fifth to a
+  // diatonic chord other than the tonic.
+  fn secondary(key: &Key, chord: &Chord, next: &Chord) -> Option<Self> {
+    let pitch_classes = key.pitch_classes();
+    let diatonic = |chord: &Chord| {
+      chord
+        .pitch_classes()
+        .iter()
+        .all(|pc| pitch_classes.contains(pc))
+    };
+    let target = Self::new(key, next);
+    let resolves = (chord.root.ordinal() - next.root.ordinal()).rem_euclid(12) == 7;
+    let dominant = matches!(chord.quality, Quality::Major | Quality::Dominant7);
+    if !dominant || !resolves || diatonic(chord) || !diatonic(next) || target.degree == 1 {
+      return None;
+    }
+    Some(Self {
+      degree: 5,
+      alteration: 0,
+      quality: chord.quality,
+      inversion: chord.inversion,
+      tonicizes: Some((target.degree, target.quality)),
+      borrowed: false,
+    })
+  }
+}
+
+impl std::fmt::Display for RomanNumeral {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    const NUMERALS: [&str; 7] = ["I", "II", "III", "IV", "V", "VI", "VII"];
+    let case = |degree: i64, quality: Quality| {
+      let numeral = NUMERALS[(degree - 1) as usize];
+      if quality.is_minor() {
+        numeral.to_lowercase()
+      } else {
+        numeral.to_string()
+      }
+    };
+    let accidental = match self.alteration {
+      -1 => "b",
+      1 => "#",
+      _ => "",
+    };
+    write!(f, "{}{}", accidental, case(self.degree, self.quality))?;
+    let marker = match self.quality {
+      Quality::Diminished | Quality::Diminished7 => "°",
+      Quality::HalfDiminished7 => "ø",
+      Quality::Augmented => "+",
+      Quality::Major7 => "maj",
+      _ => "",
+    };
+    let figures: &[&str] = if self.quality.intervals().len() == 4 {
+      &["7", "65", "43", "42"]
+    } else {
+      &["", "6", "64"]
+    };
+    write!(f, "{}{}", marker, figures[self.inversion])?;
+    match self.quality {
+      Quality::Sus2 => f.write_str("sus2")?,
+      Quality::Sus4 => f.write_str("sus4")?,
+      _ => (),
+    }
+    match self.tonicizes {
+      Some((degree, quality)) => write!(f, "/{}", case(degree, quality)),
+      None => Ok(()),
+    }
+  }
+}
+
+// Describes each chord of a progression by its function in the given key,
+// recognising secondary dominants by how they resolve.
+pub fn analyze<'c, I>(key: &Key, chords: I) -> Vec<RomanNumeral>
+where
+  I: IntoIterator<Item = &'c Chord>,
+{
+  let chords: Vec<_> = chords.into_iter().collect();
+  chords
+    .iter()
+    .enumerate()
+    .map(|(i, chord)| {
+      chords
+        .get(i + 1)
+        .and_then(|next| RomanNumeral::secondary(key, chord, next))
+        .unwrap_or_else(|| RomanNumeral::new(key, chord))
+    })
+    .collect()
+}
+
+#[test]
+fn test_recognize() {
+  use PitchClass::*;
+  let recognize = |notes: &[(PitchClass, i64)]| {
+    let notes: Vec<_> = notes.iter().map(|&(pc, o)| Note::new(pc, o)).collect();
+    Chord::recognize(&notes).map(|chord| chord.to_string())
+  };
+  assert_eq!(recognize(&[]), None);
+  assert_eq!(recognize(&[(C, 4), (C, 5)]), None);
+  assert_eq!(recognize(&[(C, 4), (E, 4), (G, 4)]).unwrap(), "C");
+  assert_eq!(recognize(&[(E, 3), (C, 4), (G, 4)]).unwrap(), "C/E");
+  assert_eq!(recognize(&[(A, 3), (C, 4), (E, 4)]).unwrap(), "Am");
+  assert_eq!(recognize(&[(G, 2), (B, 3), (D, 4), (F, 4)]).unwrap(), "G7");
+  assert_eq!(recognize(&[(B, 3), (D, 4), (F, 4)]).unwrap(), "B°");
+  assert_eq!(
+    recognize(&[(C, 4), (E, 4), (G, 4), (B, 4)]).unwrap(),
+    "Cmaj7"
+  );
+  assert_eq!(recognize(&[(D, 2), (A, 4)]).unwrap(), "D");
+  assert_eq!(recognize(&[(FSharp, 2), (D, 4)]).unwrap(), "D/F#");
+  assert_eq!(recognize(&[(C, 4), (F, 4), (G, 4)]).unwrap(), "Csus4");
+}
+
+#[test]
+fn test_analyze() {
+  use PitchClass::*;
+  use Quality::*;
+  let inverted = |root, quality, inversion| Chord {
+    root,
+    quality,
+    inversion,
+  };
+  let show = |key: &Key, chords: &[Chord]| {
+    analyze(key, chords)
+      .iter()
+      .map(|numeral| numeral.to_string())
+      .collect::<Vec<_>>()
+  };
+  let c_major = Key::major(Note::new(C, 4));
+  assert_eq!(
+    show(
+      &c_major,
+      &[
+        Chord::new(C, Major),
+        inverted(D, Minor, 1),
+        Chord::new(G, Dominant7),
+        inverted(G, Dominant7, 2),
+        Chord::new(B, Diminished),
+        Chord::new(ASharp, Major),
+        Chord::new(FSharp, HalfDiminished7),
+        Chord::new(F, Major7),
+        inverted(C, Major, 2),
+        Chord::new(G, Sus4),
+      ]
+    ),
+    vec!["I", "ii6", "V7", "V43", "vii°", "bVII", "bvø7", "IVmaj7", "I64", "Vsus4"]
+  );
+  let a_minor = Key::minor(Note::new(A, 3));
+  assert_eq!(
+    show(
+      &a_minor,
+      &[
+        Chord::new(A, Minor),
+        inverted(B, Diminished, 1),
+        Chord::new(E, Major),
+        Chord::new(GSharp, Diminished7),
+        Chord::new(C, Augmented),
+      ]
+    ),
+    vec!["i", "ii°6", "V", "vii°7", "III+"]
+  );
+  let applied = [
+    Chord::new(C, Major),
+    Chord::new(A, Dominant7),
+    Chord::new(D, Minor),
+    inverted(D, Dominant7, 1),
+    Chord::new(G, Major),
+    Chord::new(E, Major),
+    Chord::new(C, Major),
+    Chord::new(F, Minor),
+    Chord::new(GSharp, Major),
+  ];
+  assert_eq!(
+    show(&c_major, &applied),
+    vec!["I", "V7/ii", "ii", "V65/V", "V", "III", "I", "iv", "bVI"]
+  );
+  let borrowed: Vec<_> = analyze(&c_major, &applied)
+    .iter()
+    .map(|numeral| numeral.borrowed)
+    .collect();
+  assert_eq!(
+    borrowed,
+    vec![false, false, false, false, false, false, false, true, true]
+  );
+}
@@ -0,0 +1,237 @@
+use crate::seed::Seed;
+use crate::stream::Stream;
+use rand::distributions::WeightedIndex;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand_distr::Distribution;
+use std::time::Duration;
+
+// Discretised normal distribution over whole steps. Unlike rounding samples
+// from a continuous distribution, the probability of each step is computed
+// exactly, so steps the same distance above and below the mean are equally
+// likely.
+#[derive(Clone, Debug)]
+pub struct Step {
+  values: Vec<i64>,
+  index: WeightedIndex<f64>,
+}
+
+impl Step {
+  // If zero isn't allowed, its probability is shared among the other steps in
+  // proportion to their own.
+  pub fn new(mean: i64, std_dev: f64, allow_zero: bool) -> Self {
+    Self::weighted(mean, std_dev, allow_zero, |_| 1.0)
+  }
+  // Like new, with each step's probability also multiplied by its weight.
+  // Panics if a weight is negative or not finite, or if every step allowed
+  // has a weight of zero.
+  pub fn weighted<F>(mean: i64, std_dev: f64, allow_zero: bool, weight: F) -> Self
+  where
+    F: Fn(i64) -> f64,
+  {
+    assert!(std_dev > 0.0, "standard deviation must be positive");
+    let span = ((std_dev * 4.0).ceil() as i64).max(1);
+    let values: Vec<i64> = (mean - span..=mean + span)
+      .filter(|&x| allow_zero || x != 0)
+      .collect();
+    let index = WeightedIndex::new(values.iter().map(|&x| {
+      let w = weight(x);
+      assert!(
+        w.is_finite() && w >= 0.0,
+        "step weights must be finite and non-negative"
+      );
+      let z = (x - mean) as f64 / std_dev;
+      (-z * z / 2.0).exp() * w
+    }))
+    .expect("some step must have a positive weight");
+    Self { values, index }
+  }
+}
+
+impl Distribution<i64> for Step {
+  fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> i64 {
+    self.values[self.index.sample(rng)]
+  }
+}
+
+#[test]
+fn test_step_balance() {
+  let count = |mean, allow_zero| {
+    let distr = Step::new(mean, 3.5, allow_zero);
+    let mut counts = [0i64; 3];
+    for x in distr.sample_iter(Seed::new("balance").rng()).take(100_000) {
+      counts[(x.signum() + 1) as usize] += 1;
+    }
+    counts
+  };
+  let [down, zero, up] = count(0, false);
+  assert_eq!(zero, 0);
+  assert!((down - up).abs() < 1_000, "{} down, {} up", down, up);
+  let [down, zero, up] = count(0, true);
+  assert!(zero > 0);
+  assert!((down - up).abs() < 1_000, "{} down, {} up", down, up);
+  let [below, _, above] = count(-2, false);
+  let [below_mirrored, _, above_mirrored] = count(2, false);
+  assert!((below - above_mirrored).abs() < 1_000);
+  assert!((above - below_mirrored).abs() < 1_000);
+  assert!(below > above);
+}
+
+#[test]
+#[should_panic(expected = "some step must have a positive weight")]
+fn test_step_zero_weights() {
+  Step::weighted(0, 1.0, true, |_| 0.0);
+}
+
+#[test]
+#[should_panic(expected = "step weights must be finite and non-negative")]
+fn test_step_negative_weight() {
+  Step::weighted(0, 1.0, true, |x| x as f64);
+}
+
+// Deals values from a shuffled deck, reshuffling once every value has been
+// dealt. Each value appears once per round, and a reshuffle never deals the
+// value that ended the previous round first, so no value is dealt twice in a
+// row unless the deck holds only one.
+#[derive(Debug)]
+pub struct Deck<T> {
+  cards: Vec<T>,
+  order: Vec<usize>,
+  position: usize,
+  round: u64,
+  seed: Seed,
+}
+
+impl<T: Clone> Deck<T> {
+  pub fn new(cards: Vec<T>, seed: Seed) -> Self {
+    assert!(!cards.is_empty(), "deck must hold at least one card");
+    Self {
+      order: (0..cards.len()).collect(),
+      position: cards.len(),
+      round: 0,
+      cards,
+      seed,
+    }
+  }
+  fn shuffle(&mut self) {
+    let last = self.order.last().copied();
+    self.order.shuffle(&mut self.seed.fork(self.round).rng());
+    if self.round > 0 && self.order.len() > 1 && self.order.first().copied() == last {
+      let end = self.order.len() - 1;
+      self.order.swap(0, end);
+    }
+    self.round += 1;
+    self.position = 0;
+  }
+}
+
+impl<T: Clone> Iterator for Deck<T> {
+  type Item = T;
+  fn next(&mut self) -> Option<T> {
+    if self.position == self.order.len() {
+      self.shuffle();
+    }
+    let card = self.cards[self.order[self.position]].clone();
+    self.position += 1;
+    Some(card)
+  }
+}
+
+#[test]
+fn test_deck() {
+  let deal = |seed| {
+    Deck::new(vec![1, 2, 3, 4], Seed::new(seed))
+      .take(400)
+      .collect::<Vec<_>>()
+  };
+  let dealt = deal("test");
+  assert_eq!(dealt, deal("test"));
+  assert_ne!(dealt, deal("other"));
+  for round in dealt.chunks(4) {
+    let mut round = round.to_vec();
+    round.sort_unstable();
+    assert_eq!(round, vec![1, 2, 3, 4]);
+  }
+  assert!(dealt.windows(2).all(|pair| pair[0] != pair[1]));
+  assert_eq!(
+    Deck::new(vec!['a'], Seed::new(0))
+      .take(3)
+      .collect::<String>(),
+    "aaa"
+  );
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scatter {
+  // Quasi-random times from a randomly offset golden-ratio sequence, which
+  // cover the window evenly without looking regular.
+  LowDiscrepancy,
+  // Uniformly random times, but with at least the given gap between any two.
+  MinSpacing(Duration),
+}
+
+// Scatters a number of events over a window, yielding their indices in time
+// order.
+pub fn scatter<'a>(
+  count: usize,
+  window: Duration,
+  method: Scatter,
+  seed: Seed,
+) -> Stream<'a, usize> {
+  let mut rng = seed.rng();
+  let mut times: Vec<Duration> = match method {
+    Scatter::LowDiscrepancy => {
+      let golden = (5f64.sqrt() - 1.0) / 2.0;
+      let offset: f64 = rng.gen();
+      (0..count)
+        .map(|i| window.mul_f64((offset + golden * i as f64).fract()))
+        .collect()
+    }
+    Scatter::MinSpacing(gap) => {
+      // Place the events in the window shrunk by the total of the gaps, then
+      // push each one later by the gaps before it.
+      let gaps = gap * count.saturating_sub(1) as u32;
+      assert!(gaps <= window, "window too short for the minimum spacing");
+      let slack = window - gaps;
+      let mut offsets: Vec<f64> = (0..count).map(|_| rng.gen()).collect();
+      offsets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+      offsets
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| slack.mul_f64(x) + gap * i as u32)
+        .collect()
+    }
+  };
+  times.sort();
+  Stream::from_timestamps(times.into_iter().enumerate().map(|(i, time)| (time, i)))
+}
+
+#[test]
+fn test_scatter() {
+  let window = Duration::from_secs(10);
+  let times = |method, seed| {
+    let mut now = Duration::from_secs(0);
+    scatter(20, window, method, Seed::new(seed))
+      .into_iter()
+      .map(|(d, _)| {
+        now += d;
+        now
+      })
+      .collect::<Vec<_>>()
+  };
+  let even = times(Scatter::LowDiscrepancy, 0);
+  assert_eq!(even.len(), 20);
+  assert!(even.iter().all(|&t| t < window));
+  // The golden-ratio sequence never leaves a gap wider than a few times the
+  // average spacing.
+  assert!(even.windows(2).all(|w| w[1] - w[0] < window / 8));
+  assert_eq!(even, times(Scatter::LowDiscrepancy, 0));
+
+  let gap = Duration::from_millis(400);
+  for seed in 0..10 {
+    let spaced = times(Scatter::MinSpacing(gap), seed);
+    assert_eq!(spaced.len(), 20);
+    assert!(spaced.windows(2).all(|w| w[1] - w[0] >= gap));
+    assert!(*spaced.last().unwrap() <= window);
+  }
+}
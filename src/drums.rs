@@ -0,0 +1,436 @@
+use crate::arrange::Arrangement;
+use crate::event::NoteEvent;
+use crate::generator::{GenCtx, Generator};
+use crate::midi::gm::drums;
+use crate::pattern::Pattern;
+use crate::seed::Seed;
+use crate::stream::Stream;
+use crate::theory::Note;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::time::Duration;
+
+// A one bar drum fill, as hits on a grid of sixteen steps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fill {
+  pub name: &'static str,
+  // How big a change the fill suits, from 0 to 1.
+  pub intensity: f64,
+  pub hits: &'static [(u32, u8)],
+}
+
+pub const FILLS: [Fill; 5] = [
+  Fill {
+    name: "pickup",
+    intensity: 0.1,
+    hits: &[(14, drums::ACOUSTIC_SNARE), (15, drums::ACOUSTIC_SNARE)],
+  },
+  Fill {
+    name: "backbeat push",
+    intensity: 0.3,
+    hits: &[
+      (0, drums::BASS_DRUM_1),
+      (4, drums::ACOUSTIC_SNARE),
+      (8, drums::BASS_DRUM_1),
+      (12, drums::ACOUSTIC_SNARE),
+      (14, drums::ACOUSTIC_SNARE),
+      (15, drums::ACOUSTIC_SNARE),
+    ],
+  },
+  Fill {
+    name: "snare roll",
+    intensity: 0.5,
+    hits: &[
+      (0, drums::BASS_DRUM_1),
+      (4, drums::ACOUSTIC_SNARE),
+      (8, drums::BASS_DRUM_1),
+      (12, drums::ACOUSTIC_SNARE),
+      (13, drums::ACOUSTIC_SNARE),
+      (14, drums::ACOUSTIC_SNARE),
+      (15, drums::ACOUSTIC_SNARE),
+    ],
+  },
+  Fill {
+    name: "tom run",
+    intensity: 0.7,
+    hits: &[
+      (0, drums::BASS_DRUM_1),
+      (4, drums::ACOUSTIC_SNARE),
+      (8, drums::HIGH_TOM),
+      (9, drums::HIGH_TOM),
+      (10, drums::HI_MID_TOM),
+      (11, drums::HI_MID_TOM),
+      (12, drums::LOW_TOM),
+      (13, drums::LOW_TOM),
+      (14, drums::HIGH_FLOOR_TOM),
+      (15, drums::HIGH_FLOOR_TOM),
+    ],
+  },
+  Fill {
+    name: "full kit",
+    intensity: 1.0,
+    hits: &[
+      (0, drums::BASS_DRUM_1),
+      (2, drums::ACOUSTIC_SNARE),
+      (4, drums::ACOUSTIC_SNARE),
+      (6, drums::ACOUSTIC_SNARE),
+      (8, drums::HIGH_TOM),
+      (9, drums::HIGH_TOM),
+      (10, drums::HI_MID_TOM),
+      (11, drums::LOW_TOM),
+      (12, drums::ACOUSTIC_SNARE),
+      (12, drums::BASS_DRUM_1),
+      (13, drums::ACOUSTIC_SNARE),
+      (14, drums::HIGH_FLOOR_TOM),
+      (14, drums::BASS_DRUM_1),
+      (15, drums::LOW_FLOOR_TOM),
+      (15, drums::BASS_DRUM_1),
+    ],
+  },
+];
+
+// Chooses at random among the fills closest in intensity to the change.
+pub fn choose_fill(change: f64, seed: Seed) -> Fill {
+  let distance = |fill: &Fill| (fill.intensity - change).abs();
+  let nearest = FILLS.iter().map(distance).fold(f64::INFINITY, f64::min);
+  let candidates: Vec<_> = FILLS
+    .iter()
+    .filter(|fill| distance(fill) <= nearest + 0.15)
+    .collect();
+  **candidates.choose(&mut seed.rng()).unwrap()
+}
+
+// Plays the groove throughout the arrangement, except in the last bar before
+// each section boundary, where it is replaced by a fill suited to the size of
+// the change. Fills are louder for bigger changes, and a big change is marked
+// with a crash on the first beat of the new section.
+pub fn with_fills<'a>(
+  groove: &Pattern<NoteEvent>,
+  arrangement: &Arrangement,
+  seed: Seed,
+) -> Stream<'a, NoteEvent> {
+  let bar = arrangement.bar_length();
+  let step = bar / 16;
+  let boundaries = arrangement.boundaries();
+  let length: Duration = arrangement.sections.iter().map(|s| s.length).sum();
+  let mut fills = Vec::new();
+  for (i, &(boundary, change)) in boundaries.iter().enumerate() {
+    let Some(start) = boundary.checked_sub(bar) else {
+      continue;
+    };
+    let fill = choose_fill(change, seed.fork(i));
+    let velocity = (60.0 + 60.0 * change).round() as u8;
+    for &(n, drum) in fill.hits {
+      let event = NoteEvent::new(Note::from_midi(drum), velocity, step);
+      fills.push((start + step * n, event));
+    }
+    if change >= 0.5 {
+      let crash = NoteEvent::new(Note::from_midi(drums::CRASH_CYMBAL_1), velocity, bar);
+      fills.push((boundary, crash));
+    }
+  }
+  fills.sort_by_key(|&(time, _)| time);
+  let in_fill = move |time: Duration| {
+    boundaries
+      .iter()
+      .any(|&(boundary, _)| time < boundary && time + bar >= boundary)
+  };
+  let groove = Stream::from_timestamps(
+    groove
+      .looped()
+      .take(length)
+      .timestamps()
+      .filter(move |&(time, _)| !in_fill(time) && time < length),
+  );
+  Stream::from_timestamps(fills).merge(groove)
+}
+
+#[test]
+fn test_with_fills() {
+  use crate::arrange::{Density, Register, Section};
+  use crate::melody::Params;
+  let section = |density, voices| Section {
+    length: Duration::from_secs(4),
+    density,
+    register: Register::Middle,
+    voices,
+  };
+  let arrangement = Arrangement {
+    params: Params::new(Duration::from_millis(125)),
+    bar: 8,
+    sections: vec![
+      section(Density::Sparse, 1),
+      section(Density::Moderate, 1),
+      section(Density::Dense, 4),
+    ],
+  };
+  let bar = arrangement.bar_length();
+  let hat = NoteEvent::new(Note::from_midi(drums::CLOSED_HI_HAT), 80, bar / 8);
+  let groove = Pattern::new(
+    vec![
+      (Duration::from_secs(0), hat.clone()),
+      (bar / 4, hat.clone()),
+      (bar / 4, hat.clone()),
+      (bar / 4, hat),
+    ],
+    bar,
+  );
+  let events = with_fills(&groove, &arrangement, Seed::new("test"))
+    .timestamps()
+    .collect::<Vec<_>>();
+  let is_hat = |event: &NoteEvent| event.note == Note::from_midi(drums::CLOSED_HI_HAT);
+  let in_bar = |n: u32| {
+    events
+      .iter()
+      .filter(move |(time, _)| *time >= bar * n && *time < bar * (n + 1))
+      .map(|(_, event)| event)
+      .collect::<Vec<_>>()
+  };
+  let crash = Note::from_midi(drums::CRASH_CYMBAL_1);
+  // Sections of four bars, with fills in bars 3 and 7.
+  for n in [0, 1, 2, 4, 5, 6, 8, 9, 10, 11] {
+    let groove = in_bar(n)
+      .into_iter()
+      .filter(|event| event.note != crash)
+      .collect::<Vec<_>>();
+    assert_eq!(groove.len(), 4, "bar {}", n);
+    assert!(groove.iter().all(|event| is_hat(event)), "bar {}", n);
+  }
+  let small = in_bar(3);
+  let big = in_bar(7);
+  assert!(small.iter().all(|event| !is_hat(event)));
+  assert!(big.iter().all(|event| !is_hat(event)));
+  assert!(big.len() > small.len());
+  assert!(big[0].velocity > small[0].velocity);
+  assert!(in_bar(8).iter().any(|event| event.note == crash));
+  assert!(!in_bar(4).iter().any(|event| event.note == crash));
+  assert_eq!(in_bar(12).len(), 0);
+}
+
+// How bass onsets near a kick drum hit are moved.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Interlock {
+  // Onto the kick, so the two reinforce each other.
+  Snap,
+  // To just after the kick, leaving it room.
+  Avoid,
+}
+
+// Moves each bass note lying within the tolerance of a kick hit, keeping its
+// duration. Events never move before an earlier one, and notes clear of the
+// kick are left where they are.
+pub fn interlock<'a>(
+  bass: Stream<'a, NoteEvent>,
+  kick: Stream<'a, NoteEvent>,
+  mode: Interlock,
+  tolerance: Duration,
+) -> Stream<'a, NoteEvent> {
+  let mut kicks = kick.timestamps().map(|(time, _)| time).peekable();
+  let mut before: Option<Duration> = None;
+  let mut last = Duration::from_secs(0);
+  Stream::from_timestamps(bass.timestamps().map(move |(time, event)| {
+    while let Some(kick) = kicks.next_if(|&kick| kick <= time) {
+      before = Some(kick);
+    }
+    let near = |kick: &Duration| time.max(*kick) - time.min(*kick) <= tolerance;
+    let before = before.filter(near);
+    let after = kicks.peek().copied().filter(near);
+    let nearest = match (before, after) {
+      (Some(before), Some(after)) if after - time < time - before => Some(after),
+      (Some(before), _) => Some(before),
+      (None, after) => after,
+    };
+    let moved = match (nearest, mode) {
+      (Some(kick), Interlock::Snap) => kick,
+      (Some(kick), Interlock::Avoid) => kick + tolerance,
+      (None, _) => time,
+    };
+    last = last.max(moved);
+    (last, event)
+  }))
+}
+
+#[test]
+fn test_interlock() {
+  let ms = Duration::from_millis;
+  let note = |midi| NoteEvent::new(Note::from_midi(midi), 100, ms(100));
+  let bass = || {
+    Stream::from_timestamps(vec![
+      (ms(50), note(36)),
+      (ms(480), note(38)),
+      (ms(750), note(40)),
+      (ms(960), note(41)),
+    ])
+  };
+  let kick = || {
+    Stream::from_timestamps(vec![
+      (ms(0), note(drums::BASS_DRUM_1)),
+      (ms(500), note(drums::BASS_DRUM_1)),
+      (ms(1000), note(drums::BASS_DRUM_1)),
+    ])
+  };
+  let times = |mode| {
+    interlock(bass(), kick(), mode, ms(60))
+      .timestamps()
+      .map(|(time, _)| time.as_millis())
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(times(Interlock::Snap), vec![0, 500, 750, 1000]);
+  assert_eq!(times(Interlock::Avoid), vec![60, 560, 750, 1060]);
+}
+
+// Shape of the hi-hat's dynamics over each bar.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Contour {
+  Flat,
+  Crescendo,
+  Decrescendo,
+  Arch,
+}
+
+impl Contour {
+  // Velocity scale at a position from 0 (start of the bar) to 1 (end).
+  fn scale(self, position: f64) -> f64 {
+    let depth = 0.3;
+    match self {
+      Self::Flat => 1.0,
+      Self::Crescendo => 1.0 - depth * (1.0 - position),
+      Self::Decrescendo => 1.0 - depth * position,
+      Self::Arch => 1.0 - depth * (2.0 * position - 1.0).abs(),
+    }
+  }
+}
+
+// A hi-hat played hand to hand on every step, with accents, a weaker off
+// hand and occasional open hats. An open hat rings until the next stroke,
+// which closes it, so its note off always lands on the following closed hat.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HiHat {
+  pub steps_per_beat: u32,
+  pub beats_per_bar: u32,
+  // Velocity scale of each step, repeating.
+  pub accents: Vec<f64>,
+  // Velocity scale of strokes by the off hand, which plays every other step.
+  pub weak_hand: f64,
+  pub contour: Contour,
+  // Largest random change in velocity, as a proportion.
+  pub variation: f64,
+  // Chance of an off hand stroke opening the hat.
+  pub open: f64,
+}
+
+impl Default for HiHat {
+  fn default() -> Self {
+    Self {
+      steps_per_beat: 4,
+      beats_per_bar: 4,
+      accents: vec![1.0, 0.6, 0.8, 0.6],
+      weak_hand: 0.9,
+      contour: Contour::Flat,
+      variation: 0.08,
+      open: 0.1,
+    }
+  }
+}
+
+impl Generator for HiHat {
+  fn generate<'a>(&'a self, ctx: &'a GenCtx, seed: Seed) -> Stream<'a, NoteEvent> {
+    assert!(!self.accents.is_empty(), "hi-hat needs at least one accent");
+    let step = ctx.beat / self.steps_per_beat;
+    let bar = (self.steps_per_beat * self.beats_per_bar) as u64;
+    let mut was_open = false;
+    Stream::from_iter((0u64..).map(move |i| {
+      let mut rng = seed.fork(i).rng();
+      let off_hand = i % 2 == 1;
+      let open = off_hand && !was_open && rng.gen_bool(self.open);
+      was_open = open;
+      let mut scale = self.accents[i as usize % self.accents.len()]
+        * self.contour.scale((i % bar) as f64 / bar as f64);
+      if off_hand {
+        scale *= self.weak_hand;
+      }
+      if self.variation > 0.0 {
+        scale *= 1.0 + rng.gen_range(-self.variation..self.variation);
+      }
+      let velocity = (ctx.velocity as f64 * scale).round().clamp(1.0, 127.0) as u8;
+      let drum = if open {
+        drums::OPEN_HI_HAT
+      } else {
+        drums::CLOSED_HI_HAT
+      };
+      let delay = if i == 0 { Duration::from_secs(0) } else { step };
+      (delay, NoteEvent::new(Note::from_midi(drum), velocity, step))
+    }))
+  }
+}
+
+#[test]
+fn test_hi_hat() {
+  use crate::event::lower;
+  use crate::midi::{Channel, Message};
+  use crate::theory::{Key, PitchClass};
+  let ctx = GenCtx {
+    velocity: 100,
+    ..GenCtx::new(
+      Key::major(Note::new(PitchClass::C, 4)),
+      Duration::from_millis(400),
+    )
+  };
+  let plain = HiHat {
+    accents: vec![1.0, 0.5],
+    weak_hand: 1.0,
+    variation: 0.0,
+    open: 0.0,
+    ..HiHat::default()
+  };
+  let velocities: Vec<_> = plain
+    .generate(&ctx, Seed::new("test"))
+    .take_n(4)
+    .into_iter()
+    .map(|(_, event)| event.velocity)
+    .collect();
+  assert_eq!(velocities, vec![100, 50, 100, 50]);
+
+  let falling = HiHat {
+    contour: Contour::Decrescendo,
+    ..plain.clone()
+  };
+  let velocities: Vec<_> = falling
+    .generate(&ctx, Seed::new("test"))
+    .take_n(17)
+    .into_iter()
+    .map(|(_, event)| event.velocity)
+    .collect();
+  assert!(velocities[14] < velocities[0]);
+  assert_eq!(velocities[16], velocities[0]);
+
+  let open = HiHat {
+    open: 0.5,
+    ..HiHat::default()
+  };
+  let events = open
+    .generate(&ctx, Seed::new("test"))
+    .take_n(64)
+    .collect_timed();
+  let is_open = |event: &NoteEvent| event.note == Note::from_midi(drums::OPEN_HI_HAT);
+  assert!(events.iter().any(|(_, event)| is_open(event)));
+  for (i, pair) in events.windows(2).enumerate() {
+    assert_eq!(pair[1].0, Duration::from_millis(100));
+    assert!(!(is_open(&pair[0].1) && is_open(&pair[1].1)));
+    if is_open(&pair[1].1) {
+      assert_eq!(i % 2, 0);
+    }
+  }
+  // Each open hat is released as the next closed hat starts. Open hats only
+  // fall on odd steps, so the stream ends on a closed one.
+  let messages = lower(Channel::Ch10, Stream::from_iter(events).take_n(63))
+    .timestamps()
+    .collect::<Vec<_>>();
+  for (time, message) in &messages {
+    if let Message::NoteOff(_, drums::OPEN_HI_HAT, _) = message {
+      assert!(messages
+        .iter()
+        .any(|(t, m)| t == time && matches!(m, Message::NoteOn(_, drums::CLOSED_HI_HAT, _))));
+    }
+  }
+}
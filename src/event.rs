@@ -0,0 +1,622 @@
+use crate::midi::{Channel, Message};
+use crate::seed::Seed;
+use crate::stream::Stream;
+use crate::theory::Note;
+use crate::var::Var;
+use itertools::Itertools;
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Articulation {
+  Staccato,
+  Accent,
+  Tenuto,
+  Marcato,
+}
+
+impl Articulation {
+  // Fraction of the written duration for which the note sounds.
+  pub fn gate(self) -> f64 {
+    match self {
+      Self::Staccato => 0.5,
+      Self::Accent => 0.9,
+      Self::Tenuto => 1.0,
+      Self::Marcato => 0.75,
+    }
+  }
+  pub fn velocity(self, velocity: u8) -> u8 {
+    let scale = match self {
+      Self::Staccato | Self::Tenuto => 1.0,
+      Self::Accent => 1.25,
+      Self::Marcato => 1.4,
+    };
+    (velocity as f64 * scale).round().min(127.0) as u8
+  }
+  // Articulation mark as written after a note in Lilypond.
+  pub fn lilypond(self) -> &'static str {
+    match self {
+      Self::Staccato => "-.",
+      Self::Accent => "->",
+      Self::Tenuto => "--",
+      Self::Marcato => "-^",
+    }
+  }
+  // Element name inside MusicXML's <articulations>.
+  pub fn musicxml(self) -> &'static str {
+    match self {
+      Self::Staccato => "staccato",
+      Self::Accent => "accent",
+      Self::Tenuto => "tenuto",
+      Self::Marcato => "strong-accent",
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteEvent {
+  pub note: Note,
+  pub velocity: u8,
+  // Written duration; how long the note actually sounds depends on its
+  // articulations.
+  pub duration: Duration,
+  pub articulations: Vec<Articulation>,
+}
+
+impl NoteEvent {
+  pub fn new(note: Note, velocity: u8, duration: Duration) -> Self {
+    Self {
+      note,
+      velocity,
+      duration,
+      articulations: Vec::new(),
+    }
+  }
+  pub fn articulated(mut self, articulation: Articulation) -> Self {
+    self.articulations.push(articulation);
+    self
+  }
+  pub fn gate(&self) -> f64 {
+    self
+      .articulations
+      .iter()
+      .map(|a| a.gate())
+      .fold(1.0, f64::min)
+  }
+  pub fn sounding_duration(&self) -> Duration {
+    self.duration.mul_f64(self.gate())
+  }
+  pub fn sounding_velocity(&self) -> u8 {
+    self
+      .articulations
+      .iter()
+      .fold(self.velocity, |velocity, a| a.velocity(velocity))
+  }
+}
+
+// A step in a monophonic line: a note, or a rest of the given length. In a
+// stream of elements each one starts where the last one ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Element {
+  Note(NoteEvent),
+  Rest(Duration),
+}
+
+impl Element {
+  pub fn duration(&self) -> Duration {
+    match self {
+      Self::Note(event) => event.duration,
+      Self::Rest(duration) => *duration,
+    }
+  }
+}
+
+// A line given as a pitch that changes over time, held from one change to the
+// next and silent while None, as notes and rests of their actual lengths. The
+// final value has no end, so it is left out.
+pub fn elements<'a>(pitch: Var<'a, Option<Note>>, velocity: u8) -> Stream<'a, Element> {
+  Stream::from_iter(pitch.updates().into_iter().tuple_windows().map(
+    move |((delay, note), (duration, _))| {
+      let element = match note {
+        Some(note) => Element::Note(NoteEvent::new(note, velocity, duration)),
+        None => Element::Rest(duration),
+      };
+      (delay, element)
+    },
+  ))
+}
+
+// The notes of a line, with the rests becoming gaps between them.
+pub fn notes<'a>(elements: Stream<'a, Element>) -> Stream<'a, NoteEvent> {
+  elements.filter_map(|element| match element {
+    Element::Note(event) => Some(event),
+    Element::Rest(_) => None,
+  })
+}
+
+// The inverse of elements: the pitch sounding at each moment. A note is
+// released early if the next element starts before it ends.
+pub fn pitch<'a>(elements: Stream<'a, Element>) -> Var<'a, Option<Note>> {
+  let mut source = elements.timestamps().peekable();
+  let changes = std::iter::from_fn(move || {
+    let (time, element) = source.next()?;
+    let next = source.peek().map(|(next, _)| *next);
+    Some(match element {
+      Element::Note(event) => {
+        let end = time + event.duration;
+        let mut changes = vec![(time, Some(event.note))];
+        if next.is_none_or(|next| end < next) {
+          changes.push((end, None));
+        }
+        changes
+      }
+      Element::Rest(_) => vec![(time, None)],
+    })
+  });
+  let mut changes = Stream::from_timestamps(changes.flatten());
+  match changes.next() {
+    Some((delay, note)) if delay.is_zero() => Var::from_updates(note, changes),
+    Some(first) => Var::from_updates(None, Stream::from_iter(vec![first]).chain(changes)),
+    None => Var::constant(None),
+  }
+}
+
+// Adapts notes to a dynamic level on the MIDI velocity scale, such as a
+// player's average velocity. Velocities are scaled by the ratio of the level
+// to the reference level, and below the reference notes are also dropped at
+// random in the same proportion, so quieter playing is met with a sparser
+// part as well as a softer one.
+pub fn follow_dynamics<'a>(
+  events: Stream<'a, NoteEvent>,
+  level: Var<'a, f64>,
+  reference: f64,
+  seed: Seed,
+) -> Stream<'a, NoteEvent> {
+  enum Follow {
+    Level(f64),
+    Note(NoteEvent),
+    End,
+  }
+  let mut ratio = 1.0;
+  let mut index = 0u64;
+  level
+    .updates()
+    .map(Follow::Level)
+    .merge(
+      events
+        .map(Follow::Note)
+        .chain(Stream::immediate(Follow::End)),
+    )
+    // Ends with the notes, however long the level goes on.
+    .take_while(|event| !matches!(event, Follow::End))
+    .filter_map(move |event| match event {
+      Follow::End => None,
+      Follow::Level(level) => {
+        ratio = (level / reference).max(0.0);
+        None
+      }
+      Follow::Note(mut event) => {
+        index += 1;
+        if !seed.fork(index).rng().gen_bool(ratio.min(1.0)) {
+          return None;
+        }
+        event.velocity = (event.velocity as f64 * ratio).round().clamp(1.0, 127.0) as u8;
+        Some(event)
+      }
+    })
+}
+
+// Scale applied to a ducked level, `elapsed` after the last trigger: down by
+// `depth` at the trigger and recovering linearly over `release`.
+fn duck_scale(depth: f64, release: Duration, elapsed: Duration) -> f64 {
+  if elapsed >= release {
+    return 1.0;
+  }
+  1.0 - depth * (1.0 - elapsed.as_secs_f64() / release.as_secs_f64())
+}
+
+// Emulates sidechain compression in MIDI dynamics: notes starting soon after
+// a trigger, such as a kick or an accent on another track, are played more
+// quietly. A note on a trigger is reduced by the full depth (0 to 1).
+pub fn duck<'a>(
+  events: Stream<'a, NoteEvent>,
+  triggers: Stream<'a, NoteEvent>,
+  depth: f64,
+  release: Duration,
+) -> Stream<'a, NoteEvent> {
+  enum Duck {
+    Trigger,
+    Note(NoteEvent),
+  }
+  let mut since_trigger = None;
+  let events = triggers
+    .map(|_| Duck::Trigger)
+    .merge(events.map(Duck::Note))
+    .timestamps()
+    .filter_map(move |(time, event)| match event {
+      Duck::Trigger => {
+        since_trigger = Some(time);
+        None
+      }
+      Duck::Note(mut event) => {
+        if let Some(trigger) = since_trigger {
+          let scale = duck_scale(depth, release, time - trigger);
+          event.velocity = (event.velocity as f64 * scale).round().clamp(1.0, 127.0) as u8;
+        }
+        Some((time, event))
+      }
+    });
+  Stream::from_timestamps(events)
+}
+
+// The same ducking as expression (CC11) changes on the given channel, for
+// sustained notes that have already started when a trigger arrives. Each dip
+// recovers in `steps` stages, cut short by the next trigger.
+pub fn duck_expression<'a>(
+  channel: Channel,
+  triggers: Stream<'a, NoteEvent>,
+  depth: f64,
+  release: Duration,
+  steps: u32,
+) -> Stream<'a, Message> {
+  assert!(steps > 0, "ducking needs at least one step");
+  let mut source = triggers.timestamps().peekable();
+  let changes = std::iter::from_fn(move || {
+    let (time, _) = source.next()?;
+    let next = source.peek().map(|(next, _)| *next);
+    let ramp = (0..=steps)
+      .map(move |step| release * step / steps)
+      .filter(move |&elapsed| next.is_none_or(|next| time + elapsed < next))
+      .map(move |elapsed| {
+        let value = (127.0 * duck_scale(depth, release, elapsed)).round() as u8;
+        (time + elapsed, Message::ControlChange(channel, 11, value))
+      });
+    Some(ramp)
+  });
+  Stream::from_timestamps(changes.flatten())
+}
+
+// Lowers note events (whose delays are onset-to-onset) to NoteOn/NoteOff
+// messages on the given channel. A NoteOff falling at the same instant as a
+// NoteOn is sent first.
+pub fn lower<'a>(channel: Channel, events: Stream<'a, NoteEvent>) -> Stream<'a, Message> {
+  let mut source = events.into_iter();
+  let onset = source.next();
+  Stream::from_iter(Lower {
+    channel,
+    source,
+    onset,
+    now: Duration::from_secs(0),
+    releases: BinaryHeap::new(),
+  })
+}
+
+struct Lower<'a> {
+  channel: Channel,
+  source: Box<dyn Iterator<Item = (Duration, NoteEvent)> + 'a>,
+  // Next event, with its delay measured from `now`.
+  onset: Option<(Duration, NoteEvent)>,
+  now: Duration,
+  releases: BinaryHeap<Reverse<(Duration, u8)>>,
+}
+
+impl<'a> Iterator for Lower<'a> {
+  type Item = (Duration, Message);
+  fn next(&mut self) -> Option<(Duration, Message)> {
+    const RELEASE_VELOCITY: u8 = 0x40;
+    let onset_time = self.onset.as_ref().map(|(d, _)| self.now + *d);
+    let release = self.releases.peek().map(|&Reverse(release)| release);
+    match (release, onset_time) {
+      (Some((time, note)), _) if onset_time.is_none_or(|t| time <= t) => {
+        self.releases.pop();
+        let delay = time - self.now;
+        self.now = time;
+        if let Some((d, _)) = self.onset.as_mut() {
+          *d -= delay;
+        }
+        Some((
+          delay,
+          Message::NoteOff(self.channel, note, RELEASE_VELOCITY),
+        ))
+      }
+      (_, Some(time)) => {
+        let (delay, event) = self.onset.take().unwrap();
+        self.now = time;
+        self.onset = self.source.next();
+        let note = event.note.midi();
+        self
+          .releases
+          .push(Reverse((time + event.sounding_duration(), note)));
+        Some((
+          delay,
+          Message::NoteOn(self.channel, note, event.sounding_velocity()),
+        ))
+      }
+      _ => None,
+    }
+  }
+}
+
+// Caps the number of notes sounding at once on each channel by releasing the
+// oldest sounding note when another would exceed the limit, in which case the
+// stolen note's own NoteOff is dropped unless the note is played again first.
+// A NoteOn with zero velocity counts as a NoteOff. Stolen notes are logged at
+// debug level.
+pub fn limit_polyphony<'a>(max_notes: usize, messages: Stream<'a, Message>) -> Stream<'a, Message> {
+  assert!(
+    max_notes > 0,
+    "polyphony limit must allow at least one note"
+  );
+  Stream::from_iter(LimitPolyphony {
+    source: messages.into_iter(),
+    max_notes,
+    now: Duration::from_secs(0),
+    pending: Duration::from_secs(0),
+    sounding: Vec::new(),
+    stolen: Vec::new(),
+    queued: None,
+  })
+}
+
+struct LimitPolyphony<'a> {
+  source: Box<dyn Iterator<Item = (Duration, Message)> + 'a>,
+  max_notes: usize,
+  now: Duration,
+  // Delay carried over from dropped NoteOffs.
+  pending: Duration,
+  // Oldest first.
+  sounding: Vec<(Channel, u8)>,
+  stolen: Vec<(Channel, u8)>,
+  // NoteOn to send immediately after the release of a stolen note.
+  queued: Option<Message>,
+}
+
+impl<'a> Iterator for LimitPolyphony<'a> {
+  type Item = (Duration, Message);
+  fn next(&mut self) -> Option<(Duration, Message)> {
+    if let Some(message) = self.queued.take() {
+      return Some((Duration::from_secs(0), message));
+    }
+    loop {
+      let (delay, message) = self.source.next()?;
+      self.now += delay;
+      self.pending += delay;
+      match message {
+        Message::NoteOn(ch, note, 0) | Message::NoteOff(ch, note, _) => {
+          if let Some(i) = self.stolen.iter().position(|&n| n == (ch, note)) {
+            self.stolen.remove(i);
+            continue;
+          }
+          if let Some(i) = self.sounding.iter().position(|&n| n == (ch, note)) {
+            self.sounding.remove(i);
+          }
+        }
+        Message::NoteOn(ch, note, _) => {
+          // The next NoteOff now ends this note rather than the stolen one.
+          self.stolen.retain(|&n| n != (ch, note));
+          self.sounding.push((ch, note));
+          if self.sounding.iter().filter(|&&(c, _)| c == ch).count() > self.max_notes {
+            let oldest = self.sounding.iter().position(|&(c, _)| c == ch).unwrap();
+            let (_, stolen) = self.sounding.remove(oldest);
+            log::debug!(
+              "polyphony limit: stole note {} on {:?} at {}ms",
+              stolen,
+              ch,
+              self.now.as_millis()
+            );
+            self.stolen.push((ch, stolen));
+            self.queued = Some(message);
+            return Some((
+              std::mem::take(&mut self.pending),
+              Message::NoteOff(ch, stolen, 0x40),
+            ));
+          }
+        }
+        _ => {}
+      }
+      return Some((std::mem::take(&mut self.pending), message));
+    }
+  }
+}
+
+#[test]
+fn test_lower() {
+  use crate::theory::PitchClass::*;
+  let ms = Duration::from_millis;
+  let c4 = Note::new(C, 4);
+  let d4 = Note::new(D, 4);
+  let events = Stream::from_iter(vec![
+    (ms(0), NoteEvent::new(c4, 80, ms(100))),
+    (
+      ms(100),
+      NoteEvent::new(d4, 80, ms(100))
+        .articulated(Articulation::Staccato)
+        .articulated(Articulation::Accent),
+    ),
+    (ms(100), NoteEvent::new(c4, 80, ms(300))),
+    (ms(100), NoteEvent::new(d4, 80, ms(50))),
+  ]);
+  let ch = Channel::Ch1;
+  assert_eq!(
+    lower(ch, events).collect_timed(),
+    vec![
+      (ms(0), Message::NoteOn(ch, 60, 80)),
+      (ms(100), Message::NoteOff(ch, 60, 0x40)),
+      (ms(0), Message::NoteOn(ch, 62, 100)),
+      (ms(50), Message::NoteOff(ch, 62, 0x40)),
+      (ms(50), Message::NoteOn(ch, 60, 80)),
+      (ms(100), Message::NoteOn(ch, 62, 80)),
+      (ms(50), Message::NoteOff(ch, 62, 0x40)),
+      (ms(150), Message::NoteOff(ch, 60, 0x40)),
+    ]
+  );
+}
+
+#[test]
+fn test_limit_polyphony() {
+  let ms = Duration::from_millis;
+  let (ch1, ch2) = (Channel::Ch1, Channel::Ch2);
+  let messages = Stream::from_iter(vec![
+    (ms(0), Message::NoteOn(ch1, 60, 80)),
+    (ms(10), Message::NoteOn(ch2, 48, 80)),
+    (ms(10), Message::NoteOn(ch1, 64, 80)),
+    (ms(10), Message::NoteOn(ch1, 67, 80)),
+    (ms(10), Message::NoteOff(ch1, 60, 0x40)),
+    (ms(10), Message::NoteOff(ch1, 64, 0x40)),
+    (ms(10), Message::NoteOff(ch1, 67, 0x40)),
+    (ms(10), Message::NoteOff(ch2, 48, 0x40)),
+  ]);
+  assert_eq!(
+    limit_polyphony(2, messages).collect_timed(),
+    vec![
+      (ms(0), Message::NoteOn(ch1, 60, 80)),
+      (ms(10), Message::NoteOn(ch2, 48, 80)),
+      (ms(10), Message::NoteOn(ch1, 64, 80)),
+      (ms(10), Message::NoteOff(ch1, 60, 0x40)),
+      (ms(0), Message::NoteOn(ch1, 67, 80)),
+      (ms(20), Message::NoteOff(ch1, 64, 0x40)),
+      (ms(10), Message::NoteOff(ch1, 67, 0x40)),
+      (ms(10), Message::NoteOff(ch2, 48, 0x40)),
+    ]
+  );
+
+  let retriggered = Stream::from_iter(vec![
+    (ms(0), Message::NoteOn(ch1, 60, 80)),
+    (ms(10), Message::NoteOn(ch1, 64, 80)),
+    (ms(10), Message::NoteOn(ch1, 60, 80)),
+    (ms(10), Message::NoteOn(ch1, 60, 0)),
+    (ms(10), Message::NoteOff(ch1, 64, 0x40)),
+  ]);
+  assert_eq!(
+    limit_polyphony(1, retriggered).collect_timed(),
+    vec![
+      (ms(0), Message::NoteOn(ch1, 60, 80)),
+      (ms(10), Message::NoteOff(ch1, 60, 0x40)),
+      (ms(0), Message::NoteOn(ch1, 64, 80)),
+      (ms(10), Message::NoteOff(ch1, 64, 0x40)),
+      (ms(0), Message::NoteOn(ch1, 60, 80)),
+      (ms(10), Message::NoteOn(ch1, 60, 0)),
+    ]
+  );
+}
+
+#[test]
+fn test_follow_dynamics() {
+  use crate::theory::PitchClass::*;
+  let ms = Duration::from_millis;
+  let events =
+    || Stream::from_iter((0..1000).map(|_| (ms(10), NoteEvent::new(Note::new(C, 4), 80, ms(10)))));
+  let followed = |level: Var<'static, f64>| {
+    follow_dynamics(events(), level, 64.0, Seed::new("test"))
+      .into_iter()
+      .collect::<Vec<_>>()
+  };
+  let loud = followed(Var::constant(96.0));
+  assert_eq!(loud.len(), 1000);
+  assert!(loud.iter().all(|(_, e)| e.velocity == 120));
+  let quiet = followed(Var::constant(32.0));
+  assert!((400..600).contains(&quiet.len()));
+  assert!(quiet.iter().all(|(_, e)| e.velocity == 40));
+  let total: Duration = quiet.iter().map(|(d, _)| *d).sum();
+  assert!(total <= ms(10_000));
+  let changing = followed(Var::from_updates(
+    96.0,
+    Stream::from_iter(vec![(ms(5005), 32.0)]),
+  ));
+  assert!(changing[..500].iter().all(|(_, e)| e.velocity == 120));
+  assert!(changing[500..].iter().all(|(_, e)| e.velocity == 40));
+  let endless = followed(Var::from_fn(ms(100), |_| 96.0));
+  assert_eq!(endless.len(), 1000);
+}
+
+#[test]
+fn test_elements() {
+  use crate::theory::PitchClass::*;
+  let ms = Duration::from_millis;
+  let c4 = Note::new(C, 4);
+  let d4 = Note::new(D, 4);
+  let pitch_changes = vec![
+    (ms(0), Some(c4)),
+    (ms(100), None),
+    (ms(50), Some(d4)),
+    (ms(200), Some(c4)),
+    (ms(100), None),
+  ];
+  let line = || {
+    let mut changes = pitch_changes.clone().into_iter();
+    let (_, first) = changes.next().unwrap();
+    Var::from_updates(first, Stream::from_iter(changes))
+  };
+  let elements = elements(line(), 80).collect_timed();
+  assert_eq!(
+    elements,
+    vec![
+      (ms(0), Element::Note(NoteEvent::new(c4, 80, ms(100)))),
+      (ms(100), Element::Rest(ms(50))),
+      (ms(50), Element::Note(NoteEvent::new(d4, 80, ms(200)))),
+      (ms(200), Element::Note(NoteEvent::new(c4, 80, ms(100)))),
+    ]
+  );
+  assert_eq!(
+    notes(Stream::from_iter(elements.clone()))
+      .map(|event| event.note)
+      .collect_timed(),
+    vec![(ms(0), c4), (ms(150), d4), (ms(200), c4)]
+  );
+  assert_eq!(
+    pitch(Stream::from_iter(elements)).updates().collect_timed(),
+    pitch_changes
+  );
+
+  // Overlapping notes are cut short rather than reordered.
+  let overlapping = Stream::from_iter(vec![
+    (ms(50), Element::Note(NoteEvent::new(c4, 80, ms(300)))),
+    (ms(100), Element::Note(NoteEvent::new(d4, 80, ms(50)))),
+  ]);
+  assert_eq!(
+    pitch(overlapping).updates().collect_timed(),
+    vec![
+      (ms(0), None),
+      (ms(50), Some(c4)),
+      (ms(100), Some(d4)),
+      (ms(50), None)
+    ]
+  );
+}
+
+#[test]
+fn test_duck() {
+  use crate::theory::PitchClass::*;
+  let ms = Duration::from_millis;
+  let kick = || {
+    Stream::from_iter(vec![
+      (ms(0), NoteEvent::new(Note::new(C, 2), 100, ms(50))),
+      (ms(500), NoteEvent::new(Note::new(C, 2), 100, ms(50))),
+    ])
+  };
+  let pad = Stream::from_timestamps(
+    [0, 100, 300, 500, 550]
+      .iter()
+      .map(|&t| (ms(t), NoteEvent::new(Note::new(E, 4), 100, ms(50)))),
+  );
+  let velocities: Vec<_> = duck(pad, kick(), 0.5, ms(200))
+    .into_iter()
+    .map(|(_, event)| event.velocity)
+    .collect();
+  assert_eq!(velocities, vec![50, 75, 100, 50, 63]);
+
+  let ch = Channel::Ch2;
+  assert_eq!(
+    duck_expression(ch, kick(), 0.5, ms(200), 2).collect_timed(),
+    vec![
+      (ms(0), Message::ControlChange(ch, 11, 64)),
+      (ms(100), Message::ControlChange(ch, 11, 95)),
+      (ms(100), Message::ControlChange(ch, 11, 127)),
+      (ms(300), Message::ControlChange(ch, 11, 64)),
+      (ms(100), Message::ControlChange(ch, 11, 95)),
+      (ms(100), Message::ControlChange(ch, 11, 127)),
+    ]
+  );
+}
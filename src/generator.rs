@@ -0,0 +1,169 @@
+use crate::event::NoteEvent;
+use crate::melody::{self, Params, Phrasing};
+use crate::seed::Seed;
+use crate::stream::Stream;
+use crate::theory::Key;
+use itertools::Itertools;
+use std::time::Duration;
+
+// Settings shared by every generator in a piece.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenCtx {
+  pub key: Key,
+  pub beat: Duration,
+  pub velocity: u8,
+}
+
+impl GenCtx {
+  pub fn new(key: Key, beat: Duration) -> Self {
+    Self {
+      key,
+      beat,
+      velocity: 0x40,
+    }
+  }
+}
+
+// A source of notes, such as a melody or rhythm algorithm. The stream's
+// delays are onset-to-onset, as expected by event::lower.
+pub trait Generator {
+  fn generate<'a>(&'a self, ctx: &'a GenCtx, seed: Seed) -> Stream<'a, NoteEvent>;
+}
+
+// Generators looked up by name, so that configuration can refer to them
+// without knowing where they come from. Code outside avril adds its own with
+// register.
+pub struct Registry {
+  generators: Vec<(String, Box<dyn Generator>)>,
+}
+
+impl Registry {
+  pub fn new() -> Self {
+    Self {
+      generators: Vec::new(),
+    }
+  }
+  // A registry containing the generators avril ships with.
+  pub fn builtin() -> Self {
+    let mut registry = Self::new();
+    registry.register("phrases", Phrases::default());
+    registry.register("hi-hat", crate::drums::HiHat::default());
+    registry
+  }
+  pub fn register<G>(&mut self, name: &str, generator: G)
+  where
+    G: Generator + 'static,
+  {
+    assert!(
+      self.get(name).is_none(),
+      "generator {:?} is already registered",
+      name
+    );
+    self
+      .generators
+      .push((name.to_string(), Box::new(generator)));
+  }
+  pub fn get(&self, name: &str) -> Option<&dyn Generator> {
+    self
+      .generators
+      .iter()
+      .find(|(n, _)| n == name)
+      .map(|(_, generator)| generator.as_ref())
+  }
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.generators.iter().map(|(name, _)| name.as_str())
+  }
+}
+
+impl Default for Registry {
+  fn default() -> Self {
+    Self::builtin()
+  }
+}
+
+// Looped phrases from melody::phrases, each note held until the next.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Phrases {
+  pub params: Params,
+  pub phrasing: Phrasing,
+}
+
+impl Default for Phrases {
+  fn default() -> Self {
+    let quantum = Duration::from_millis(250);
+    Self {
+      params: Params::new(quantum),
+      phrasing: Phrasing {
+        length: quantum * 16,
+        repetitions: 2,
+        home: 0,
+        first_note: melody::FirstNote::Tonic,
+        stitch: None,
+      },
+    }
+  }
+}
+
+impl Generator for Phrases {
+  fn generate<'a>(&'a self, ctx: &'a GenCtx, seed: Seed) -> Stream<'a, NoteEvent> {
+    let velocity = ctx.velocity;
+    let notes = melody::phrases(&ctx.key, &self.params, &self.phrasing, seed);
+    Stream::from_iter(notes.updates().into_iter().tuple_windows().map(
+      move |((delay, note), (duration, _))| {
+        (delay, NoteEvent::new(note.note(), velocity, duration))
+      },
+    ))
+  }
+}
+
+#[test]
+fn test_registry() {
+  use crate::theory::{Note, PitchClass};
+
+  struct Drone;
+  impl Generator for Drone {
+    fn generate<'a>(&'a self, ctx: &'a GenCtx, _seed: Seed) -> Stream<'a, NoteEvent> {
+      let event = NoteEvent::new(ctx.key.tonic(), ctx.velocity, ctx.beat * 4);
+      Stream::immediate(event).repeat_every(ctx.beat * 4)
+    }
+  }
+
+  let mut registry = Registry::builtin();
+  registry.register("drone", Drone);
+  assert_eq!(
+    registry.names().collect::<Vec<_>>(),
+    vec!["phrases", "hi-hat", "drone"]
+  );
+  assert!(registry.get("arpeggio").is_none());
+
+  let ctx = GenCtx::new(
+    Key::major(Note::new(PitchClass::C, 3)),
+    Duration::from_millis(500),
+  );
+  let drone: Vec<_> = registry
+    .get("drone")
+    .unwrap()
+    .generate(&ctx, Seed::new("test"))
+    .take_n(3)
+    .into_iter()
+    .collect();
+  assert_eq!(drone.len(), 3);
+  assert_eq!(drone[1].0, Duration::from_secs(2));
+  assert_eq!(drone[1].1.note, Note::new(PitchClass::C, 3));
+
+  let phrases = registry.get("phrases").unwrap();
+  let a: Vec<_> = phrases
+    .generate(&ctx, Seed::new("test"))
+    .take_n(20)
+    .into_iter()
+    .collect();
+  let b: Vec<_> = phrases
+    .generate(&ctx, Seed::new("test"))
+    .take_n(20)
+    .into_iter()
+    .collect();
+  assert_eq!(a, b);
+  for window in a.windows(2) {
+    assert_eq!(window[0].1.duration, window[1].0);
+  }
+}
@@ -0,0 +1,353 @@
+use crate::chord::{Chord, Quality};
+use crate::rhythm;
+use crate::seed::Seed;
+use crate::stream::Stream;
+use crate::theory::{Key, Note, PitchClass, Range};
+use crate::var::Var;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::time::Duration;
+
+// How often the chord changes within a phrase.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HarmonicRhythm {
+  // One chord for the whole phrase.
+  PerPhrase,
+  // A change every so many beats, such as every bar or every two beats. The
+  // last chord is cut short if the phrase isn't a whole number of them.
+  Every(u32),
+  // Chords lasting a seeded number of beats, drawn by weight from (beats,
+  // weight) pairs, that fill each phrase exactly.
+  Irregular(Vec<(u32, f64)>),
+}
+
+impl HarmonicRhythm {
+  // Length in beats of each chord in a phrase of the given number of beats.
+  pub fn lengths(&self, phrase: u32, seed: Seed) -> Vec<u32> {
+    match self {
+      Self::PerPhrase => vec![phrase],
+      Self::Every(beats) => {
+        assert!(*beats > 0, "chords must last at least a beat");
+        let mut lengths = vec![*beats; (phrase / beats) as usize];
+        let rest = phrase % beats;
+        if rest > 0 {
+          lengths.push(rest);
+        }
+        lengths
+      }
+      Self::Irregular(values) => rhythm::partition(phrase, values, seed),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+  pub rhythm: HarmonicRhythm,
+  // Chance of each chord after the first being replaced by the dominant
+  // seventh of the chord that follows it, if that chord isn't the tonic.
+  pub secondary_dominants: f64,
+  // Chance of each chord between the first and the closing dominant being
+  // taken from the parallel major or minor key instead.
+  pub borrowed: f64,
+}
+
+impl Params {
+  pub fn new(rhythm: HarmonicRhythm) -> Self {
+    Self {
+      rhythm,
+      secondary_dominants: 0.0,
+      borrowed: 0.0,
+    }
+  }
+}
+
+// Diatonic triad built on a scale step of the key.
+pub fn triad(key: &Key, step: i64) -> Chord {
+  let notes: Vec<Note> = [0, 2, 4].iter().map(|i| key.at(step + i).note()).collect();
+  Chord::recognize(&notes).unwrap()
+}
+
+// The triad on the tonic of the key. Outside a seven note scale, this is
+// major if the key has a major third and minor otherwise.
+pub(crate) fn tonic_triad(key: &Key) -> Chord {
+  if key.scale().num_intervals() == 7 {
+    return triad(key, 0);
+  }
+  let tonic = key.tonic().pitch_class();
+  let major_third = PitchClass::from_ordinal(tonic.ordinal() + 4);
+  if key.pitch_classes().contains(&major_third) {
+    Chord::new(tonic, Quality::Major)
+  } else {
+    Chord::new(tonic, Quality::Minor)
+  }
+}
+
+// The triad on the fifth of the key. Stacking alternate steps only makes a
+// triad in a seven note scale, so in any other the dominant is built as a
+// major triad.
+fn dominant(key: &Key) -> Chord {
+  let fifth = key.tonic().offset(7).pitch_class();
+  let step = (0..7).find(|&step| key.at(step).note().pitch_class() == fifth);
+  match step {
+    Some(step) if key.scale().num_intervals() == 7 => triad(key, step),
+    _ => Chord::new(fifth, Quality::Major),
+  }
+}
+
+// Scale steps that commonly follow each degree of a seven note scale.
+const NEXT: [&[i64]; 7] = [
+  &[3, 4, 5, 1],
+  &[4, 6],
+  &[5, 3],
+  &[4, 1, 0],
+  &[0, 5],
+  &[1, 3],
+  &[0],
+];
+
+// Chords changing at the given harmonic rhythm. Each phrase of `phrase`
+// beats, which must be at least one, opens on the tonic and, if it has room
+// for more than one chord, closes on the dominant. The same arguments always
+// give the same chords, so every chord-aware voice can follow its own copy of
+// the progression.
+pub fn progression(
+  key: &Key,
+  params: &Params,
+  beat: Duration,
+  phrase: u32,
+  seed: Seed,
+) -> Var<'static, Chord> {
+  assert!(phrase > 0, "phrases must last at least a beat");
+  let key = key.clone();
+  let params = params.clone();
+  let mut chords = (0u64..).flat_map(move |i| {
+    let seed = seed.fork(i);
+    let lengths = params.rhythm.lengths(phrase, seed.fork("rhythm"));
+    let chords = phrase_chords(&key, &params, lengths.len(), seed);
+    lengths
+      .into_iter()
+      .map(|length| beat * length)
+      .zip(chords)
+      .collect::<Vec<_>>()
+  });
+  let (mut length, first) = chords.next().unwrap();
+  Var::from_updates(
+    first,
+    Stream::from_iter(
+      chords.map(move |(next_length, chord)| (std::mem::replace(&mut length, next_length), chord)),
+    ),
+  )
+}
+
+fn phrase_chords(key: &Key, params: &Params, count: usize, seed: Seed) -> Vec<Chord> {
+  let num_degrees = key.scale().num_intervals() as i64;
+  let mut rng = seed.fork("degrees").rng();
+  let mut step = 0;
+  let mut steps = Vec::new();
+  for n in 0..count {
+    if n > 0 && n == count - 1 {
+      step = 4;
+    } else if n > 0 {
+      step = match num_degrees {
+        7 => *NEXT[step as usize].choose(&mut rng).unwrap(),
+        degrees => rng.gen_range(0..degrees),
+      };
+    }
+    steps.push(step);
+  }
+  let parallel = key.parallel();
+  let mut rng = seed.fork("colour").rng();
+  let mut chords: Vec<Chord> = steps
+    .iter()
+    .enumerate()
+    .map(|(n, &step)| match &parallel {
+      _ if n > 0 && n == count - 1 => dominant(key),
+      Some(parallel) if n > 0 && n < count - 1 && rng.gen_bool(params.borrowed) => {
+        triad(parallel, step)
+      }
+      _ => triad(key, step),
+    })
+    .collect();
+  let pitch_classes = key.pitch_classes();
+  // From the end, so that each dominant resolves to a chord that stays as it
+  // is: a chord that was itself replaced is never made a target.
+  let mut replaced = vec![false; count];
+  for n in (1..count.saturating_sub(1)).rev() {
+    if replaced[n + 1] {
+      continue;
+    }
+    let target = chords[n + 1];
+    let diatonic = target
+      .pitch_classes()
+      .iter()
+      .all(|pc| pitch_classes.contains(pc));
+    let tonic = steps[n + 1].rem_euclid(num_degrees) == 0;
+    if diatonic
+      && !tonic
+      && target.quality != Quality::Diminished
+      && rng.gen_bool(params.secondary_dominants)
+    {
+      let root = PitchClass::from_ordinal(target.root.ordinal() + 7);
+      chords[n] = Chord::new(root, Quality::Dominant7);
+      replaced[n] = true;
+    }
+  }
+  chords
+}
+
+// The lowest note in the range on each chord's bass.
+pub fn bass<'a>(chords: Var<'a, Chord>, range: Range) -> Var<'a, Note> {
+  chords.map(move |chord| {
+    let note = Note::new(chord.bass(), range.lowest.octave());
+    if note < range.lowest {
+      note.offset(12)
+    } else {
+      note
+    }
+  })
+}
+
+// Each chord in close position from the lowest note up.
+pub fn pad<'a>(chords: Var<'a, Chord>, lowest: Note) -> Var<'a, Vec<Note>> {
+  chords.map(move |chord| chord.voice(lowest))
+}
+
+#[test]
+fn test_harmonic_rhythm() {
+  let seed = || Seed::new("test");
+  assert_eq!(HarmonicRhythm::PerPhrase.lengths(16, seed()), vec![16]);
+  assert_eq!(HarmonicRhythm::Every(4).lengths(16, seed()), vec![4; 4]);
+  assert_eq!(HarmonicRhythm::Every(3).lengths(8, seed()), vec![3, 3, 2]);
+  for i in 0..20 {
+    let lengths = HarmonicRhythm::Irregular(vec![(2, 1.0), (4, 1.0)]).lengths(16, Seed::new(i));
+    assert_eq!(lengths.iter().sum::<u32>(), 16);
+    assert!(lengths.iter().all(|&n| n == 2 || n == 4));
+  }
+}
+
+#[test]
+fn test_progression() {
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let beat = Duration::from_millis(500);
+  let chords = || {
+    progression(
+      &key,
+      &Params::new(HarmonicRhythm::Every(2)),
+      beat,
+      8,
+      Seed::new("test"),
+    )
+  };
+  let changes: Vec<_> = chords().updates().take_n(12).timestamps().collect();
+  for (i, (time, chord)) in changes.iter().enumerate() {
+    assert_eq!(*time, beat * 2 * i as u32);
+    match i % 4 {
+      0 => assert_eq!(*chord, Chord::new(PitchClass::C, Quality::Major)),
+      3 => assert_eq!(*chord, Chord::new(PitchClass::G, Quality::Major)),
+      _ => (),
+    }
+  }
+  let range = Range::new(Note::new(PitchClass::E, 2), Note::new(PitchClass::E, 3));
+  let roots = bass(chords(), range).updates().take_n(12).collect_timed();
+  for ((_, chord), (_, root)) in changes.iter().zip(roots) {
+    assert_eq!(root.pitch_class(), chord.root);
+    assert!(range.contains(root));
+  }
+  let voicings = pad(chords(), Note::new(PitchClass::C, 4))
+    .updates()
+    .take_n(1)
+    .collect_timed();
+  assert_eq!(
+    voicings[0].1,
+    vec![
+      Note::new(PitchClass::C, 4),
+      Note::new(PitchClass::E, 4),
+      Note::new(PitchClass::G, 4)
+    ]
+  );
+}
+
+#[test]
+fn test_pentatonic_progression() {
+  let key = Key::pentatonic(Note::new(PitchClass::D, 4));
+  let params = Params::new(HarmonicRhythm::Every(2));
+  let beat = Duration::from_millis(500);
+  let changes = progression(&key, &params, beat, 8, Seed::new("test"))
+    .updates()
+    .take_n(12)
+    .collect_timed();
+  for (_, chord) in changes.iter().skip(3).step_by(4) {
+    assert_eq!(*chord, Chord::new(PitchClass::A, Quality::Major));
+  }
+}
+
+#[test]
+#[should_panic(expected = "phrases must last at least a beat")]
+fn test_empty_phrase() {
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let params = Params::new(HarmonicRhythm::Every(2));
+  progression(
+    &key,
+    &params,
+    Duration::from_millis(500),
+    0,
+    Seed::new("test"),
+  );
+}
+
+#[test]
+fn test_chromatic_progression() {
+  use crate::chord::analyze;
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let chords = |params: Params| {
+    progression(&key, &params, Duration::from_secs(1), 4, Seed::new("test"))
+      .updates()
+      .take_n(40)
+      .into_iter()
+      .map(|(_, chord)| chord)
+      .collect::<Vec<_>>()
+  };
+  let rhythm = HarmonicRhythm::Every(1);
+  let plain = chords(Params::new(rhythm.clone()));
+  assert!(analyze(&key, &plain)
+    .iter()
+    .all(|numeral| numeral.tonicizes.is_none() && !numeral.borrowed));
+
+  let applied = chords(Params {
+    secondary_dominants: 1.0,
+    ..Params::new(rhythm.clone())
+  });
+  for (i, chord) in applied.iter().enumerate() {
+    if chord.quality == Quality::Dominant7 {
+      let next = applied[i + 1].root;
+      assert_eq!(chord.root, PitchClass::from_ordinal(next.ordinal() + 7));
+    }
+  }
+  let numerals = analyze(&key, &applied);
+  assert!(numerals.iter().any(|numeral| numeral.tonicizes.is_some()));
+  for (i, numeral) in numerals.iter().enumerate() {
+    if let Some(target) = numeral.tonicizes {
+      let next = &numerals[i + 1];
+      assert_eq!(target, (next.degree, next.quality));
+      assert_eq!(numeral.quality, Quality::Dominant7);
+    }
+  }
+
+  let borrowed = chords(Params {
+    borrowed: 1.0,
+    ..Params::new(rhythm)
+  });
+  let numerals = analyze(&key, &borrowed);
+  for (i, numeral) in numerals.iter().enumerate() {
+    if i % 4 == 1 || i % 4 == 2 {
+      let parallel = Key::minor(Note::new(PitchClass::C, 4));
+      let pitch_classes = parallel.pitch_classes();
+      assert!(borrowed[i]
+        .pitch_classes()
+        .iter()
+        .all(|pc| pitch_classes.contains(pc)));
+    }
+    assert_eq!(numeral.tonicizes, None);
+  }
+  assert!(numerals.iter().any(|numeral| numeral.borrowed));
+}
@@ -0,0 +1,46 @@
+// The prelude and the public modules below are avril's API and follow
+// semver: breaking changes only come with a new minor version while avril is
+// at 0.x. The exception is `surprise`, which is experimental and may change
+// in any release. Most programs only need the prelude.
+
+pub mod arrange;
+pub mod capture;
+pub mod chord;
+pub mod distr;
+pub mod drums;
+pub mod event;
+pub mod generator;
+pub mod harmony;
+pub mod melody;
+pub mod midi;
+pub mod pattern;
+pub mod rhythm;
+pub mod score;
+pub mod seed;
+pub mod stream;
+pub mod template;
+pub mod tempo;
+pub mod theory;
+pub mod transport;
+pub mod var;
+
+pub mod surprise;
+
+#[cfg(test)]
+mod testing;
+
+pub mod prelude {
+  pub use crate::chord::Chord;
+  pub use crate::event::{Articulation, Element, NoteEvent};
+  pub use crate::generator::{GenCtx, Generator, Registry};
+  pub use crate::melody::{
+    Durations, FirstNote, Gesture, Params, Phrasing, RepeatMode, Repetition, Stitch,
+  };
+  pub use crate::midi::{Channel, Message, MessageExt};
+  pub use crate::pattern::Pattern;
+  pub use crate::seed::Seed;
+  pub use crate::stream::Stream;
+  pub use crate::tempo::{BeatStream, Beats, TempoMap};
+  pub use crate::theory::{Key, Note, NoteInKey, PitchClass, Range, Scale};
+  pub use crate::var::Var;
+}
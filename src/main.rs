@@ -1,54 +1,10 @@
-use self::midi::MessageExt;
-use self::seed::Seed;
-use self::stream::Stream;
-use self::theory::{Key, Note, NoteInKey, PitchClass};
-use self::var::Var;
-use rand_distr::{Distribution, Exp, Normal};
+use avril::chord::RomanNumeral;
+use avril::midi::gm::GmProgram;
+use avril::prelude::*;
+use avril::transport::Output;
+use avril::{event, melody, midi};
 use std::time::Duration;
 
-mod midi;
-mod seed;
-mod stream;
-mod theory;
-mod var;
-
-fn melody<'k>(
-  key: &'k Key,
-  first_note: NoteInKey<'k>,
-  quantum_duration: Duration,
-  seed: Seed,
-) -> Var<'k, NoteInKey<'k>> {
-  let delta_std_dev = (key.scale().num_intervals() as f64) / 2.0;
-  let num_quanta_distr = Exp::<f64>::new(2.0).unwrap();
-  Var::from_updates(
-    first_note,
-    Stream::from_iter(itertools::unfold(
-      (first_note, seed.fork("notes")),
-      move |(prev_note, seed)| {
-        let delta_distr = Normal::<f64>::new(
-          (-prev_note.scale_steps_from_tonic() / 2) as f64,
-          delta_std_dev,
-        )
-        .unwrap();
-        let delta = delta_distr
-          .sample_iter(&mut seed.fork("delta").rng())
-          .map(|x| x.round() as i64)
-          .filter(|&x| x != 0)
-          .next()
-          .unwrap();
-        let note = prev_note.offset(delta);
-        let num_quanta = num_quanta_distr
-          .sample(&mut seed.fork("num_quanta").rng())
-          .ceil() as u32;
-        let duration = quantum_duration * num_quanta;
-        *prev_note = note;
-        *seed = seed.fork("next");
-        Some((duration, note))
-      },
-    )),
-  )
-}
-
 fn play<'a>(channel: midi::Channel, pitch: Var<'a, Option<Note>>) -> Stream<'a, midi::Message> {
   use std::mem::replace;
   let mut current_pitch = None;
@@ -83,56 +39,107 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
   let beat_duration = Duration::from_millis(230);
   let phrase_duration = beat_duration * 16;
   let phrase_repetitions = 2;
-  let reseed_interval = phrase_duration * phrase_repetitions;
   let num_phrases = 6;
   let key = Key::pentatonic(Note::new(PitchClass::D, 4));
+  let treble_params = Params {
+    durations: Durations::Bars {
+      values: vec![(1, 3.0), (2, 4.0), (3, 2.0), (4, 1.0)],
+      bar: 8,
+    },
+    range: Range::new(Note::new(PitchClass::D, 4), Note::new(PitchClass::D, 6)),
+    repetition: Repetition {
+      probability: 0.15,
+      max_consecutive: 1,
+      mode: RepeatMode::Rearticulate,
+    },
+    ..Params::new(beat_duration / 2)
+  };
+  let bass_params = Params {
+    range: Range::new(Note::new(PitchClass::D, 1), Note::new(PitchClass::D, 3)),
+    repetition: Repetition {
+      probability: 0.25,
+      max_consecutive: 2,
+      mode: RepeatMode::Tie,
+    },
+    ..Params::new(beat_duration * 2)
+  };
 
-  let treble_seed = seed.fork("treble");
-  let treble = Var::from_updates(
-    treble_seed.fork(0),
-    Stream::from_iter((1..).map(|i| (reseed_interval, treble_seed.fork(i)))),
+  let treble = melody::phrases(
+    &key,
+    &treble_params,
+    &Phrasing {
+      length: phrase_duration,
+      repetitions: phrase_repetitions,
+      home: 7,
+      first_note: FirstNote::ChordTone,
+      stitch: Some(Stitch {
+        threshold: 3,
+        gesture: Gesture::Run { max_notes: 3 },
+        length: beat_duration * 2,
+      }),
+    },
+    seed.fork("treble"),
   )
-  .map(|mel_seed| melody(&key, key.at(7), beat_duration, mel_seed).repeat_every(phrase_duration))
-  .sequence()
   .map(|note_in_key| Some(note_in_key.note()));
 
-  let bass_seed = seed.fork("bass");
-  let bass = Var::from_updates(
-    bass_seed.fork(0),
-    Stream::from_iter((1..).map(|i| (reseed_interval, bass_seed.fork(i)))),
+  let bass = melody::phrases(
+    &key,
+    &bass_params,
+    &Phrasing {
+      length: phrase_duration,
+      repetitions: phrase_repetitions,
+      home: -10,
+      first_note: FirstNote::Tonic,
+      stitch: Some(Stitch {
+        threshold: 3,
+        gesture: Gesture::Hold,
+        length: beat_duration * 4,
+      }),
+    },
+    seed.fork("bass"),
   )
-  .map(|mel_seed| {
-    melody(&key, key.at(-10), beat_duration * 2, mel_seed).repeat_every(phrase_duration)
-  })
-  .sequence()
   .map(|note_in_key| Some(note_in_key.note()));
 
-  let messages = Stream::merge_all(vec![
-    Stream::immediate(midi::Message::ProgramChange(midi::Channel::Ch1, 0)),
-    Stream::immediate(midi::Message::ProgramChange(midi::Channel::Ch2, 0)),
-    play(midi::Channel::Ch1, treble),
-    play(midi::Channel::Ch2, bass),
-    active_sensing(),
-  ]);
+  // Message's ordering puts note offs and program changes before note ons, so
+  // a voice releasing a note never cuts off another voice starting it.
+  let messages = Stream::merge_all_by(
+    vec![
+      Stream::immediate(midi::Message::ProgramChange(
+        midi::Channel::Ch1,
+        GmProgram::AcousticGrandPiano.into(),
+      )),
+      Stream::immediate(midi::Message::ProgramChange(
+        midi::Channel::Ch2,
+        GmProgram::AcousticGrandPiano.into(),
+      )),
+      play(midi::Channel::Ch1, treble),
+      play(midi::Channel::Ch2, bass),
+      active_sensing(),
+    ],
+    |a, b| a <= b,
+  );
   let messages = messages.take(phrase_duration * num_phrases);
+  let messages = event::limit_polyphony(8, messages);
 
-  let output = midir::MidiOutput::new("avril")?;
-  let ports = output.ports();
-  let port = ports
-    .iter()
-    .filter(|port| {
-      output
-        .port_name(port)
-        .unwrap_or(String::new())
-        .starts_with("FLUID")
-    })
-    .next()
-    .unwrap();
-  let mut conn = output.connect(port, "avril_port")?;
+  let mut output = Output::connect("FLUID")?;
+  let mut sounding = Vec::new();
+  let mut harmony = None;
   for (delay, message) in messages {
     println!("{} {:?}", delay.as_millis(), message);
     std::thread::sleep(delay);
-    conn.send(&message.encode())?;
+    output.send(&message)?;
+    match message {
+      midi::Message::NoteOn(_, note, _) => sounding.push(Note::from_midi(note)),
+      midi::Message::NoteOff(_, note, _) => sounding.retain(|&n| n != Note::from_midi(note)),
+      _ => continue,
+    }
+    let chord = Chord::recognize(&sounding);
+    if chord != harmony {
+      if let Some(chord) = chord {
+        println!("harmony: {} ({})", chord, RomanNumeral::new(&key, &chord));
+      }
+      harmony = chord;
+    }
   }
 
   Ok(())
@@ -0,0 +1,601 @@
+use crate::distr::Step;
+use crate::harmony;
+use crate::rhythm;
+use crate::seed::Seed;
+use crate::stream::Stream;
+use crate::theory::{Direction, Key, NoteInKey, Range};
+use crate::var::Var;
+use rand::distributions::WeightedIndex;
+use rand::Rng;
+use rand_distr::{Distribution, Exp};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RepeatMode {
+  // Strike the repeated note again.
+  Rearticulate,
+  // Extend the previous note instead of striking it again.
+  Tie,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Repetition {
+  pub probability: f64,
+  pub max_consecutive: u32,
+  pub mode: RepeatMode,
+}
+
+impl Repetition {
+  pub fn never() -> Self {
+    Self {
+      probability: 0.0,
+      max_consecutive: 0,
+      mode: RepeatMode::Rearticulate,
+    }
+  }
+}
+
+// How note lengths, in quanta, are chosen.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Durations {
+  // Exponentially distributed with the given rate, rounded up.
+  Exponential(f64),
+  // Drawn from a weighted vocabulary of note values. Values that would cross
+  // the next bar line are never chosen; if none fit, the note lasts until the
+  // bar line.
+  Weighted { values: Vec<(u32, f64)>, bar: u32 },
+  // Each bar is partitioned exactly into note values drawn from a weighted
+  // vocabulary, so notes always line up with bar lines.
+  Bars { values: Vec<(u32, f64)>, bar: u32 },
+}
+
+impl Durations {
+  // Eighth, quarter, dotted quarter and half notes, with an eighth note
+  // quantum and four beats to the bar.
+  pub fn common_time() -> Self {
+    Self::Weighted {
+      values: vec![(1, 3.0), (2, 4.0), (3, 2.0), (4, 1.0)],
+      bar: 8,
+    }
+  }
+  // Length of the note starting `position` quanta into the melody.
+  fn sample(&self, position: u32, seed: &Seed) -> u32 {
+    match self {
+      Self::Exponential(rate) => Exp::new(*rate)
+        .unwrap()
+        .sample(&mut seed.fork(position).rng())
+        .ceil() as u32,
+      Self::Weighted { values, bar } => {
+        let remaining = bar - position % bar;
+        let fitting: Vec<_> = values.iter().filter(|(n, _)| *n <= remaining).collect();
+        if fitting.is_empty() {
+          return remaining;
+        }
+        let distr = WeightedIndex::new(fitting.iter().map(|(_, weight)| weight)).unwrap();
+        fitting[distr.sample(&mut seed.fork(position).rng())].0
+      }
+      Self::Bars { values, bar } => {
+        let offset = position % bar;
+        rhythm::partition(*bar, values, seed.fork(position / bar))
+          .into_iter()
+          .scan(0, |end, n| {
+            *end += n;
+            Some(*end)
+          })
+          .find(|&end| end > offset)
+          .unwrap()
+          - offset
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+  pub quantum: Duration,
+  pub durations: Durations,
+  pub range: Range,
+  pub repetition: Repetition,
+}
+
+impl Params {
+  pub fn new(quantum: Duration) -> Self {
+    Self {
+      quantum,
+      durations: Durations::Exponential(2.0),
+      range: Range::midi(),
+      repetition: Repetition::never(),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FirstNote {
+  // Restart on the home scale step.
+  Home,
+  // Start on the note the previous phrase ended on.
+  Continue,
+  // Start on the tonic nearest to where the previous phrase ended.
+  Tonic,
+  // Start on one of the tonic triad tones nearest to where the previous phrase
+  // ended.
+  ChordTone,
+  // Start on a random scale step at most this far from the home step.
+  Seeded(i64),
+}
+
+impl FirstNote {
+  pub(crate) fn choose<'k>(
+    self,
+    key: &'k Key,
+    home: i64,
+    previous: Option<NoteInKey<'k>>,
+    seed: Seed,
+  ) -> NoteInKey<'k> {
+    let len = key.scale().num_intervals() as i64;
+    let reference = previous.map_or(home, |note| note.scale_steps_from_tonic());
+    match self {
+      Self::Home => key.at(home),
+      Self::Continue => previous.unwrap_or_else(|| key.at(home)),
+      Self::Tonic => key.at((reference + len / 2).div_euclid(len) * len),
+      Self::ChordTone => {
+        let tones = harmony::tonic_triad(key).pitch_classes();
+        let mut candidates: Vec<i64> = (reference - len..=reference + len)
+          .filter(|&step| tones.contains(&key.at(step).note().pitch_class()))
+          .collect();
+        candidates.sort_by_key(|step| (step - reference).abs());
+        candidates.truncate(3);
+        key.at(candidates[seed.rng().gen_range(0..candidates.len())])
+      }
+      Self::Seeded(spread) => key.at(home + seed.rng().gen_range(-spread..=spread)),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Gesture {
+  // Step-wise run approaching the next phrase's first note.
+  Run { max_notes: usize },
+  // A single note held midway between the two phrases.
+  Hold,
+}
+
+// Connective material inserted at the end of a phrase when the leap into the
+// next phrase would otherwise be too large.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stitch {
+  // Leaps of more than this many scale steps are stitched.
+  pub threshold: i64,
+  pub gesture: Gesture,
+  // Time taken from the end of the phrase for the gesture.
+  pub length: Duration,
+}
+
+impl Stitch {
+  pub(crate) fn apply<'k>(
+    &self,
+    key: &'k Key,
+    phrase: Var<'k, NoteInKey<'k>>,
+    phrase_length: Duration,
+    next_note: NoteInKey<'k>,
+  ) -> Var<'k, NoteInKey<'k>> {
+    if self.length >= phrase_length {
+      return phrase;
+    }
+    let start = phrase_length - self.length;
+    let mut updates: Vec<_> = phrase
+      .updates()
+      .take(phrase_length)
+      .timestamps()
+      .filter(|&(elapsed, _)| elapsed < phrase_length)
+      .collect();
+    let last_note = updates.last().unwrap().1;
+    let leap = next_note.scale_steps_from_tonic() - last_note.scale_steps_from_tonic();
+    if leap.abs() > self.threshold {
+      // The gesture replaces the end of the phrase, leading on from the note
+      // sounding when it starts.
+      updates.retain(|&(elapsed, _)| elapsed < start);
+      let from = updates.last().unwrap().1;
+      let gesture = self.gesture(key, from, next_note);
+      let note_length = self.length / gesture.len() as u32;
+      updates.extend(
+        gesture
+          .into_iter()
+          .enumerate()
+          .map(|(i, note)| (start + note_length * i as u32, note)),
+      );
+    }
+    let mut updates = Stream::from_timestamps(updates);
+    let (_, present) = updates.next().unwrap();
+    Var::from_updates(present, updates)
+  }
+  fn gesture<'k>(
+    &self,
+    key: &'k Key,
+    from: NoteInKey<'k>,
+    to: NoteInKey<'k>,
+  ) -> Vec<NoteInKey<'k>> {
+    let (from, to) = (from.scale_steps_from_tonic(), to.scale_steps_from_tonic());
+    let direction = Direction::of(to - from);
+    match self.gesture {
+      Gesture::Run { max_notes } => {
+        let num_notes = ((to - from).abs() - 1).clamp(1, max_notes.max(1) as i64);
+        (1..=num_notes)
+          .rev()
+          .map(|i| key.at_in_direction(to - (to - from).signum() * i, direction))
+          .collect()
+      }
+      Gesture::Hold => vec![key.at_in_direction(from + (to - from) / 2, direction)],
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Phrasing {
+  pub length: Duration,
+  // Number of times each phrase is looped before a new one is generated.
+  pub repetitions: u32,
+  // Scale step the first phrase starts on.
+  pub home: i64,
+  pub first_note: FirstNote,
+  pub stitch: Option<Stitch>,
+}
+
+// A sequence of looped phrases, each generated from a fresh seed.
+pub fn phrases<'k>(
+  key: &'k Key,
+  params: &Params,
+  phrasing: &Phrasing,
+  seed: Seed,
+) -> Var<'k, NoteInKey<'k>> {
+  let params = params.clone();
+  let phrasing = phrasing.clone();
+  let first_note =
+    phrasing
+      .first_note
+      .choose(key, phrasing.home, None, seed.fork(0).fork("first_note"));
+  let (first_phrase, next_first_note) = phrase(key, &params, &phrasing, first_note, &seed, 0);
+  Var::from_updates(
+    first_phrase,
+    Stream::from_iter(itertools::unfold(
+      (1, next_first_note),
+      move |(i, first_note)| {
+        let (next_phrase, next_first_note) =
+          phrase(key, &params, &phrasing, *first_note, &seed, *i);
+        *i += 1;
+        *first_note = next_first_note;
+        Some((phrasing.length * phrasing.repetitions, next_phrase))
+      },
+    )),
+  )
+  .sequence()
+}
+
+// Generates the i'th phrase, looped for the configured number of repetitions,
+// along with the note the following phrase will start on.
+fn phrase<'k>(
+  key: &'k Key,
+  params: &Params,
+  phrasing: &Phrasing,
+  first_note: NoteInKey<'k>,
+  seed: &Seed,
+  i: i32,
+) -> (Var<'k, NoteInKey<'k>>, NoteInKey<'k>) {
+  let mut sample: Vec<_> = melody(key, first_note, params, seed.fork(i))
+    .updates()
+    .take(phrasing.length)
+    .into_iter()
+    .collect();
+  sample.remove(0);
+  let last_note = sample.last().map_or(first_note, |&(_, note)| note);
+  let next_first_note = phrasing.first_note.choose(
+    key,
+    phrasing.home,
+    Some(last_note),
+    seed.fork(i + 1).fork("first_note"),
+  );
+  let phrase =
+    Var::from_updates(first_note, Stream::from_iter(sample)).repeat_every_tied(phrasing.length);
+  let phrase = match &phrasing.stitch {
+    Some(stitch) => stitch.apply(
+      key,
+      phrase,
+      phrasing.length * phrasing.repetitions,
+      next_first_note,
+    ),
+    None => phrase,
+  };
+  (phrase, next_first_note)
+}
+
+pub fn melody<'k>(
+  key: &'k Key,
+  first_note: NoteInKey<'k>,
+  params: &Params,
+  seed: Seed,
+) -> Var<'k, NoteInKey<'k>> {
+  weighted_melody(key, first_note, params, Var::constant(Vec::new()), seed)
+}
+
+// Like melody, with the choice of each note also weighted by its scale
+// degree. `degree_weights` lists the weight of each degree from the tonic up,
+// with missing degrees weighted 1, and may change over time, for example to
+// lean towards the dominant before a cadence. The weights in effect when a
+// note starts are used to choose it.
+pub fn weighted_melody<'k>(
+  key: &'k Key,
+  first_note: NoteInKey<'k>,
+  params: &Params,
+  degree_weights: Var<'k, Vec<f64>>,
+  seed: Seed,
+) -> Var<'k, NoteInKey<'k>> {
+  let Params {
+    quantum,
+    durations,
+    range,
+    repetition,
+  } = params.clone();
+  let num_degrees = key.scale().num_intervals() as i64;
+  let delta_std_dev = num_degrees as f64 / 2.0;
+  let rhythm_seed = seed.fork("rhythm");
+  let mut weight_updates = degree_weights.updates().timestamps().peekable();
+  let mut weights = Vec::new();
+  let mut now = Duration::from_secs(0);
+  Var::from_updates(
+    first_note,
+    Stream::from_iter(itertools::unfold(
+      (first_note, 0, 0, seed.fork("notes")),
+      move |(prev_note, repeats, position, seed)| {
+        let mut duration = Duration::from_secs(0);
+        loop {
+          let num_quanta = durations.sample(*position, &rhythm_seed);
+          *position += num_quanta;
+          duration += quantum * num_quanta;
+          while let Some((_, w)) = weight_updates.next_if(|(time, _)| *time <= now + duration) {
+            weights = w;
+          }
+          let repeat = *repeats < repetition.max_consecutive
+            && seed.fork("repeat").rng().gen_bool(repetition.probability);
+          let note = if repeat {
+            *prev_note
+          } else {
+            let prev_note = *prev_note;
+            let delta_distr = Step::weighted(
+              -prev_note.scale_steps_from_tonic() / 2,
+              delta_std_dev,
+              false,
+              |delta| {
+                let degree = range
+                  .fold_in_key(prev_note.offset(delta))
+                  .scale_steps_from_tonic()
+                  .rem_euclid(num_degrees);
+                weights.get(degree as usize).copied().unwrap_or(1.0)
+              },
+            );
+            let delta = delta_distr.sample(&mut seed.fork("delta").rng());
+            range.fold_in_key(prev_note.offset(delta))
+          };
+          *repeats = if repeat { *repeats + 1 } else { 0 };
+          *prev_note = note;
+          *seed = seed.fork("next");
+          if !(repeat && repetition.mode == RepeatMode::Tie) {
+            now += duration;
+            return Some((duration, note));
+          }
+        }
+      },
+    )),
+  )
+}
+
+#[test]
+fn test_repetition() {
+  use crate::theory::{Note, PitchClass};
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let params = Params {
+    repetition: Repetition {
+      probability: 1.0,
+      max_consecutive: 2,
+      mode: RepeatMode::Rearticulate,
+    },
+    ..Params::new(Duration::from_millis(100))
+  };
+  let notes: Vec<_> = melody(&key, key.at(0), &params, Seed::new("test"))
+    .updates()
+    .into_iter()
+    .take(9)
+    .map(|(_, n)| n.scale_steps_from_tonic())
+    .collect();
+  for run in notes.chunks(3) {
+    assert_eq!(run[0], run[1]);
+    assert_eq!(run[1], run[2]);
+  }
+  assert_ne!(notes[2], notes[3]);
+
+  let params = Params {
+    repetition: Repetition {
+      mode: RepeatMode::Tie,
+      ..params.repetition
+    },
+    ..params
+  };
+  let notes: Vec<_> = melody(&key, key.at(0), &params, Seed::new("test"))
+    .updates()
+    .into_iter()
+    .take(9)
+    .map(|(_, n)| n.scale_steps_from_tonic())
+    .collect();
+  assert!(notes.windows(2).all(|w| w[0] != w[1]));
+}
+
+#[test]
+fn test_first_note() {
+  use crate::theory::{Note, PitchClass};
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let pentatonic = Key::pentatonic(Note::new(PitchClass::C, 4));
+  let seed = || Seed::new("test");
+  assert_eq!(
+    FirstNote::Home.choose(&key, 3, Some(key.at(12)), seed()),
+    key.at(3)
+  );
+  assert_eq!(FirstNote::Continue.choose(&key, 3, None, seed()), key.at(3));
+  assert_eq!(
+    FirstNote::Continue.choose(&key, 3, Some(key.at(12)), seed()),
+    key.at(12)
+  );
+  assert_eq!(
+    FirstNote::Tonic.choose(&key, 3, Some(key.at(12)), seed()),
+    key.at(14)
+  );
+  assert_eq!(
+    FirstNote::Tonic.choose(&key, 3, Some(key.at(-2)), seed()),
+    key.at(0)
+  );
+  for i in 0..20 {
+    let note = FirstNote::ChordTone.choose(&key, 0, Some(key.at(12)), Seed::new(i));
+    assert!([9, 11, 14].contains(&note.scale_steps_from_tonic()));
+    let note = FirstNote::ChordTone.choose(&pentatonic, 0, Some(pentatonic.at(10)), Seed::new(i));
+    assert!([8, 10, 11].contains(&note.scale_steps_from_tonic()));
+    let note = FirstNote::Seeded(2).choose(&key, 7, None, Seed::new(i));
+    assert!((5..=9).contains(&note.scale_steps_from_tonic()));
+  }
+}
+
+#[test]
+fn test_stitch() {
+  use crate::theory::{Note, PitchClass};
+  let ms = Duration::from_millis;
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let phrase = || Var::from_updates(key.at(0), Stream::from_iter(vec![(ms(2), key.at(1))]));
+  let stitch = Stitch {
+    threshold: 2,
+    gesture: Gesture::Run { max_notes: 3 },
+    length: ms(3),
+  };
+  let updates = |var: Var<'_, NoteInKey<'_>>| {
+    var
+      .updates()
+      .into_iter()
+      .map(|(d, n)| (d, n.scale_steps_from_tonic()))
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    updates(stitch.apply(&key, phrase(), ms(8), key.at(8))),
+    vec![(ms(0), 0), (ms(2), 1), (ms(3), 5), (ms(1), 6), (ms(1), 7)]
+  );
+  assert_eq!(
+    updates(stitch.apply(&key, phrase(), ms(8), key.at(3))),
+    vec![(ms(0), 0), (ms(2), 1)]
+  );
+  let stitch = Stitch {
+    gesture: Gesture::Hold,
+    ..stitch
+  };
+  assert_eq!(
+    updates(stitch.apply(&key, phrase(), ms(8), key.at(-5))),
+    vec![(ms(0), 0), (ms(2), 1), (ms(3), -2)]
+  );
+  // The leap is from the last note of the phrase, even within the length.
+  let ending = || phrase().updates().chain(Stream::at(ms(4), key.at(7)));
+  assert_eq!(
+    updates(stitch.apply(
+      &key,
+      Var::from_updates(key.at(0), ending().skip_n(1)),
+      ms(8),
+      key.at(8)
+    )),
+    vec![(ms(0), 0), (ms(2), 1), (ms(4), 7)]
+  );
+  let unstitched = Stitch {
+    threshold: 1000,
+    ..stitch
+  };
+  assert_eq!(
+    updates(unstitched.apply(
+      &key,
+      Var::from_updates(key.at(0), ending().skip_n(1)),
+      ms(8),
+      key.at(-20)
+    )),
+    vec![(ms(0), 0), (ms(2), 1), (ms(4), 7)]
+  );
+}
+
+#[test]
+fn test_durations() {
+  let seed = Seed::new("test");
+  for durations in [
+    Durations::Weighted {
+      values: vec![(3, 1.0), (2, 1.0)],
+      bar: 8,
+    },
+    Durations::Bars {
+      values: vec![(3, 1.0), (2, 1.0)],
+      bar: 8,
+    },
+  ] {
+    let mut position = 0;
+    while position < 800 {
+      let n = durations.sample(position, &seed);
+      let bar_end = (position / 8 + 1) * 8;
+      assert!(position + n <= bar_end);
+      if let Durations::Bars { .. } = durations {
+        assert!(n == 2 || n == 3);
+      } else {
+        assert!(n == 2 || n == 3 || position + n == bar_end);
+      }
+      position += n;
+    }
+  }
+}
+
+#[test]
+fn test_weighted_melody() {
+  use crate::theory::{Note, PitchClass};
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let params = Params {
+    durations: Durations::Bars {
+      values: vec![(1, 1.0)],
+      bar: 4,
+    },
+    range: Range::new(Note::new(PitchClass::C, 4), Note::new(PitchClass::C, 6)),
+    ..Params::new(Duration::from_millis(100))
+  };
+  let half = Duration::from_secs(20);
+  let mut dominant = vec![1.0; 7];
+  dominant[4] = 100.0;
+  let weights = Var::from_updates(Vec::new(), Stream::from_iter(vec![(half, dominant)]));
+  let notes: Vec<_> = weighted_melody(&key, key.at(0), &params, weights, Seed::new("test"))
+    .updates()
+    .take(half * 2)
+    .timestamps()
+    .collect();
+  let share = |from: Duration, to: Duration| {
+    let degrees: Vec<_> = notes
+      .iter()
+      .filter(|(time, _)| from <= *time && *time < to)
+      .map(|(_, note)| note.scale_steps_from_tonic().rem_euclid(7))
+      .collect();
+    degrees.iter().filter(|&&d| d == 4).count() as f64 / degrees.len() as f64
+  };
+  assert!(share(Duration::from_secs(0), half) < 0.3);
+  assert!(share(half + Duration::from_millis(100), half * 2) > 0.6);
+
+  let unweighted: Vec<_> = melody(&key, key.at(0), &params, Seed::new("test"))
+    .updates()
+    .take(half)
+    .into_iter()
+    .collect();
+  let weighted: Vec<_> = weighted_melody(
+    &key,
+    key.at(0),
+    &params,
+    Var::constant(vec![1.0; 7]),
+    Seed::new("test"),
+  )
+  .updates()
+  .take(half)
+  .into_iter()
+  .collect();
+  assert_eq!(unweighted, weighted);
+}
@@ -1,10 +1,41 @@
+use crate::stream::Stream;
+use crate::var::Var;
 pub use midi::{Channel, Message};
 use midi::{RawMessage, ToRawMessages};
+use std::time::Duration;
 
-pub trait MessageExt {
+pub mod diff;
+mod dump;
+pub mod gm;
+pub mod smf;
+pub mod state;
+pub mod validate;
+
+pub trait MessageExt: Sized {
   fn encode(&self) -> Vec<u8>;
+  fn decode(bytes: &[u8]) -> Option<Self>;
+  fn channel(&self) -> Option<Channel>;
 }
 
+pub const CHANNELS: [Channel; 16] = [
+  Channel::Ch1,
+  Channel::Ch2,
+  Channel::Ch3,
+  Channel::Ch4,
+  Channel::Ch5,
+  Channel::Ch6,
+  Channel::Ch7,
+  Channel::Ch8,
+  Channel::Ch9,
+  Channel::Ch10,
+  Channel::Ch11,
+  Channel::Ch12,
+  Channel::Ch13,
+  Channel::Ch14,
+  Channel::Ch15,
+  Channel::Ch16,
+];
+
 impl MessageExt for Message {
   fn encode(&self) -> Vec<u8> {
     let mut dest = Vec::new();
@@ -18,4 +49,182 @@ impl MessageExt for Message {
     }
     dest
   }
+  // Decodes a single complete message, such as those delivered by a MIDI
+  // input port. System exclusive and other unsupported messages are ignored.
+  fn decode(bytes: &[u8]) -> Option<Self> {
+    let (&status, data) = bytes.split_first()?;
+    let ch = CHANNELS[(status & 0x0f) as usize];
+    Some(match (status >> 4, data) {
+      (0x8, &[note, velocity]) => Message::NoteOff(ch, note, velocity),
+      (0x9, &[note, 0]) => Message::NoteOff(ch, note, 0),
+      (0x9, &[note, velocity]) => Message::NoteOn(ch, note, velocity),
+      (0xa, &[note, pressure]) => Message::PolyphonicPressure(ch, note, pressure),
+      (0xb, &[120, _]) => Message::AllSoundOff(ch),
+      (0xb, &[121, _]) => Message::ResetAllControllers(ch),
+      (0xb, &[122, 0]) => Message::LocalControlOff(ch),
+      (0xb, &[122, 127]) => Message::LocalControlOn(ch),
+      (0xb, &[123, _]) => Message::AllNotesOff(ch),
+      (0xb, &[controller, value]) => Message::ControlChange(ch, controller, value),
+      (0xc, &[program]) => Message::ProgramChange(ch, program),
+      (0xd, &[pressure]) => Message::ChannelPressure(ch, pressure),
+      (0xe, &[lsb, msb]) => Message::PitchBend(ch, ((msb as u16) << 7) | lsb as u16),
+      (0xf, &[]) => match status {
+        0xf8 => Message::TimingClock,
+        0xfa => Message::Start,
+        0xfb => Message::Continue,
+        0xfc => Message::Stop,
+        0xfe => Message::ActiveSensing,
+        0xff => Message::SystemReset,
+        _ => return None,
+      },
+      _ => return None,
+    })
+  }
+  fn channel(&self) -> Option<Channel> {
+    match *self {
+      Message::AllSoundOff(ch)
+      | Message::ResetAllControllers(ch)
+      | Message::LocalControlOff(ch)
+      | Message::LocalControlOn(ch)
+      | Message::AllNotesOff(ch)
+      | Message::NoteOff(ch, _, _)
+      | Message::ProgramChange(ch, _)
+      | Message::ControlChange(ch, _, _)
+      | Message::RPN7(ch, _, _)
+      | Message::RPN14(ch, _, _)
+      | Message::NRPN7(ch, _, _)
+      | Message::NRPN14(ch, _, _)
+      | Message::NoteOn(ch, _, _)
+      | Message::PitchBend(ch, _)
+      | Message::PolyphonicPressure(ch, _, _)
+      | Message::ChannelPressure(ch, _) => Some(ch),
+      _ => None,
+    }
+  }
+}
+
+// Control changes following a Var from 0 to 1, sent only when the value
+// changes and at most once every `resolution`. A change that comes too soon
+// is sent late, with the latest value by then.
+pub fn cc_automation<'a>(
+  channel: Channel,
+  controller: u8,
+  var: Var<'a, f64>,
+  resolution: Duration,
+) -> Stream<'a, Message> {
+  // The raw events run out rather than block once the Var stops changing, so
+  // a deferred change is still sent if there are no more updates.
+  let (events, endless) = var
+    .updates()
+    .map(|level| (level.clamp(0.0, 1.0) * 127.0).round() as u8)
+    .into_parts();
+  let mut now = Duration::from_secs(0);
+  let mut updates = events
+    .filter_map(move |(delay, value)| {
+      now += delay;
+      value.map(|value| (now, value))
+    })
+    .peekable();
+  let mut sent: Option<(Duration, u8)> = None;
+  let changes = std::iter::from_fn(move || loop {
+    let (mut time, mut value) = updates.next()?;
+    if let Some((last_time, last_value)) = sent {
+      let earliest = last_time + resolution;
+      while time < earliest {
+        match updates.peek() {
+          Some(&(next, _)) if next <= earliest => {
+            time = next;
+            value = updates.next().unwrap().1;
+          }
+          _ => time = earliest,
+        }
+      }
+      if value == last_value {
+        continue;
+      }
+    }
+    sent = Some((time, value));
+    return Some((time, Message::ControlChange(channel, controller, value)));
+  });
+  let (changes, _) = Stream::from_timestamps(changes).into_parts();
+  Stream::from_parts(changes, endless)
+}
+
+#[test]
+fn test_cc_automation() {
+  let ms = Duration::from_millis;
+  let ch = Channel::Ch1;
+  let sweep = Var::from_updates(
+    0.0,
+    Stream::from_iter((1..=1000).map(move |i| (ms(1), i as f64 / 1000.0))),
+  );
+  let messages = cc_automation(ch, 74, sweep, ms(10))
+    .timestamps()
+    .collect::<Vec<_>>();
+  assert_eq!(messages[0], (ms(0), Message::ControlChange(ch, 74, 0)));
+  assert_eq!(
+    messages.last().unwrap().1,
+    Message::ControlChange(ch, 74, 127)
+  );
+  assert!(messages.len() <= 101);
+  assert!(messages
+    .windows(2)
+    .all(|pair| pair[1].0 - pair[0].0 >= ms(10)));
+
+  let steps = Var::from_updates(
+    0.5,
+    Stream::from_iter(vec![
+      (ms(5), 0.6),
+      (ms(1), 0.7),
+      (ms(20), 0.7),
+      (ms(20), 0.5),
+    ]),
+  );
+  assert_eq!(
+    cc_automation(ch, 1, steps, ms(10)).collect_timed(),
+    vec![
+      (ms(0), Message::ControlChange(ch, 1, 64)),
+      (ms(10), Message::ControlChange(ch, 1, 89)),
+      (ms(36), Message::ControlChange(ch, 1, 64)),
+    ]
+  );
+
+  // A change held back is still sent once the Var settles for good.
+  let settled = Var::from_updates(0.5, Stream::from_iter(vec![(ms(5), 0.6)]).pending());
+  assert_eq!(
+    cc_automation(ch, 1, settled, ms(10))
+      .take_n(2)
+      .collect_timed(),
+    vec![
+      (ms(0), Message::ControlChange(ch, 1, 64)),
+      (ms(10), Message::ControlChange(ch, 1, 76)),
+    ]
+  );
+}
+
+#[test]
+fn test_decode() {
+  let ch = Channel::Ch3;
+  for message in vec![
+    Message::NoteOn(ch, 60, 100),
+    Message::NoteOff(ch, 60, 64),
+    Message::PolyphonicPressure(ch, 61, 20),
+    Message::ControlChange(ch, 1, 90),
+    Message::AllSoundOff(ch),
+    Message::AllNotesOff(ch),
+    Message::LocalControlOn(ch),
+    Message::ProgramChange(ch, 40),
+    Message::ChannelPressure(ch, 3),
+    Message::PitchBend(ch, 0x2345),
+    Message::ActiveSensing,
+    Message::Start,
+  ] {
+    assert_eq!(Message::decode(&message.encode()), Some(message));
+  }
+  assert_eq!(
+    Message::decode(&[0x91, 60, 0]),
+    Some(Message::NoteOff(Channel::Ch2, 60, 0))
+  );
+  assert_eq!(Message::decode(&[0x91, 60]), None);
+  assert_eq!(Message::decode(&[]), None);
 }
@@ -0,0 +1,193 @@
+use super::{Channel, Message};
+use crate::stream::Stream;
+use crate::theory::Note;
+use std::fmt;
+use std::time::Duration;
+
+// A note as played: when it started and how long it sounded, from the start
+// of the stream. Notes never released last until the end of the stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Played {
+  pub channel: Channel,
+  pub note: u8,
+  pub velocity: u8,
+  pub start: Duration,
+  pub length: Duration,
+}
+
+// A difference between two performances, found by diff.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Change {
+  Added(Played),
+  Removed(Played),
+  // The same note on the same channel, moved or lengthened or shortened.
+  Retimed(Played, Played),
+}
+
+impl Change {
+  // Start of the note, as played after the change if it still is.
+  pub fn time(&self) -> Duration {
+    match self {
+      Self::Added(played) | Self::Removed(played) | Self::Retimed(_, played) => played.start,
+    }
+  }
+}
+
+impl fmt::Display for Played {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{:?} {} at {:.3}s for {:.3}s",
+      self.channel,
+      Note::from_midi(self.note),
+      self.start.as_secs_f64(),
+      self.length.as_secs_f64()
+    )
+  }
+}
+
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Added(played) => write!(f, "+ {}", played),
+      Self::Removed(played) => write!(f, "- {}", played),
+      Self::Retimed(before, after) => write!(f, "~ {} -> {}", before, after),
+    }
+  }
+}
+
+// The notes of a finite stream, in order of starting. A note on with zero
+// velocity ends a note, like a note off.
+pub fn played(messages: Stream<Message>) -> Vec<Played> {
+  let mut notes: Vec<Played> = Vec::new();
+  let mut sounding: Vec<usize> = Vec::new();
+  let mut end = Duration::from_secs(0);
+  for (time, message) in messages.timestamps() {
+    end = time;
+    match message {
+      Message::NoteOn(channel, note, 0) | Message::NoteOff(channel, note, _) => {
+        let position = sounding
+          .iter()
+          .position(|&i| notes[i].channel == channel && notes[i].note == note);
+        if let Some(position) = position {
+          let i = sounding.remove(position);
+          notes[i].length = time - notes[i].start;
+        }
+      }
+      Message::NoteOn(channel, note, velocity) => {
+        sounding.push(notes.len());
+        notes.push(Played {
+          channel,
+          note,
+          velocity,
+          start: time,
+          length: Duration::from_secs(0),
+        });
+      }
+      _ => (),
+    }
+  }
+  for i in sounding {
+    notes[i].length = end - notes[i].start;
+  }
+  notes
+}
+
+// Compares two finite performances note by note. Notes match if they are on
+// the same channel and pitch and their starts and lengths differ by at most
+// the tolerance; unmatched notes within `window` of each other are reported
+// as retimed, and the rest as added or removed. Velocities are ignored.
+pub fn diff(
+  before: Stream<Message>,
+  after: Stream<Message>,
+  tolerance: Duration,
+  window: Duration,
+) -> Vec<Change> {
+  let distance = |a: Duration, b: Duration| a.max(b) - a.min(b);
+  let same_note = |a: &Played, b: &Played| a.channel == b.channel && a.note == b.note;
+  let mut before = played(before);
+  let mut after = played(after);
+  before.retain(|old| {
+    let matching = after.iter().position(|new| {
+      same_note(old, new)
+        && distance(old.start, new.start) <= tolerance
+        && distance(old.length, new.length) <= tolerance
+    });
+    match matching {
+      Some(i) => {
+        after.remove(i);
+        false
+      }
+      None => true,
+    }
+  });
+  let mut changes = Vec::new();
+  for old in before {
+    let nearest = after
+      .iter()
+      .enumerate()
+      .filter(|(_, new)| same_note(&old, new) && distance(old.start, new.start) <= window)
+      .min_by_key(|(_, new)| distance(old.start, new.start))
+      .map(|(i, _)| i);
+    match nearest {
+      Some(i) => changes.push(Change::Retimed(old, after.remove(i))),
+      None => changes.push(Change::Removed(old)),
+    }
+  }
+  changes.extend(after.into_iter().map(Change::Added));
+  changes.sort_by_key(|change| change.time());
+  changes
+}
+
+#[test]
+fn test_diff() {
+  let ms = Duration::from_millis;
+  let (ch1, ch2) = (Channel::Ch1, Channel::Ch2);
+  let before = Stream::from_timestamps(vec![
+    (ms(0), Message::NoteOn(ch1, 60, 100)),
+    (ms(0), Message::NoteOn(ch2, 48, 100)),
+    (ms(500), Message::NoteOff(ch1, 60, 0)),
+    (ms(500), Message::NoteOn(ch1, 62, 100)),
+    (ms(1000), Message::NoteOff(ch1, 62, 0)),
+    (ms(1000), Message::NoteOn(ch1, 64, 100)),
+    (ms(1500), Message::NoteOff(ch1, 64, 0)),
+    (ms(2000), Message::NoteOff(ch2, 48, 0)),
+  ]);
+  let after = Stream::from_timestamps(vec![
+    (ms(0), Message::NoteOn(ch1, 60, 90)),
+    (ms(0), Message::NoteOn(ch2, 48, 100)),
+    (ms(501), Message::NoteOff(ch1, 60, 0)),
+    (ms(600), Message::NoteOn(ch1, 62, 100)),
+    (ms(1000), Message::NoteOff(ch1, 62, 0)),
+    (ms(1500), Message::NoteOn(ch1, 65, 100)),
+    (ms(1750), Message::NoteOff(ch1, 65, 0)),
+  ]);
+  let changes = diff(before, after, ms(5), ms(250));
+  let released = played(Stream::from_timestamps(vec![
+    (ms(0), Message::NoteOn(ch1, 60, 100)),
+    (ms(250), Message::NoteOn(ch1, 60, 0)),
+    (ms(500), Message::NoteOn(ch1, 62, 100)),
+  ]));
+  assert_eq!(released.len(), 2);
+  assert_eq!(released[0].length, ms(250));
+  let played = |channel, note, start, length| Played {
+    channel,
+    note,
+    velocity: 100,
+    start: ms(start),
+    length: ms(length),
+  };
+  assert_eq!(
+    changes,
+    vec![
+      Change::Retimed(played(ch2, 48, 0, 2000), played(ch2, 48, 0, 1750)),
+      Change::Retimed(played(ch1, 62, 500, 500), played(ch1, 62, 600, 400)),
+      Change::Removed(played(ch1, 64, 1000, 500)),
+      Change::Added(played(ch1, 65, 1500, 250)),
+    ]
+  );
+  assert_eq!(
+    changes[2].to_string(),
+    format!("- Ch1 {} at 1.000s for 0.500s", Note::from_midi(64))
+  );
+}
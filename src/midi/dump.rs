@@ -0,0 +1,182 @@
+use super::{Message, CHANNELS};
+use crate::stream::Stream;
+use ::midi::Manufacturer;
+use std::convert::TryInto;
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+// Rendered message streams saved exactly as they were generated, so they can
+// be played again without running the generators. Each record is a delay in
+// nanoseconds (8 bytes, big endian), the length of the message that follows
+// (4 bytes, big endian) and the message: a byte saying which kind it is, then
+// its fields. Messages are saved as values rather than as MIDI bytes, so that
+// those MIDI can't tell apart, such as a note on with no velocity and a note
+// off, come back as they were.
+
+const MAGIC: &[u8] = b"avrildump2";
+
+impl<'a> Stream<'a, Message> {
+  // Writes a finite stream to a file. Never returns if the stream is
+  // infinite.
+  pub fn record_to<P: AsRef<Path>>(self, path: P) -> Result<(), Box<dyn Error>> {
+    std::fs::write(path, dump(self))?;
+    Ok(())
+  }
+  // Reads a stream written by record_to.
+  pub fn replay_from<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+    Ok(Self::from_iter(undump(&std::fs::read(path)?)?))
+  }
+}
+
+fn dump(messages: Stream<Message>) -> Vec<u8> {
+  let mut file = MAGIC.to_vec();
+  for (delay, message) in messages {
+    let bytes = serialize(&message);
+    file.extend_from_slice(&(delay.as_nanos() as u64).to_be_bytes());
+    file.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    file.extend_from_slice(&bytes);
+  }
+  file
+}
+
+fn undump(file: &[u8]) -> Result<Vec<(Duration, Message)>, Box<dyn Error>> {
+  let mut rest = file
+    .strip_prefix(MAGIC)
+    .ok_or("not an avril stream recording")?;
+  let mut events = Vec::new();
+  while !rest.is_empty() {
+    if rest.len() < 12 {
+      return Err("unexpected end of file".into());
+    }
+    let delay = Duration::from_nanos(u64::from_be_bytes(rest[..8].try_into().unwrap()));
+    let length = u32::from_be_bytes(rest[8..12].try_into().unwrap()) as usize;
+    if rest.len() - 12 < length {
+      return Err("unexpected end of file".into());
+    }
+    let message = deserialize(&rest[12..12 + length]).ok_or("unrecognised message")?;
+    events.push((delay, message));
+    rest = &rest[12 + length..];
+  }
+  Ok(events)
+}
+
+fn serialize(message: &Message) -> Vec<u8> {
+  let u14 = |value: u16| value.to_be_bytes();
+  match *message {
+    Message::Start => vec![0],
+    Message::TimingClock => vec![1],
+    Message::Continue => vec![2],
+    Message::Stop => vec![3],
+    Message::ActiveSensing => vec![4],
+    Message::SystemReset => vec![5],
+    Message::AllSoundOff(ch) => vec![6, ch as u8],
+    Message::ResetAllControllers(ch) => vec![7, ch as u8],
+    Message::LocalControlOff(ch) => vec![8, ch as u8],
+    Message::LocalControlOn(ch) => vec![9, ch as u8],
+    Message::AllNotesOff(ch) => vec![10, ch as u8],
+    Message::NoteOff(ch, note, velocity) => vec![11, ch as u8, note, velocity],
+    Message::ProgramChange(ch, program) => vec![12, ch as u8, program],
+    Message::ControlChange(ch, controller, value) => vec![13, ch as u8, controller, value],
+    Message::RPN7(ch, param, value) => [&[14, ch as u8][..], &u14(param), &[value]].concat(),
+    Message::RPN14(ch, param, value) => [&[15, ch as u8][..], &u14(param), &u14(value)].concat(),
+    Message::NRPN7(ch, param, value) => [&[16, ch as u8][..], &u14(param), &[value]].concat(),
+    Message::NRPN14(ch, param, value) => [&[17, ch as u8][..], &u14(param), &u14(value)].concat(),
+    Message::SysEx(manufacturer, ref data) => {
+      let mut bytes = match manufacturer {
+        Manufacturer::OneByte(id) => vec![18, 1, id],
+        Manufacturer::ThreeByte(a, b, c) => vec![18, 3, a, b, c],
+      };
+      bytes.extend_from_slice(data);
+      bytes
+    }
+    Message::NoteOn(ch, note, velocity) => vec![19, ch as u8, note, velocity],
+    Message::PitchBend(ch, value) => [&[20, ch as u8][..], &u14(value)].concat(),
+    Message::PolyphonicPressure(ch, note, pressure) => vec![21, ch as u8, note, pressure],
+    Message::ChannelPressure(ch, pressure) => vec![22, ch as u8, pressure],
+  }
+}
+
+fn deserialize(bytes: &[u8]) -> Option<Message> {
+  let channel = |index: u8| CHANNELS.get(index as usize).copied();
+  let u14 = |high: u8, low: u8| u16::from_be_bytes([high, low]);
+  Some(match *bytes {
+    [0] => Message::Start,
+    [1] => Message::TimingClock,
+    [2] => Message::Continue,
+    [3] => Message::Stop,
+    [4] => Message::ActiveSensing,
+    [5] => Message::SystemReset,
+    [6, ch] => Message::AllSoundOff(channel(ch)?),
+    [7, ch] => Message::ResetAllControllers(channel(ch)?),
+    [8, ch] => Message::LocalControlOff(channel(ch)?),
+    [9, ch] => Message::LocalControlOn(channel(ch)?),
+    [10, ch] => Message::AllNotesOff(channel(ch)?),
+    [11, ch, note, velocity] => Message::NoteOff(channel(ch)?, note, velocity),
+    [12, ch, program] => Message::ProgramChange(channel(ch)?, program),
+    [13, ch, controller, value] => Message::ControlChange(channel(ch)?, controller, value),
+    [14, ch, p1, p2, value] => Message::RPN7(channel(ch)?, u14(p1, p2), value),
+    [15, ch, p1, p2, v1, v2] => Message::RPN14(channel(ch)?, u14(p1, p2), u14(v1, v2)),
+    [16, ch, p1, p2, value] => Message::NRPN7(channel(ch)?, u14(p1, p2), value),
+    [17, ch, p1, p2, v1, v2] => Message::NRPN14(channel(ch)?, u14(p1, p2), u14(v1, v2)),
+    [18, 1, id, ref data @ ..] => Message::SysEx(Manufacturer::OneByte(id), data.to_vec()),
+    [18, 3, a, b, c, ref data @ ..] => {
+      Message::SysEx(Manufacturer::ThreeByte(a, b, c), data.to_vec())
+    }
+    [19, ch, note, velocity] => Message::NoteOn(channel(ch)?, note, velocity),
+    [20, ch, high, low] => Message::PitchBend(channel(ch)?, u14(high, low)),
+    [21, ch, note, pressure] => Message::PolyphonicPressure(channel(ch)?, note, pressure),
+    [22, ch, pressure] => Message::ChannelPressure(channel(ch)?, pressure),
+    _ => return None,
+  })
+}
+
+#[test]
+fn test_record() {
+  use super::Channel;
+  let ms = Duration::from_millis;
+  let ch = Channel::Ch1;
+  let messages = vec![
+    (ms(0), Message::ProgramChange(ch, 5)),
+    (ms(0), Message::NoteOn(ch, 60, 100)),
+    (ms(250), Message::PitchBend(ch, 0x2345)),
+    (ms(250), Message::NoteOff(ch, 60, 64)),
+    (ms(10), Message::ActiveSensing),
+  ];
+  let path = std::env::temp_dir().join(format!("avril-test-record-{}", std::process::id()));
+  Stream::from_iter(messages.clone())
+    .record_to(&path)
+    .unwrap();
+  let replayed = Stream::replay_from(&path).unwrap().collect_timed();
+  std::fs::remove_file(&path).unwrap();
+  assert_eq!(replayed, messages);
+
+  // Messages that MIDI can't tell apart from others, or that it sends as
+  // several, come back as they were.
+  let ambiguous = vec![
+    (ms(0), Message::NoteOn(ch, 60, 0)),
+    (ms(0), Message::NoteOff(ch, 60, 0)),
+    (ms(5), Message::ControlChange(ch, 120, 0)),
+    (ms(0), Message::ControlChange(ch, 121, 0)),
+    (ms(0), Message::ControlChange(ch, 122, 127)),
+    (ms(0), Message::ControlChange(ch, 123, 0)),
+    (ms(0), Message::AllNotesOff(Channel::Ch16)),
+    (ms(5), Message::RPN7(ch, 0, 2)),
+    (ms(0), Message::RPN14(ch, 0x1234, 0x2345)),
+    (ms(0), Message::NRPN7(ch, 0x3fff, 1)),
+    (ms(0), Message::NRPN14(ch, 1, 0x3fff)),
+    (
+      ms(5),
+      Message::SysEx(Manufacturer::OneByte(0x43), vec![1, 2, 3]),
+    ),
+    (
+      ms(0),
+      Message::SysEx(Manufacturer::ThreeByte(0, 0x20, 0x29), vec![7; 300]),
+    ),
+    (ms(0), Message::LocalControlOn(ch)),
+  ];
+  let replayed = undump(&dump(Stream::from_iter(ambiguous.clone()))).unwrap();
+  assert_eq!(replayed, ambiguous);
+  assert!(undump(b"not a recording").is_err());
+  assert!(undump(&dump(Stream::immediate(Message::Start))[..12]).is_err());
+}
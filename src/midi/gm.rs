@@ -0,0 +1,218 @@
+use super::Channel;
+
+// General MIDI reserves channel 10 for percussion.
+pub const PERCUSSION_CHANNEL: Channel = Channel::Ch10;
+
+// General MIDI level 1 instrument programs, in patch number order.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub enum GmProgram {
+  AcousticGrandPiano,
+  BrightAcousticPiano,
+  ElectricGrandPiano,
+  HonkyTonkPiano,
+  ElectricPiano1,
+  ElectricPiano2,
+  Harpsichord,
+  Clavinet,
+  Celesta,
+  Glockenspiel,
+  MusicBox,
+  Vibraphone,
+  Marimba,
+  Xylophone,
+  TubularBells,
+  Dulcimer,
+  DrawbarOrgan,
+  PercussiveOrgan,
+  RockOrgan,
+  ChurchOrgan,
+  ReedOrgan,
+  Accordion,
+  Harmonica,
+  TangoAccordion,
+  AcousticGuitarNylon,
+  AcousticGuitarSteel,
+  ElectricGuitarJazz,
+  ElectricGuitarClean,
+  ElectricGuitarMuted,
+  OverdrivenGuitar,
+  DistortionGuitar,
+  GuitarHarmonics,
+  AcousticBass,
+  ElectricBassFinger,
+  ElectricBassPick,
+  FretlessBass,
+  SlapBass1,
+  SlapBass2,
+  SynthBass1,
+  SynthBass2,
+  Violin,
+  Viola,
+  Cello,
+  Contrabass,
+  TremoloStrings,
+  PizzicatoStrings,
+  OrchestralHarp,
+  Timpani,
+  StringEnsemble1,
+  StringEnsemble2,
+  SynthStrings1,
+  SynthStrings2,
+  ChoirAahs,
+  VoiceOohs,
+  SynthVoice,
+  OrchestraHit,
+  Trumpet,
+  Trombone,
+  Tuba,
+  MutedTrumpet,
+  FrenchHorn,
+  BrassSection,
+  SynthBrass1,
+  SynthBrass2,
+  SopranoSax,
+  AltoSax,
+  TenorSax,
+  BaritoneSax,
+  Oboe,
+  EnglishHorn,
+  Bassoon,
+  Clarinet,
+  Piccolo,
+  Flute,
+  Recorder,
+  PanFlute,
+  BlownBottle,
+  Shakuhachi,
+  Whistle,
+  Ocarina,
+  Lead1Square,
+  Lead2Sawtooth,
+  Lead3Calliope,
+  Lead4Chiff,
+  Lead5Charang,
+  Lead6Voice,
+  Lead7Fifths,
+  Lead8BassAndLead,
+  Pad1NewAge,
+  Pad2Warm,
+  Pad3Polysynth,
+  Pad4Choir,
+  Pad5Bowed,
+  Pad6Metallic,
+  Pad7Halo,
+  Pad8Sweep,
+  Fx1Rain,
+  Fx2Soundtrack,
+  Fx3Crystal,
+  Fx4Atmosphere,
+  Fx5Brightness,
+  Fx6Goblins,
+  Fx7Echoes,
+  Fx8SciFi,
+  Sitar,
+  Banjo,
+  Shamisen,
+  Koto,
+  Kalimba,
+  Bagpipe,
+  Fiddle,
+  Shanai,
+  TinkleBell,
+  Agogo,
+  SteelDrums,
+  Woodblock,
+  TaikoDrum,
+  MelodicTom,
+  SynthDrum,
+  ReverseCymbal,
+  GuitarFretNoise,
+  BreathNoise,
+  Seashore,
+  BirdTweet,
+  TelephoneRing,
+  Helicopter,
+  Applause,
+  Gunshot,
+}
+
+impl GmProgram {
+  pub fn number(self) -> u8 {
+    self as u8
+  }
+}
+
+impl From<GmProgram> for u8 {
+  fn from(program: GmProgram) -> u8 {
+    program.number()
+  }
+}
+
+#[test]
+fn test_gm_program() {
+  assert_eq!(GmProgram::AcousticGrandPiano.number(), 0);
+  assert_eq!(GmProgram::Violin.number(), 40);
+  assert_eq!(u8::from(GmProgram::Flute), 73);
+  assert_eq!(GmProgram::Gunshot.number(), 127);
+}
+
+// Note numbers of the General MIDI percussion key map.
+pub mod drums {
+  pub const ACOUSTIC_BASS_DRUM: u8 = 35;
+  pub const BASS_DRUM_1: u8 = 36;
+  pub const SIDE_STICK: u8 = 37;
+  pub const ACOUSTIC_SNARE: u8 = 38;
+  pub const HAND_CLAP: u8 = 39;
+  pub const ELECTRIC_SNARE: u8 = 40;
+  pub const LOW_FLOOR_TOM: u8 = 41;
+  pub const CLOSED_HI_HAT: u8 = 42;
+  pub const HIGH_FLOOR_TOM: u8 = 43;
+  pub const PEDAL_HI_HAT: u8 = 44;
+  pub const LOW_TOM: u8 = 45;
+  pub const OPEN_HI_HAT: u8 = 46;
+  pub const LOW_MID_TOM: u8 = 47;
+  pub const HI_MID_TOM: u8 = 48;
+  pub const CRASH_CYMBAL_1: u8 = 49;
+  pub const HIGH_TOM: u8 = 50;
+  pub const RIDE_CYMBAL_1: u8 = 51;
+  pub const CHINESE_CYMBAL: u8 = 52;
+  pub const RIDE_BELL: u8 = 53;
+  pub const TAMBOURINE: u8 = 54;
+  pub const SPLASH_CYMBAL: u8 = 55;
+  pub const COWBELL: u8 = 56;
+  pub const CRASH_CYMBAL_2: u8 = 57;
+  pub const VIBRASLAP: u8 = 58;
+  pub const RIDE_CYMBAL_2: u8 = 59;
+  pub const HI_BONGO: u8 = 60;
+  pub const LOW_BONGO: u8 = 61;
+  pub const MUTE_HI_CONGA: u8 = 62;
+  pub const OPEN_HI_CONGA: u8 = 63;
+  pub const LOW_CONGA: u8 = 64;
+  pub const HIGH_TIMBALE: u8 = 65;
+  pub const LOW_TIMBALE: u8 = 66;
+  pub const HIGH_AGOGO: u8 = 67;
+  pub const LOW_AGOGO: u8 = 68;
+  pub const CABASA: u8 = 69;
+  pub const MARACAS: u8 = 70;
+  pub const SHORT_WHISTLE: u8 = 71;
+  pub const LONG_WHISTLE: u8 = 72;
+  pub const SHORT_GUIRO: u8 = 73;
+  pub const LONG_GUIRO: u8 = 74;
+  pub const CLAVES: u8 = 75;
+  pub const HI_WOOD_BLOCK: u8 = 76;
+  pub const LOW_WOOD_BLOCK: u8 = 77;
+  pub const MUTE_CUICA: u8 = 78;
+  pub const OPEN_CUICA: u8 = 79;
+  pub const MUTE_TRIANGLE: u8 = 80;
+  pub const OPEN_TRIANGLE: u8 = 81;
+
+  // Common shorthands.
+  pub const KICK: u8 = BASS_DRUM_1;
+  pub const SNARE: u8 = ACOUSTIC_SNARE;
+  pub const CLOSED_HAT: u8 = CLOSED_HI_HAT;
+  pub const PEDAL_HAT: u8 = PEDAL_HI_HAT;
+  pub const OPEN_HAT: u8 = OPEN_HI_HAT;
+  pub const CRASH: u8 = CRASH_CYMBAL_1;
+  pub const RIDE: u8 = RIDE_CYMBAL_1;
+}
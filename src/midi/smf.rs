@@ -0,0 +1,414 @@
+use super::{Message, MessageExt};
+use crate::stream::Stream;
+use crate::theory::NoteInKey;
+use crate::var::Var;
+use std::error::Error;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+  Message(Message),
+  TrackName(String),
+  Text(String),
+  Lyric(String),
+}
+
+// A track read from a file.
+pub type Track = Stream<'static, Event>;
+
+// Standard MIDI File reader and writer, with a fixed tempo.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Smf {
+  pub ticks_per_quarter: u16,
+  // Duration of a quarter note.
+  pub quarter: Duration,
+}
+
+impl Smf {
+  pub fn new(quarter: Duration) -> Self {
+    Self {
+      ticks_per_quarter: 480,
+      quarter,
+    }
+  }
+  fn ticks(&self, time: Duration) -> u32 {
+    let ticks = time.as_secs_f64() / self.quarter.as_secs_f64() * self.ticks_per_quarter as f64;
+    ticks.round() as u32
+  }
+  // Writes a format 1 file with a track for each stream, which must be
+  // finite. The tempo is set at the start of the first track. System common
+  // and real time messages, which have no place in a file, are left out.
+  pub fn write<'a, I>(&self, tracks: I) -> Vec<u8>
+  where
+    I: IntoIterator<Item = Stream<'a, Event>>,
+  {
+    let mut chunks = Vec::new();
+    let mut count: u16 = 0;
+    for track in tracks {
+      let mut data = Vec::new();
+      if count == 0 {
+        let tempo = self.quarter.as_micros() as u32;
+        write_vlq(&mut data, 0);
+        data.extend_from_slice(&[0xff, 0x51, 0x03]);
+        data.extend_from_slice(&tempo.to_be_bytes()[1..]);
+      }
+      let mut written_ticks = 0;
+      for (now, event) in track.timestamps() {
+        let ticks = self.ticks(now);
+        let delta = ticks - written_ticks;
+        let mut write_meta = |kind: u8, text: &str| {
+          write_vlq(&mut data, delta);
+          data.extend_from_slice(&[0xff, kind]);
+          write_vlq(&mut data, text.len() as u32);
+          data.extend_from_slice(text.as_bytes());
+        };
+        match event {
+          Event::Message(message) => {
+            let bytes = message.encode();
+            match bytes[0] {
+              // The length of a system exclusive event follows its status.
+              0xf0 => {
+                write_vlq(&mut data, delta);
+                data.push(0xf0);
+                write_vlq(&mut data, bytes.len() as u32 - 1);
+                data.extend_from_slice(&bytes[1..]);
+              }
+              0xf1..=0xff => continue,
+              // Messages such as RPNs encode as several events at once.
+              _ => {
+                for (i, &byte) in bytes.iter().enumerate() {
+                  if byte >= 0x80 {
+                    write_vlq(&mut data, if i == 0 { delta } else { 0 });
+                  }
+                  data.push(byte);
+                }
+              }
+            }
+          }
+          Event::TrackName(name) => write_meta(0x03, &name),
+          Event::Text(text) => write_meta(0x01, &text),
+          Event::Lyric(lyric) => write_meta(0x05, &lyric),
+        }
+        written_ticks = ticks;
+      }
+      write_vlq(&mut data, 0);
+      data.extend_from_slice(&[0xff, 0x2f, 0x00]);
+      chunks.extend_from_slice(b"MTrk");
+      chunks.extend_from_slice(&(data.len() as u32).to_be_bytes());
+      chunks.extend_from_slice(&data);
+      count += 1;
+    }
+    let mut file = Vec::new();
+    file.extend_from_slice(b"MThd");
+    file.extend_from_slice(&6u32.to_be_bytes());
+    file.extend_from_slice(&1u16.to_be_bytes());
+    file.extend_from_slice(&count.to_be_bytes());
+    file.extend_from_slice(&self.ticks_per_quarter.to_be_bytes());
+    file.extend_from_slice(&chunks);
+    file
+  }
+  // Reads a format 0 or 1 file, returning a stream for each track. Only the
+  // first tempo is used; the default of 120 beats per minute applies if there
+  // is none. System exclusive and unrecognised meta events are skipped.
+  pub fn read(bytes: &[u8]) -> Result<(Self, Vec<Track>), Box<dyn Error>> {
+    let mut file = Reader(bytes);
+    if file.take(4)? != b"MThd" || file.u32()? != 6 {
+      return Err("not a standard MIDI file".into());
+    }
+    let format = file.u16()?;
+    let count = file.u16()?;
+    let ticks_per_quarter = file.u16()?;
+    if format > 1 {
+      return Err(format!("unsupported SMF format {}", format).into());
+    }
+    if ticks_per_quarter & 0x8000 != 0 {
+      return Err("SMPTE time division is not supported".into());
+    }
+    let mut tempo = None;
+    let mut tracks = Vec::new();
+    for _ in 0..count {
+      if file.take(4)? != b"MTrk" {
+        return Err("expected a track chunk".into());
+      }
+      let length = file.u32()? as usize;
+      let mut track = Reader(file.take(length)?);
+      let mut events = Vec::new();
+      let mut ticks: u64 = 0;
+      let mut running_status = None;
+      while !track.0.is_empty() {
+        ticks += track.vlq()? as u64;
+        let status = match *track.0.first().ok_or("unexpected end of track")? {
+          byte if byte >= 0x80 => track.byte()?,
+          _ => running_status.ok_or("data byte without a status byte")?,
+        };
+        match status {
+          0xff => {
+            let kind = track.byte()?;
+            let length = track.vlq()? as usize;
+            let data = track.take(length)?;
+            let text = || String::from_utf8_lossy(data).into_owned();
+            match kind {
+              0x01 => events.push((ticks, Event::Text(text()))),
+              0x03 => events.push((ticks, Event::TrackName(text()))),
+              0x05 => events.push((ticks, Event::Lyric(text()))),
+              0x2f => break,
+              0x51 if length == 3 && tempo.is_none() => {
+                tempo = Some(u32::from_be_bytes([0, data[0], data[1], data[2]]));
+              }
+              _ => (),
+            }
+          }
+          0xf0 | 0xf7 => {
+            let length = track.vlq()? as usize;
+            track.take(length)?;
+          }
+          0x80..=0xef => {
+            running_status = Some(status);
+            let length = match status >> 4 {
+              0xc | 0xd => 1,
+              _ => 2,
+            };
+            let mut raw = vec![status];
+            raw.extend_from_slice(track.take(length)?);
+            if let Some(message) = Message::decode(&raw) {
+              events.push((ticks, Event::Message(message)));
+            }
+          }
+          _ => return Err(format!("unexpected status byte {:#x}", status).into()),
+        }
+      }
+      tracks.push(events);
+    }
+    let smf = Self {
+      ticks_per_quarter,
+      quarter: Duration::from_micros(tempo.unwrap_or(500_000) as u64),
+    };
+    let quarter = smf.quarter.as_nanos() as u64;
+    let streams = tracks
+      .into_iter()
+      .map(|events| {
+        Stream::from_timestamps(events.into_iter().map(move |(ticks, event)| {
+          let nanos = ticks * quarter / ticks_per_quarter as u64;
+          (Duration::from_nanos(nanos), event)
+        }))
+      })
+      .collect();
+    Ok((smf, streams))
+  }
+}
+
+// Cursor over the bytes of a file being read.
+struct Reader<'b>(&'b [u8]);
+
+impl<'b> Reader<'b> {
+  fn take(&mut self, count: usize) -> Result<&'b [u8], Box<dyn Error>> {
+    if count > self.0.len() {
+      return Err("unexpected end of file".into());
+    }
+    let (taken, rest) = self.0.split_at(count);
+    self.0 = rest;
+    Ok(taken)
+  }
+  fn byte(&mut self) -> Result<u8, Box<dyn Error>> {
+    Ok(self.take(1)?[0])
+  }
+  fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+    let bytes = self.take(2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+  }
+  fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+  fn vlq(&mut self) -> Result<u32, Box<dyn Error>> {
+    let mut value = 0;
+    for _ in 0..4 {
+      let byte = self.byte()?;
+      value = (value << 7) | (byte & 0x7f) as u32;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+    Err("variable-length quantity is too long".into())
+  }
+}
+
+// Variable-length quantity, seven bits per byte, most significant first.
+fn write_vlq(dest: &mut Vec<u8>, value: u32) {
+  let mut bytes = vec![(value & 0x7f) as u8];
+  let mut value = value >> 7;
+  while value > 0 {
+    bytes.push((value & 0x7f) as u8 | 0x80);
+    value >>= 7;
+  }
+  bytes.reverse();
+  dest.extend_from_slice(&bytes);
+}
+
+// What to show as the lyric of each note.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Lyrics {
+  // The same syllable for every note.
+  Placeholder(String),
+  Solfege,
+  Degree,
+}
+
+// Lyric events naming each note of a melody, to be merged into the track
+// holding the melody's notes. Merge the lyrics first so that each lyric
+// precedes its note.
+pub fn lyrics<'a>(notes: Var<'a, NoteInKey<'a>>, style: Lyrics) -> Stream<'a, Event> {
+  notes.updates().map(move |note| {
+    Event::Lyric(match &style {
+      Lyrics::Placeholder(syllable) => syllable.clone(),
+      Lyrics::Solfege => note.solfege().to_string(),
+      Lyrics::Degree => note.degree().to_string(),
+    })
+  })
+}
+
+#[test]
+fn test_write_vlq() {
+  let vlq = |value| {
+    let mut dest = Vec::new();
+    write_vlq(&mut dest, value);
+    dest
+  };
+  assert_eq!(vlq(0), vec![0x00]);
+  assert_eq!(vlq(0x7f), vec![0x7f]);
+  assert_eq!(vlq(0x80), vec![0x81, 0x00]);
+  assert_eq!(vlq(0x3fff), vec![0xff, 0x7f]);
+  assert_eq!(vlq(0x0fffffff), vec![0xff, 0xff, 0xff, 0x7f]);
+}
+
+#[test]
+fn test_write() {
+  use super::Channel;
+  use crate::theory::{Key, Note, PitchClass};
+  use ::midi::Manufacturer;
+  let ms = Duration::from_millis;
+  let key = Key::major(Note::new(PitchClass::C, 4));
+  let melody = Var::from_updates(key.at(0), Stream::from_iter(vec![(ms(500), key.at(4))]));
+  let ch = Channel::Ch1;
+  let notes = Stream::from_iter(vec![
+    (ms(0), Event::Message(Message::NoteOn(ch, 60, 64))),
+    (ms(0), Event::Message(Message::ActiveSensing)),
+    (ms(500), Event::Message(Message::NoteOff(ch, 60, 64))),
+    (ms(0), Event::Message(Message::NoteOn(ch, 67, 64))),
+  ]);
+  let track = Stream::immediate(Event::TrackName("melody".to_string()))
+    .chain(lyrics(melody, Lyrics::Solfege).merge(notes));
+  let file = Smf::new(ms(500)).write(vec![track]);
+  assert_eq!(
+    &file[..14],
+    &[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 1, 0x01, 0xe0]
+  );
+  assert_eq!(&file[14..18], b"MTrk");
+  assert_eq!(
+    &file[22..],
+    &[
+      0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20, // tempo
+      0x00, 0xff, 0x03, 6, b'm', b'e', b'l', b'o', b'd', b'y', // track name
+      0x00, 0xff, 0x05, 2, b'd', b'o', // lyric
+      0x00, 0x90, 60, 64, // note on
+      0x83, 0x60, 0xff, 0x05, 3, b's', b'o', b'l', // lyric after 480 ticks
+      0x00, 0x80, 60, 64, // note off
+      0x00, 0x90, 67, 64, // note on
+      0x00, 0xff, 0x2f, 0x00, // end of track
+    ][..]
+  );
+  assert_eq!(
+    u32::from_be_bytes([file[18], file[19], file[20], file[21]]) as usize,
+    file.len() - 22
+  );
+
+  let sysex = Stream::from_iter(vec![
+    (ms(0), Event::Message(Message::ActiveSensing)),
+    (
+      ms(500),
+      Event::Message(Message::SysEx(Manufacturer::OneByte(0x43), vec![1, 2])),
+    ),
+  ]);
+  let file = Smf::new(ms(500)).write(vec![sysex]);
+  assert_eq!(
+    &file[29..],
+    &[
+      0x83, 0x60, 0xf0, 4, 0x43, 1, 2, 0xf7, // system exclusive after 480 ticks
+      0x00, 0xff, 0x2f, 0x00, // end of track
+    ][..]
+  );
+}
+
+#[test]
+fn test_read() {
+  use super::Channel;
+  let ms = Duration::from_millis;
+  let ch = Channel::Ch2;
+  let track = || {
+    Stream::from_iter(vec![
+      (ms(0), Event::TrackName("bass".to_string())),
+      (ms(0), Event::Message(Message::ProgramChange(ch, 33))),
+      (ms(0), Event::Message(Message::NoteOn(ch, 40, 90))),
+      (ms(250), Event::Lyric("la".to_string())),
+      (ms(0), Event::Message(Message::NoteOff(ch, 40, 64))),
+      (ms(750), Event::Message(Message::PitchBend(ch, 0x2100))),
+    ])
+  };
+  let smf = Smf::new(ms(600));
+  let (read, tracks) = Smf::read(&smf.write(vec![track(), Stream::empty()])).unwrap();
+  assert_eq!(read, smf);
+  assert_eq!(tracks.len(), 2);
+  let mut tracks = tracks.into_iter();
+  let first = tracks.next().unwrap().collect_timed();
+  assert_eq!(first, track().collect_timed());
+  assert_eq!(tracks.next().unwrap().count_events(), 0);
+
+  // Messages left out of the file don't move the events after them.
+  let live = || {
+    Stream::from_iter(vec![
+      (ms(0), Event::Message(Message::NoteOn(ch, 40, 90))),
+      (ms(250), Event::Message(Message::ActiveSensing)),
+      (ms(250), Event::Message(Message::NoteOff(ch, 40, 64))),
+    ])
+  };
+  let (_, tracks) = Smf::read(&smf.write(vec![live()])).unwrap();
+  assert_eq!(
+    tracks.into_iter().next().unwrap().collect_timed(),
+    live()
+      .filter(|e| *e != Event::Message(Message::ActiveSensing))
+      .collect_timed()
+  );
+
+  // Running status, a note on with zero velocity and an unknown meta event.
+  let mut file = b"MThd\x00\x00\x00\x06\x00\x00\x00\x01\x00\x60MTrk".to_vec();
+  let data = [
+    0x00, 0x90, 60, 100, 0x60, 60, 0, 0x00, 0xff, 0x7f, 0x01, 0x00, 0x00, 0xff, 0x2f, 0x00,
+  ];
+  file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  file.extend_from_slice(&data);
+  let (read, tracks) = Smf::read(&file).unwrap();
+  assert_eq!(read.quarter, ms(500));
+  let events = tracks.into_iter().next().unwrap().collect_timed();
+  assert_eq!(
+    events,
+    vec![
+      (
+        ms(0),
+        Event::Message(Message::NoteOn(Channel::Ch1, 60, 100))
+      ),
+      (
+        ms(500),
+        Event::Message(Message::NoteOff(Channel::Ch1, 60, 0))
+      ),
+    ]
+  );
+  assert!(Smf::read(&file[..30]).is_err());
+  assert!(Smf::read(b"RIFF").is_err());
+
+  // A track ending just after a delta time.
+  let mut file = b"MThd\x00\x00\x00\x06\x00\x00\x00\x01\x00\x60MTrk".to_vec();
+  let data = [0x00, 0x90, 60, 100, 0x60];
+  file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  file.extend_from_slice(&data);
+  assert!(Smf::read(&file).is_err());
+}
@@ -0,0 +1,144 @@
+use super::{Channel, Message, CHANNELS};
+use crate::stream::Stream;
+
+const BANK_SELECT: [u8; 2] = [0, 32];
+
+// The program, controller values, pitch bend and channel pressure last sent on
+// each channel. Values that have never been sent are None.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelState {
+  programs: [Option<u8>; 16],
+  controllers: Vec<[Option<u8>; 128]>,
+  pitch_bends: [Option<u16>; 16],
+  pressures: [Option<u8>; 16],
+}
+
+impl ChannelState {
+  pub fn new() -> Self {
+    Self {
+      programs: [None; 16],
+      controllers: vec![[None; 128]; 16],
+      pitch_bends: [None; 16],
+      pressures: [None; 16],
+    }
+  }
+  pub fn program(&self, ch: Channel) -> Option<u8> {
+    self.programs[ch as usize]
+  }
+  pub fn controller(&self, ch: Channel, controller: u8) -> Option<u8> {
+    self.controllers[ch as usize][controller as usize]
+  }
+  pub fn pitch_bend(&self, ch: Channel) -> Option<u16> {
+    self.pitch_bends[ch as usize]
+  }
+  pub fn pressure(&self, ch: Channel) -> Option<u8> {
+    self.pressures[ch as usize]
+  }
+  // Records the effect of a message, returning false if it was redundant
+  // because it set a value already in effect. Messages that don't affect the
+  // tracked state are never redundant.
+  pub fn update(&mut self, message: &Message) -> bool {
+    fn set<T: PartialEq>(slot: &mut Option<T>, value: T) -> bool {
+      let changed = slot.as_ref() != Some(&value);
+      *slot = Some(value);
+      changed
+    }
+    match *message {
+      Message::ProgramChange(ch, program) => set(&mut self.programs[ch as usize], program),
+      Message::ControlChange(ch, controller, value) => {
+        // A bank select only takes effect with the next program change, so
+        // repeating one isn't necessarily redundant.
+        let changed = set(
+          &mut self.controllers[ch as usize][controller as usize],
+          value,
+        );
+        changed || BANK_SELECT.contains(&controller)
+      }
+      Message::PitchBend(ch, value) => set(&mut self.pitch_bends[ch as usize], value),
+      Message::ChannelPressure(ch, pressure) => set(&mut self.pressures[ch as usize], pressure),
+      Message::ResetAllControllers(ch) => {
+        self.controllers[ch as usize] = [None; 128];
+        self.pitch_bends[ch as usize] = None;
+        self.pressures[ch as usize] = None;
+        true
+      }
+      _ => true,
+    }
+  }
+  // Messages that bring a device into this state. Bank selects come before
+  // the program change they apply to.
+  pub fn restore(&self) -> Vec<Message> {
+    let mut messages = Vec::new();
+    for &ch in &CHANNELS {
+      let control = |controller: u8| {
+        self
+          .controller(ch, controller)
+          .map(|value| Message::ControlChange(ch, controller, value))
+      };
+      messages.extend(BANK_SELECT.iter().filter_map(|&c| control(c)));
+      messages.extend(self.program(ch).map(|p| Message::ProgramChange(ch, p)));
+      messages.extend(
+        (0..128)
+          .filter(|c| !BANK_SELECT.contains(c))
+          .filter_map(control),
+      );
+      messages.extend(self.pitch_bend(ch).map(|b| Message::PitchBend(ch, b)));
+      messages.extend(self.pressure(ch).map(|p| Message::ChannelPressure(ch, p)));
+    }
+    messages
+  }
+}
+
+impl Default for ChannelState {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Drops program, controller, pitch bend and pressure messages that would
+// resend the value already in effect.
+pub fn suppress_redundant<'a>(messages: Stream<'a, Message>) -> Stream<'a, Message> {
+  let mut state = ChannelState::new();
+  messages.filter(move |message| state.update(message))
+}
+
+#[test]
+fn test_channel_state() {
+  use std::time::Duration;
+  let ms = Duration::from_millis;
+  let ch = Channel::Ch3;
+  let mut state = ChannelState::new();
+  assert!(state.update(&Message::ControlChange(ch, 7, 100)));
+  assert!(!state.update(&Message::ControlChange(ch, 7, 100)));
+  assert!(state.update(&Message::ControlChange(ch, 0, 2)));
+  assert!(state.update(&Message::ControlChange(ch, 0, 2)));
+  assert!(state.update(&Message::ProgramChange(ch, 9)));
+  assert!(state.update(&Message::NoteOn(ch, 60, 90)));
+  assert!(state.update(&Message::NoteOn(ch, 60, 90)));
+  assert_eq!(state.controller(ch, 7), Some(100));
+  assert_eq!(state.program(ch), Some(9));
+  assert_eq!(state.program(Channel::Ch1), None);
+  assert_eq!(
+    state.restore(),
+    vec![
+      Message::ControlChange(ch, 0, 2),
+      Message::ProgramChange(ch, 9),
+      Message::ControlChange(ch, 7, 100),
+    ]
+  );
+  assert!(state.update(&Message::ResetAllControllers(ch)));
+  assert_eq!(state.restore(), vec![Message::ProgramChange(ch, 9)]);
+
+  let messages = Stream::from_iter(vec![
+    (ms(0), Message::PitchBend(ch, 8192)),
+    (ms(10), Message::PitchBend(ch, 8192)),
+    (ms(10), Message::PitchBend(ch, 9000)),
+  ]);
+  assert_eq!(
+    suppress_redundant(messages).collect_timed(),
+    vec![
+      (ms(0), Message::PitchBend(ch, 8192)),
+      (ms(20), Message::PitchBend(ch, 9000)),
+    ]
+  );
+}
@@ -0,0 +1,107 @@
+use super::{Channel, Message};
+use crate::stream::Stream;
+use std::time::Duration;
+
+// Something wrong with a message stream, found by validate. Times are from
+// the start of the stream.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Problem {
+  // A data value too large to be sent.
+  OutOfRange(Duration, Message),
+  // A note on for a note that is already sounding on the same channel.
+  Retriggered(Duration, Channel, u8),
+  // A note off for a note that isn't sounding.
+  UnmatchedNoteOff(Duration, Channel, u8),
+  // A note still sounding when the stream ends.
+  Unreleased(Channel, u8),
+}
+
+// Checks a finite stream of messages, returning the problems in the order
+// they were found. A note on with zero velocity counts as a note off, as MIDI
+// allows, and all notes off and all sound off release every note on their
+// channel.
+pub fn validate(messages: Stream<Message>) -> Vec<Problem> {
+  let mut problems = Vec::new();
+  let mut sounding: Vec<(Channel, u8)> = Vec::new();
+  for (time, message) in messages.timestamps() {
+    if !in_range(&message) {
+      problems.push(Problem::OutOfRange(time, message));
+      continue;
+    }
+    match message {
+      Message::NoteOn(ch, note, 0) | Message::NoteOff(ch, note, _) => {
+        match sounding.iter().position(|&n| n == (ch, note)) {
+          Some(i) => {
+            sounding.remove(i);
+          }
+          None => problems.push(Problem::UnmatchedNoteOff(time, ch, note)),
+        }
+      }
+      Message::NoteOn(ch, note, _) => {
+        if sounding.contains(&(ch, note)) {
+          problems.push(Problem::Retriggered(time, ch, note));
+        } else {
+          sounding.push((ch, note));
+        }
+      }
+      Message::AllNotesOff(ch) | Message::AllSoundOff(ch) => sounding.retain(|&(c, _)| c != ch),
+      _ => (),
+    }
+  }
+  problems.extend(
+    sounding
+      .into_iter()
+      .map(|(ch, note)| Problem::Unreleased(ch, note)),
+  );
+  problems
+}
+
+fn in_range(message: &Message) -> bool {
+  match *message {
+    Message::NoteOn(_, a, b)
+    | Message::NoteOff(_, a, b)
+    | Message::PolyphonicPressure(_, a, b)
+    | Message::ControlChange(_, a, b) => a < 0x80 && b < 0x80,
+    Message::ProgramChange(_, a) | Message::ChannelPressure(_, a) => a < 0x80,
+    Message::PitchBend(_, value) => value < 0x4000,
+    _ => true,
+  }
+}
+
+#[test]
+fn test_validate() {
+  let ms = Duration::from_millis;
+  let (ch1, ch2) = (Channel::Ch1, Channel::Ch2);
+  let messages = Stream::from_iter(vec![
+    (ms(0), Message::NoteOn(ch1, 60, 100)),
+    (ms(0), Message::NoteOn(ch2, 60, 100)),
+    (ms(10), Message::NoteOn(ch1, 60, 100)),
+    (ms(10), Message::NoteOff(ch1, 60, 0)),
+    (ms(10), Message::NoteOff(ch1, 62, 0)),
+    (ms(10), Message::ControlChange(ch1, 7, 200)),
+    (ms(10), Message::PitchBend(ch1, 0x4000)),
+    (ms(10), Message::NoteOn(ch1, 64, 100)),
+    (ms(0), Message::NoteOn(ch1, 67, 100)),
+    (ms(10), Message::AllNotesOff(ch1)),
+  ]);
+  assert_eq!(
+    validate(messages),
+    vec![
+      Problem::Retriggered(ms(10), ch1, 60),
+      Problem::UnmatchedNoteOff(ms(30), ch1, 62),
+      Problem::OutOfRange(ms(40), Message::ControlChange(ch1, 7, 200)),
+      Problem::OutOfRange(ms(50), Message::PitchBend(ch1, 0x4000)),
+      Problem::Unreleased(ch2, 60),
+    ]
+  );
+
+  let messages = Stream::from_iter(vec![
+    (ms(0), Message::NoteOn(ch1, 60, 100)),
+    (ms(10), Message::NoteOn(ch1, 60, 0)),
+    (ms(10), Message::NoteOn(ch1, 62, 0)),
+  ]);
+  assert_eq!(
+    validate(messages),
+    vec![Problem::UnmatchedNoteOff(ms(20), ch1, 62)]
+  );
+}
@@ -0,0 +1,111 @@
+use crate::stream::Stream;
+use std::time::Duration;
+
+// The extent of a finite sequence of events.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Measure {
+  // Time from the start to the last event.
+  pub duration: Duration,
+  pub count: usize,
+}
+
+// A finite, replayable sequence of events occupying a fixed length of time.
+// Unlike a Stream it can be played any number of times.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern<E> {
+  events: Vec<(Duration, E)>,
+  length: Duration,
+}
+
+impl<E> Pattern<E> {
+  pub fn new(events: Vec<(Duration, E)>, length: Duration) -> Self {
+    assert!(
+      events.iter().map(|(d, _)| *d).sum::<Duration>() <= length,
+      "pattern events extend beyond its length"
+    );
+    Self { events, length }
+  }
+  pub fn from_stream(stream: Stream<E>, length: Duration) -> Self {
+    Self::new(stream.take(length).into_iter().collect(), length)
+  }
+  pub fn events(&self) -> &[(Duration, E)] {
+    &self.events
+  }
+  pub fn length(&self) -> Duration {
+    self.length
+  }
+  pub fn measure(&self) -> Measure {
+    Measure {
+      duration: self.events.iter().map(|(d, _)| *d).sum(),
+      count: self.events.len(),
+    }
+  }
+  pub fn is_empty(&self) -> bool {
+    self.events.is_empty()
+  }
+  pub fn map<F, EE>(&self, mut fun: F) -> Pattern<EE>
+  where
+    F: FnMut(&E) -> EE,
+  {
+    Pattern {
+      events: self.events.iter().map(|(d, e)| (*d, fun(e))).collect(),
+      length: self.length,
+    }
+  }
+}
+
+impl<E: Clone> Pattern<E> {
+  pub fn stream<'a>(&self) -> Stream<'a, E>
+  where
+    E: 'a,
+  {
+    Stream::from_iter(self.events.clone())
+  }
+  // Plays the pattern back to back forever.
+  pub fn looped<'a>(&self) -> Stream<'a, E>
+  where
+    E: 'a,
+  {
+    if self.events.is_empty() {
+      return Stream::empty();
+    }
+    self.stream().repeat_every(self.length)
+  }
+}
+
+#[test]
+fn test_pattern() {
+  let ms = Duration::from_millis;
+  let pattern = Pattern::from_stream(
+    Stream::from_iter(vec![(ms(0), 'a'), (ms(20), 'b'), (ms(20), 'c')]),
+    ms(30),
+  );
+  assert_eq!(pattern.events(), &[(ms(0), 'a'), (ms(20), 'b')]);
+  assert_eq!(
+    pattern.looped().into_iter().take(5).collect::<Vec<_>>(),
+    vec![
+      (ms(0), 'a'),
+      (ms(20), 'b'),
+      (ms(10), 'a'),
+      (ms(20), 'b'),
+      (ms(10), 'a')
+    ]
+  );
+}
+
+#[test]
+fn test_measure() {
+  let ms = Duration::from_millis;
+  let (measure, pattern) =
+    Stream::from_iter(vec![(ms(5), 'a'), (ms(20), 'b'), (ms(0), 'c')]).measure();
+  assert_eq!(
+    measure,
+    Measure {
+      duration: ms(25),
+      count: 3
+    }
+  );
+  assert_eq!(pattern.length(), ms(25));
+  assert_eq!(pattern.measure(), measure);
+  assert_eq!(Stream::<()>::empty().measure().0, Measure::default());
+}
@@ -0,0 +1,47 @@
+use crate::seed::Seed;
+use rand::distributions::WeightedIndex;
+use rand_distr::Distribution;
+
+// Splits `total` quanta into a seeded sequence of note values, drawn by weight
+// from `values` (given as (quanta, weight) pairs), that add up to exactly
+// `total`. A value is only ever chosen if what remains of the total can still
+// be filled exactly afterwards.
+pub fn partition(total: u32, values: &[(u32, f64)], seed: Seed) -> Vec<u32> {
+  let mut fillable = vec![false; total as usize + 1];
+  fillable[0] = true;
+  for remaining in 1..=total {
+    fillable[remaining as usize] = values
+      .iter()
+      .any(|&(n, _)| n > 0 && n <= remaining && fillable[(remaining - n) as usize]);
+  }
+  assert!(
+    fillable[total as usize],
+    "{} quanta can't be filled with the given note values",
+    total
+  );
+  let mut rng = seed.rng();
+  let mut notes = Vec::new();
+  let mut remaining = total;
+  while remaining > 0 {
+    let candidates: Vec<_> = values
+      .iter()
+      .filter(|&&(n, _)| n > 0 && n <= remaining && fillable[(remaining - n) as usize])
+      .collect();
+    let distr = WeightedIndex::new(candidates.iter().map(|(_, weight)| weight)).unwrap();
+    let n = candidates[distr.sample(&mut rng)].0;
+    notes.push(n);
+    remaining -= n;
+  }
+  notes
+}
+
+#[test]
+fn test_partition() {
+  for i in 0..50 {
+    let notes = partition(7, &[(3, 1.0), (2, 1.0)], Seed::new(i));
+    assert_eq!(notes.iter().sum::<u32>(), 7);
+    assert!(notes.iter().all(|&n| n == 2 || n == 3));
+  }
+  assert_eq!(partition(4, &[(4, 1.0), (1, 0.0)], Seed::new(0)), vec![4]);
+  assert_eq!(partition(0, &[(4, 1.0)], Seed::new(0)), vec![]);
+}
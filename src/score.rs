@@ -0,0 +1,109 @@
+use crate::theory::Note;
+use crate::var::Var;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Renders voices as plain text, with a line for each bar of each voice
+// listing its notes and rests with their lengths in beats, for example:
+//
+//   bar 1, treble: D4 1, F sharp 4 1/2, rest 1/2, E4 2
+//   bar 2, treble: tied E4 1, ...
+//
+// Accidentals are spelled out and no layout is used, so that the score reads
+// well with a screen reader or braille display.
+pub fn text_score<'a>(
+  voices: Vec<(&str, Var<'a, Option<Note>>)>,
+  beat: Duration,
+  beats_per_bar: u32,
+  bars: u32,
+) -> String {
+  let bar = beat * beats_per_bar;
+  let end = bar * bars;
+  let voices: Vec<_> = voices
+    .into_iter()
+    .map(|(name, notes)| (name, segments(notes, end)))
+    .collect();
+  let mut out = String::new();
+  for i in 0..bars {
+    let (bar_start, bar_end) = (bar * i, bar * (i + 1));
+    for (name, segments) in &voices {
+      let items: Vec<String> = segments
+        .iter()
+        .filter(|&&(start, stop, _)| start < bar_end && stop > bar_start)
+        .map(|&(start, stop, note)| {
+          let length = stop.min(bar_end) - start.max(bar_start);
+          let tied = if start < bar_start && note.is_some() {
+            "tied "
+          } else {
+            ""
+          };
+          let name = note.map_or("rest".to_string(), spoken);
+          format!("{}{} {}", tied, name, beats(length, beat))
+        })
+        .collect();
+      writeln!(out, "bar {}, {}: {}", i + 1, name, items.join(", ")).unwrap();
+    }
+  }
+  out
+}
+
+// Start and end times of each value held by the Var up to the given time.
+fn segments(notes: Var<Option<Note>>, end: Duration) -> Vec<(Duration, Duration, Option<Note>)> {
+  let mut segments: Vec<(Duration, Duration, Option<Note>)> = Vec::new();
+  for (now, note) in notes.updates().take(end).timestamps() {
+    if let Some(last) = segments.last_mut() {
+      last.1 = now;
+    }
+    segments.push((now, end, note));
+  }
+  segments.retain(|&(start, stop, _)| start < stop);
+  segments
+}
+
+fn spoken(note: Note) -> String {
+  note.to_string().replace('#', " sharp ")
+}
+
+// Length in beats as a whole number and a fraction, to the nearest
+// twelfth of a beat so that both duplets and triplets come out exactly.
+fn beats(length: Duration, beat: Duration) -> String {
+  let twelfths = (length.as_secs_f64() / beat.as_secs_f64() * 12.0).round() as u32;
+  let (whole, rest) = (twelfths / 12, twelfths % 12);
+  let gcd = (1..=rest)
+    .rev()
+    .find(|d| rest % d == 0 && 12 % d == 0)
+    .unwrap_or(12);
+  match (whole, rest) {
+    (_, 0) => whole.to_string(),
+    (0, _) => format!("{}/{}", rest / gcd, 12 / gcd),
+    _ => format!("{} {}/{}", whole, rest / gcd, 12 / gcd),
+  }
+}
+
+#[test]
+fn test_text_score() {
+  use crate::stream::Stream;
+  use crate::theory::PitchClass::*;
+  let ms = Duration::from_millis;
+  let treble = Var::from_updates(
+    Some(Note::new(D, 4)),
+    Stream::from_iter(vec![
+      (ms(500), Some(Note::new(FSharp, 4))),
+      (ms(250), None),
+      (ms(250), Some(Note::new(E, 4))),
+    ]),
+  );
+  let bass = Var::from_updates(
+    Some(Note::new(D, 2)),
+    Stream::from_iter(vec![(ms(1500), Some(Note::new(A, 2)))]),
+  );
+  assert_eq!(
+    text_score(vec![("treble", treble), ("bass", bass)], ms(500), 3, 2),
+    "bar 1, treble: D4 1, F sharp 4 1/2, rest 1/2, E4 1\n\
+     bar 1, bass: D2 3\n\
+     bar 2, treble: tied E4 3\n\
+     bar 2, bass: A2 3\n"
+  );
+  assert_eq!(beats(ms(1000), ms(300)), "3 1/3");
+  assert_eq!(beats(ms(375), ms(500)), "3/4");
+}
@@ -1,24 +1,129 @@
 use fnv::FnvHasher;
 use rand::rngs::SmallRng;
-use rand::SeedableRng;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_pcg::Pcg64Mcg;
 use std::hash::{Hash, Hasher};
 
+// Random number generator algorithm used by a composition. SmallRng is fast
+// but its algorithm may change between versions of rand; the others are
+// guaranteed to produce the same sequence forever.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RngKind {
+  Small,
+  ChaCha,
+  Pcg,
+}
+
+impl RngKind {
+  pub const ALL: [RngKind; 3] = [Self::Small, Self::ChaCha, Self::Pcg];
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Small => "small",
+      Self::ChaCha => "chacha8",
+      Self::Pcg => "pcg64mcg",
+    }
+  }
+}
+
+impl std::fmt::Display for RngKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl std::str::FromStr for RngKind {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, String> {
+    Self::ALL
+      .iter()
+      .copied()
+      .find(|kind| kind.name() == s)
+      .ok_or_else(|| format!("unknown RNG: {}", s))
+  }
+}
+
 #[derive(Debug)]
-pub struct Seed(u64);
+pub enum SeedRng {
+  Small(SmallRng),
+  ChaCha(Box<ChaCha8Rng>),
+  Pcg(Pcg64Mcg),
+}
+
+impl RngCore for SeedRng {
+  fn next_u32(&mut self) -> u32 {
+    match self {
+      Self::Small(rng) => rng.next_u32(),
+      Self::ChaCha(rng) => rng.next_u32(),
+      Self::Pcg(rng) => rng.next_u32(),
+    }
+  }
+  fn next_u64(&mut self) -> u64 {
+    match self {
+      Self::Small(rng) => rng.next_u64(),
+      Self::ChaCha(rng) => rng.next_u64(),
+      Self::Pcg(rng) => rng.next_u64(),
+    }
+  }
+  fn fill_bytes(&mut self, dest: &mut [u8]) {
+    match self {
+      Self::Small(rng) => rng.fill_bytes(dest),
+      Self::ChaCha(rng) => rng.fill_bytes(dest),
+      Self::Pcg(rng) => rng.fill_bytes(dest),
+    }
+  }
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+    match self {
+      Self::Small(rng) => rng.try_fill_bytes(dest),
+      Self::ChaCha(rng) => rng.try_fill_bytes(dest),
+      Self::Pcg(rng) => rng.try_fill_bytes(dest),
+    }
+  }
+}
+
+// Forked seeds use the same kind of RNG as their parent.
+#[derive(Debug)]
+pub struct Seed(u64, RngKind);
 impl Seed {
   const DELIM: u64 = 0xe16013eafc14eeed;
   pub fn new<H: Hash>(seed: H) -> Self {
-    Self(0).fork(seed)
+    Self(0, RngKind::Small).fork(seed)
+  }
+  pub fn with_rng(self, kind: RngKind) -> Self {
+    Self(self.0, kind)
+  }
+  pub fn rng_kind(&self) -> RngKind {
+    self.1
   }
   pub fn fork<H: Hash>(&self, route: H) -> Self {
     let mut h = FnvHasher::with_key(self.0);
     Self::DELIM.hash(&mut h);
     route.hash(&mut h);
-    Self(h.finish())
+    Self(h.finish(), self.1)
   }
-  pub fn rng(self) -> SmallRng {
+  pub fn rng(self) -> SeedRng {
     let mut h = FnvHasher::with_key(self.0);
     Self::DELIM.hash(&mut h);
-    SmallRng::seed_from_u64(h.finish())
+    let state = h.finish();
+    match self.1 {
+      RngKind::Small => SeedRng::Small(SmallRng::seed_from_u64(state)),
+      RngKind::ChaCha => SeedRng::ChaCha(Box::new(ChaCha8Rng::seed_from_u64(state))),
+      RngKind::Pcg => SeedRng::Pcg(Pcg64Mcg::seed_from_u64(state)),
+    }
+  }
+}
+
+#[test]
+fn test_rng_kind() {
+  use rand::Rng;
+  let sample = |kind| {
+    let mut rng = Seed::new("test").with_rng(kind).fork("a").rng();
+    (0..4).map(|_| rng.gen_range(0..1000)).collect::<Vec<u32>>()
+  };
+  for &kind in &RngKind::ALL {
+    assert_eq!(kind.to_string().parse(), Ok(kind));
+    assert_eq!(sample(kind), sample(kind));
   }
+  assert_ne!(sample(RngKind::ChaCha), sample(RngKind::Pcg));
+  assert!("mersenne".parse::<RngKind>().is_err());
 }
@@ -1,41 +1,134 @@
+use crate::pattern::{Measure, Pattern};
+use crate::seed::Seed;
+use crate::var::Var;
 use itertools::Itertools;
+use rand::Rng;
+use rand_distr::{Distribution, Exp, Normal};
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
 use std::time::Duration;
 
-pub struct Stream<'a, E: 'a>(Box<dyn Iterator<Item = (Duration, E)> + 'a>);
+// Activity of a stream over one period, reported by Stream::metrics. Times
+// are from the start of the stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Metrics {
+  pub start: Duration,
+  pub period: Duration,
+  pub events: usize,
+  // Longest time without an event, counting gaps that began in an earlier
+  // period up to the end of this one.
+  pub largest_gap: Duration,
+}
+
+impl Metrics {
+  // Events per second.
+  pub fn rate(&self) -> f64 {
+    self.events as f64 / self.period.as_secs_f64()
+  }
+}
+
+// Position of a stream among those merged by merge_labeled or
+// merge_all_labeled.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SourceId(pub usize);
+
+// A sequence of events in time. Each event's delay is measured from the
+// previous event, or from the start of the stream for the first one, so
+// simultaneous events are separated by zero delays.
+pub struct Stream<'a, E: 'a> {
+  events: Events<'a, E>,
+  // Set, by the time its events run out, for a stream that carries on
+  // silently forever rather than ending. Only iterating the stream itself
+  // blocks once the events run out: combinators read the events directly,
+  // look at the flag when they run out and pass it on, so they never block.
+  // Each flag is only ever set by the combinator that made it.
+  endless: Rc<Cell<bool>>,
+}
+
+// Events as combinators see them, with None for a rest: time passing where an
+// event was dropped. Rests are passed on so that combinators downstream, such
+// as take, see time go by without waiting for the next event that is kept.
+// Iterating a stream leaves them out, carrying their delays on.
+pub(crate) type Events<'a, E> = Box<dyn Iterator<Item = Event<E>> + 'a>;
+type Event<E> = (Duration, Option<E>);
 
 impl<'a, E: 'a> Stream<'a, E> {
+  // Other is never reached if self is endless.
   pub fn chain(self, other: Self) -> Self {
-    Self::from_iter(self.into_iter().chain(other))
+    let endless = Rc::new(Cell::new(false));
+    Self {
+      events: Box::new(Chain {
+        source1: Some((self.events, self.endless)),
+        source2: other.events,
+        endless2: other.endless,
+        endless: endless.clone(),
+      }),
+      endless,
+    }
   }
   pub fn chain_at(self, threshold: Duration, other: Self) -> Self {
-    Self::from_iter(ChainAt {
-      source1: self.into_iter(),
-      source2: other.into_iter(),
-      threshold,
-    })
+    Self {
+      events: Box::new(ChainAt {
+        source1: self.events,
+        source2: other.events,
+        threshold,
+      }),
+      endless: other.endless,
+    }
   }
-  pub fn coalesce<F>(mut self, reduce: F) -> Self
+  pub fn coalesce<F>(self, reduce: F) -> Self
   where
     F: Fn(E, E) -> E + 'a,
   {
-    Self::from_iter(Coalesce {
-      head: self.next(),
-      source: self.into_iter(),
+    self.adapt(|mut source| Coalesce {
+      head: source.next(),
+      source,
       reduce,
     })
   }
+  // Collects a finite stream, keeping each event's delay. Never returns if
+  // the stream is infinite.
+  pub fn collect_timed(self) -> Vec<(Duration, E)> {
+    self.into_iter().collect()
+  }
+  pub fn count_events(self) -> usize {
+    self.into_iter().count()
+  }
   pub fn delay(self, duration: Duration) -> Self {
     Self::empty().chain_at(duration, self)
   }
+  // Drops each event equal to the one before it, carrying its delay over to
+  // the next event kept, so a run of repeats is heard once.
+  pub fn dedup(self) -> Self
+  where
+    E: Clone + PartialEq,
+  {
+    self.adapt(|source| Dedup { source, last: None })
+  }
   pub fn drop(self, duration: Duration) -> Self {
-    Self::from_iter(Drop {
-      source: self.into_iter(),
-      duration,
-    })
+    self.adapt(|source| Drop { source, duration })
   }
   pub fn empty() -> Self {
     Self::from_iter(std::iter::empty())
   }
+  // Drops events not matching the predicate. The delays of dropped events are
+  // carried over to the next retained event, so timing is preserved.
+  pub fn filter<P>(self, mut predicate: P) -> Self
+  where
+    P: FnMut(&E) -> bool + 'a,
+  {
+    self.filter_map(move |e| if predicate(&e) { Some(e) } else { None })
+  }
+  pub fn filter_map<F, EE>(self, mut fun: F) -> Stream<'a, EE>
+  where
+    F: FnMut(E) -> Option<EE> + 'a,
+  {
+    // Dropped events become rests.
+    self.adapt(|source| source.map_events(move |e| e.and_then(&mut fun)))
+  }
   pub fn flat_map<F, I>(self, fun: F) -> Stream<'a, I::Item>
   where
     F: FnMut(E) -> I + 'a,
@@ -43,93 +136,969 @@ impl<'a, E: 'a> Stream<'a, E> {
   {
     self.map(fun).flatten()
   }
+  // Plays the items of each event at its time. The delay of an empty event
+  // is carried on to the next, so later events keep their times.
   pub fn flatten(self) -> Stream<'a, E::Item>
   where
     E: IntoIterator,
   {
-    Stream::from_iter(self.into_iter().flat_map(|(d, es)| {
-      let ds = std::iter::once(d).chain(std::iter::repeat(Duration::from_secs(0)));
-      ds.zip(es)
-    }))
+    self.adapt(|source| {
+      source.flat_map(|(d, es)| {
+        let mut es = es.into_iter().flatten().map(Some).peekable();
+        let rest = match es.peek() {
+          None => Some(None),
+          Some(_) => None,
+        };
+        let ds = std::iter::once(d).chain(std::iter::repeat(Duration::from_secs(0)));
+        ds.zip(es.chain(rest))
+      })
+    })
+  }
+  // Combines the events of a finite stream into a single value.
+  pub fn fold<A, F>(self, init: A, mut fun: F) -> A
+  where
+    F: FnMut(A, E) -> A,
+  {
+    self.into_iter().fold(init, |acc, (_, e)| fun(acc, e))
+  }
+  // Like fold, also giving each event's time since the start of the stream.
+  pub fn fold_timed<A, F>(self, init: A, mut fun: F) -> A
+  where
+    F: FnMut(A, Duration, E) -> A,
+  {
+    self
+      .timestamps()
+      .fold(init, |acc, (time, e)| fun(acc, time, e))
   }
   pub fn from_iter<I>(iter: I) -> Self
   where
     I: IntoIterator<Item = (Duration, E)>,
     I::IntoIter: 'a,
   {
-    Self(Box::new(iter.into_iter()))
+    Self {
+      events: Box::new(iter.into_iter().map(|(d, e)| (d, Some(e)))),
+      endless: Rc::new(Cell::new(false)),
+    }
+  }
+  // Builds a stream from events tagged with their time since the start, the
+  // inverse of timestamps. Panics if the times ever go backwards.
+  pub fn from_timestamps<I>(events: I) -> Self
+  where
+    I: IntoIterator<Item = (Duration, E)>,
+    I::IntoIter: 'a,
+  {
+    Self::from_iter(to_delays(events.into_iter()))
   }
   pub fn immediate(event: E) -> Self {
-    Self::from_iter(std::iter::once((Duration::from_secs(0), event)))
+    Self::at(Duration::from_secs(0), event)
+  }
+  // A single event after the delay.
+  pub fn at(delay: Duration, event: E) -> Self {
+    Self::from_iter(std::iter::once((delay, event)))
   }
-  pub fn lazy<F: 'a>(fun: F) -> Self
+  // Calls the function with each event and its time since the start of the
+  // stream as the event passes through.
+  pub fn inspect_timed<F>(self, mut fun: F) -> Self
   where
-    F: FnOnce() -> Self,
+    F: FnMut(Duration, &E) + 'a,
   {
-    Self::from_iter(Lazy::Before(Some(fun)))
+    let mut now = Duration::from_secs(0);
+    self.adapt(|source| {
+      source.map(move |(d, e)| {
+        now += d;
+        if let Some(e) = &e {
+          fun(now, e);
+        }
+        (d, e)
+      })
+    })
+  }
+  // Reports the stream's activity over each period as time passes its end,
+  // which is marked with a rest, so a voice that has stalled still reports
+  // its gap while the streams merged with it play on. Periods after the
+  // stream's last event or rest are never reported.
+  pub fn metrics<F>(self, period: Duration, mut report: F) -> Self
+  where
+    F: FnMut(&Metrics) + 'a,
+  {
+    assert!(period > Duration::from_secs(0), "period must be positive");
+    let mut current = Metrics {
+      start: Duration::from_secs(0),
+      period,
+      events: 0,
+      largest_gap: Duration::from_secs(0),
+    };
+    let mut last = Duration::from_secs(0);
+    let mut ended = false;
+    self.adapt_timestamps(move |source| {
+      let mut source = source.peekable();
+      std::iter::from_fn(move || {
+        // The rest ending the period has been passed, so it is over.
+        if ended {
+          report(&current);
+          current.start += period;
+          current.events = 0;
+          current.largest_gap = Duration::from_secs(0);
+          ended = false;
+        }
+        let end = current.start + period;
+        if source.peek()?.0 >= end {
+          current.largest_gap = current.largest_gap.max(end - last.max(current.start));
+          ended = true;
+          return Some((end, None));
+        }
+        let (now, e) = source.next()?;
+        if e.is_some() {
+          current.largest_gap = current.largest_gap.max(now - last.max(current.start));
+          current.events += 1;
+          last = now;
+        }
+        Some((now, e))
+      })
+    })
+  }
+  // Inserts a copy of the event midway between each pair of successive
+  // events, except those at the same instant.
+  pub fn intersperse(self, event: E) -> Self
+  where
+    E: Clone,
+  {
+    self.intersperse_with(move || event.clone())
+  }
+  // Like intersperse, calling the function for each event inserted.
+  pub fn intersperse_with<F>(self, mut fun: F) -> Self
+  where
+    F: FnMut() -> E + 'a,
+  {
+    self.adapt_timestamps(|source| {
+      let mut source = source.filter(|(_, e)| e.is_some()).peekable();
+      let events = std::iter::from_fn(move || {
+        let (time, e) = source.next()?;
+        let middle = match source.peek() {
+          Some(&(next, _)) if next > time => Some((time + (next - time) / 2, Some(fun()))),
+          _ => None,
+        };
+        Some(std::iter::once((time, e)).chain(middle))
+      });
+      events.flatten()
+    })
+  }
+  pub fn lazy<F>(fun: F) -> Self
+  where
+    F: FnOnce() -> Self + 'a,
+  {
+    let endless = Rc::new(Cell::new(false));
+    Self {
+      events: Box::new(Lazy {
+        state: LazyState::Before(Some(fun)),
+        endless: endless.clone(),
+      }),
+      endless,
+    }
   }
   pub fn map<F, EE>(self, mut fun: F) -> Stream<'a, EE>
   where
     F: FnMut(E) -> EE + 'a,
   {
-    Stream::from_iter(self.into_iter().map(move |(d, e)| (d, fun(e))))
+    self.adapt(|source| source.map_events(move |e| e.map(&mut fun)))
   }
-  pub fn merge(mut self, mut other: Self) -> Self {
-    Self::from_iter(Merge {
-      head1: self.next(),
-      head2: other.next(),
-      source1: self.into_iter(),
-      source2: other.into_iter(),
-    })
+  // Collects a finite stream into a pattern ending with its last event, so
+  // that it can still be played after being measured. Never returns if the
+  // stream is infinite.
+  pub fn measure(self) -> (Measure, Pattern<E>) {
+    let events = self.collect_timed();
+    let duration = events.iter().map(|(d, _)| *d).sum();
+    let pattern = Pattern::new(events, duration);
+    (pattern.measure(), pattern)
   }
+  // Simultaneous events are taken from self first.
+  pub fn merge(self, other: Self) -> Self {
+    self.merge_by(other, |_, _| true)
+  }
+  // Simultaneous events are ordered by `first`, which is given the next
+  // event from each stream and says whether the one from self goes first.
+  pub fn merge_by<F>(self, other: Self, first: F) -> Self
+  where
+    F: FnMut(&E, &E) -> bool + 'a,
+  {
+    let (mut source1, mut source2) = (self.events, other.events);
+    let endless = Rc::new(Cell::new(false));
+    Self {
+      events: Box::new(Merge {
+        head1: source1.next(),
+        head2: source2.next(),
+        source1,
+        source2,
+        first,
+        ended: [self.endless, other.endless],
+        endless: endless.clone(),
+      }),
+      endless,
+    }
+  }
+  // Merges any number of streams, with simultaneous events in the order of
+  // the streams they come from. The streams are kept in a heap ordered by
+  // next event, so each event costs O(log n) for n streams.
   pub fn merge_all<I>(streams: I) -> Self
   where
     I: IntoIterator<Item = Self>,
+  {
+    let mut sources = Vec::new();
+    let mut heads = Vec::new();
+    let mut heap = BinaryHeap::new();
+    let mut ended = Vec::new();
+    for (index, stream) in streams.into_iter().enumerate() {
+      let mut source = stream.events;
+      let head = source.next();
+      if let Some((time, _)) = head {
+        heap.push(Reverse((time, index)));
+      }
+      sources.push(source);
+      heads.push(head.map(|(_, e)| e));
+      ended.push(stream.endless);
+    }
+    let endless = Rc::new(Cell::new(false));
+    Self {
+      events: Box::new(MergeAll {
+        sources,
+        heads,
+        heap,
+        now: Duration::from_secs(0),
+        ended,
+        endless: endless.clone(),
+      }),
+      endless,
+    }
+  }
+  pub fn merge_all_by<I, F>(streams: I, first: F) -> Self
+  where
+    I: IntoIterator<Item = Self>,
+    F: FnMut(&E, &E) -> bool + Clone + 'a,
   {
     streams
       .into_iter()
-      .fold1(Self::merge)
+      .fold1(|a, b| a.merge_by(b, first.clone()))
       .unwrap_or_else(Self::empty)
   }
-  pub fn next(&mut self) -> Option<(Duration, E)> {
-    self.0.next()
+  // Like merge, but tagging each event with the stream it came from: 0 for
+  // self and 1 for other.
+  pub fn merge_labeled(self, other: Self) -> Stream<'a, (SourceId, E)> {
+    Stream::merge_all_labeled(vec![self, other])
+  }
+  // Like merge_all, but tagging each event with the position of the stream
+  // it came from.
+  pub fn merge_all_labeled<I>(streams: I) -> Stream<'a, (SourceId, E)>
+  where
+    I: IntoIterator<Item = Self>,
+  {
+    Stream::merge_all(
+      streams
+        .into_iter()
+        .enumerate()
+        .map(|(i, stream)| stream.map(move |e| (SourceId(i), e))),
+    )
+  }
+  // A stream that never has any events but never ends either. Merged with
+  // other streams, it keeps the result going after they have all ended, so it
+  // can stand in for a voice that hasn't started yet.
+  pub fn never() -> Self {
+    Self {
+      events: Box::new(std::iter::empty()),
+      endless: Rc::new(Cell::new(true)),
+    }
+  }
+  // Keeps the stream going silently after its last event, as if chained
+  // with never.
+  pub fn pending(self) -> Self {
+    Self {
+      events: self.events,
+      endless: Rc::new(Cell::new(true)),
+    }
+  }
+  pub(crate) fn next(&mut self) -> Option<(Duration, E)> {
+    let mut carried = Duration::from_secs(0);
+    loop {
+      match self.events.next() {
+        Some((d, Some(e))) => return Some((carried + d, e)),
+        Some((d, None)) => carried += d,
+        None if self.endless.get() => block_forever(),
+        None => return None,
+      }
+    }
+  }
+  // The events, which end rather than block, and the flag saying whether the
+  // stream carries on silently after them, for adaptors outside this module.
+  pub(crate) fn into_parts(self) -> (Events<'a, E>, Rc<Cell<bool>>) {
+    (self.events, self.endless)
+  }
+  pub(crate) fn from_parts(events: Events<'a, E>, endless: Rc<Cell<bool>>) -> Self {
+    Self { events, endless }
+  }
+  // Builds a stream from this one's events, which end when they run out
+  // rather than blocking if this stream is endless. The stream built is
+  // endless if this one is, once the events `fun` returns run out.
+  fn adapt<F, I, EE>(self, fun: F) -> Stream<'a, EE>
+  where
+    F: FnOnce(Events<'a, E>) -> I,
+    I: Iterator<Item = (Duration, Option<EE>)> + 'a,
+  {
+    Stream {
+      events: Box::new(fun(self.events)),
+      endless: self.endless,
+    }
+  }
+  // Like adapt, with events tagged with their time since the start both ways.
+  // Panics if the times returned ever go backwards.
+  fn adapt_timestamps<F, I, EE>(self, fun: F) -> Stream<'a, EE>
+  where
+    F: FnOnce(Events<'a, E>) -> I,
+    I: Iterator<Item = (Duration, Option<EE>)> + 'a,
+  {
+    let mut now = Duration::from_secs(0);
+    let times = Box::new(self.events.map(move |(d, e)| {
+      now += d;
+      (now, e)
+    }));
+    Stream {
+      events: Box::new(to_delays(fun(times))),
+      endless: self.endless,
+    }
+  }
+  // Moves every event, and every rest, to the time the function gives for
+  // its time since the start, for adaptors outside this module. Panics if
+  // the times returned ever go backwards.
+  pub(crate) fn retime<F>(self, mut fun: F) -> Self
+  where
+    F: FnMut(Duration) -> Duration + 'a,
+  {
+    self.adapt_timestamps(|source| source.map(move |(time, e)| (fun(time), e)))
+  }
+  // Allows looking at the next event without consuming it.
+  pub fn peekable(self) -> Peekable<'a, E> {
+    let events: Box<dyn Iterator<Item = (Duration, E)> + 'a> = Box::new(settle(self.events));
+    Peekable {
+      events: events.peekable(),
+      endless: self.endless,
+    }
   }
   pub fn repeat_every(self, interval: Duration) -> Self
   where
     E: Clone,
   {
-    let sample: Vec<_> = self.take(interval).into_iter().collect();
-    Self::replay_every(sample, interval)
+    // The first pass is played as it is read, and recorded for the rest.
+    let recorded = Rc::new(RefCell::new(Vec::new()));
+    let sink = recorded.clone();
+    let first = self.take(interval).adapt(|source| {
+      source.map(move |(d, e)| {
+        sink.borrow_mut().push((d, e.clone()));
+        (d, e)
+      })
+    });
+    first.chain_at(
+      interval,
+      Self::lazy(move || Self::replay_every(recorded.take(), interval)),
+    )
   }
-  fn replay_every(sample: Vec<(Duration, E)>, interval: Duration) -> Self
+  // Plays the first `interval` of the stream `count` times, back to back.
+  pub fn repeat_n(self, interval: Duration, count: usize) -> Self
   where
     E: Clone,
   {
+    let sample = self.take(interval).collect_timed();
+    (0..count).fold(Self::empty(), |rest, _| {
+      Self::from_iter(sample.clone()).chain_at(interval, rest)
+    })
+  }
+  // Loops a finite stream forever. Each repetition follows on from the last
+  // event of the previous one, so the period is the total of the delays.
+  pub fn cycle(self) -> Self
+  where
+    E: Clone,
+  {
+    if self.endless.get() {
+      return self;
+    }
+    let events = self.collect_timed();
+    Self::from_iter(events.into_iter().cycle())
+  }
+  fn replay_every(sample: Vec<(Duration, Option<E>)>, interval: Duration) -> Self
+  where
+    E: Clone,
+  {
+    // Repeating silence would otherwise recurse forever looking for an event.
+    if sample.iter().all(|(_, e)| e.is_none()) {
+      return Self::never();
+    }
+    Self::lazy(move || {
+      let pass = Self {
+        events: Box::new(sample.clone().into_iter()),
+        endless: Rc::new(Cell::new(false)),
+      };
+      pass.chain_at(interval, Self::replay_every(sample, interval))
+    })
+  }
+  // Moves each event to the nearest multiple of the grid. Event times rather
+  // than delays are rounded, so rounding errors don't accumulate.
+  pub fn quantize(self, grid: Duration) -> Self {
+    assert!(
+      grid > Duration::from_secs(0),
+      "quantization grid must be positive"
+    );
+    let grid_nanos = grid.as_nanos();
+    self.adapt_timestamps(|source| {
+      source.map(move |(now, e)| {
+        let steps = (now.as_nanos() + grid_nanos / 2) / grid_nanos;
+        (Duration::from_nanos((steps * grid_nanos) as u64), e)
+      })
+    })
+  }
+  // Offsets each event from its original time by a normally distributed
+  // amount. Offsets don't accumulate, so the stream doesn't drift, and events
+  // are never moved before the one preceding them.
+  pub fn humanize(self, seed: Seed, std_dev: Duration) -> Self {
+    let distr = Normal::new(0.0, std_dev.as_secs_f64()).unwrap();
+    let mut now = Duration::from_secs(0);
+    let mut humanized_now = Duration::from_secs(0);
+    let mut index = 0;
+    self.adapt(|source| {
+      source.map(move |(d, e)| {
+        now += d;
+        let time = match e {
+          Some(_) => {
+            let offset = distr.sample(&mut seed.fork(index).rng());
+            index += 1;
+            Duration::from_secs_f64((now.as_secs_f64() + offset).max(0.0))
+          }
+          None => now,
+        };
+        let time = time.max(humanized_now);
+        let delay = time - humanized_now;
+        humanized_now = time;
+        (delay, e)
+      })
+    })
+  }
+  // Delays off-beats: within each pair of grid steps, the second step is moved
+  // to the given proportion of the pair (0.5 is straight, about 0.67 a triplet
+  // swing) and the times around it are stretched or squashed to match.
+  pub fn swing(self, grid: Duration, ratio: f64) -> Self {
+    assert!(grid > Duration::from_secs(0), "swing grid must be positive");
+    assert!(
+      ratio > 0.0 && ratio < 1.0,
+      "swing ratio must be between 0 and 1"
+    );
+    let grid = grid.as_nanos();
+    let pair = grid * 2;
+    let offbeat = (pair as f64 * ratio).round() as u128;
+    self.adapt_timestamps(|source| {
+      source.map(move |(now, e)| {
+        let position = now.as_nanos() % pair;
+        let swung = if position < grid {
+          position * offbeat / grid
+        } else {
+          offbeat + (position - grid) * (pair - offbeat) / (pair - grid)
+        };
+        let time = Duration::from_nanos((now.as_nanos() - position + swung) as u64);
+        (time, e)
+      })
+    })
+  }
+  // Adds the offset to every delay, so each event lags the one before it by
+  // that much more and the lag builds up over the stream. To move the whole
+  // stream later without it building up, such as to lay one voice slightly
+  // behind the beat, use delay.
+  pub fn shift_each(self, offset: Duration) -> Self {
+    self.adapt(|source| {
+      source.map(move |(d, e)| match e {
+        Some(e) => (d + offset, Some(e)),
+        None => (d, None),
+      })
+    })
+  }
+  // Takes the offset off every delay, the opposite of shift_each, so each
+  // event gains on the one before it. Delays shorter than the offset become
+  // zero.
+  pub fn push(self, offset: Duration) -> Self {
+    self.adapt(|source| {
+      source.map(move |(d, e)| match e {
+        Some(e) => (d.saturating_sub(offset), Some(e)),
+        None => (d, None),
+      })
+    })
+  }
+  // Moves every event earlier by the same amount, to put a voice ahead of the
+  // beat; the opposite of delay. Events that would come before the start of
+  // the stream are played at the start instead.
+  pub fn advance(self, offset: Duration) -> Self {
+    self.adapt_timestamps(|source| source.map(move |(now, e)| (now.saturating_sub(offset), e)))
+  }
+  // Multiplies every delay by the factor: 2.0 plays at half speed, 0.5 at
+  // double speed.
+  pub fn scale_time(self, factor: f64) -> Self {
+    self.adapt(|source| source.scale_time(factor))
+  }
+  // Rescales the delays of a finite stream so that its last event falls
+  // exactly at `total`. A stream whose events all happen at the start can't
+  // be stretched and is left as it is.
+  pub fn stretch_to(self, total: Duration) -> Self {
     Self::lazy(move || {
-      Self::from_iter(sample.clone()).chain_at(interval, Self::replay_every(sample, interval))
+      let events: Vec<_> = settle(self.events).collect();
+      let current = events.iter().map(|(d, _)| *d).sum::<Duration>().as_nanos();
+      let stretched = if current == 0 {
+        Self::from_iter(events)
+      } else {
+        let total = total.as_nanos();
+        let mut now = 0;
+        Self::from_timestamps(events.into_iter().map(move |(d, e)| {
+          now += d.as_nanos();
+          (Duration::from_nanos((now * total / current) as u64), e)
+        }))
+      };
+      Self {
+        events: stretched.events,
+        endless: self.endless,
+      }
     })
   }
+  // Splits the stream into `count` identical streams that can be consumed
+  // independently. Events are buffered until every stream has consumed them,
+  // so streams that fall far behind the others cost memory. Streams that are
+  // dropped stop buffering.
+  pub fn tee(self, count: usize) -> Vec<Self>
+  where
+    E: Clone,
+  {
+    let endless = self.endless;
+    let shared = Rc::new(RefCell::new(Tee {
+      source: self.events,
+      queues: vec![Some(VecDeque::new()); count],
+    }));
+    (0..count)
+      .map(|index| Self {
+        events: Box::new(TeeBranch {
+          shared: shared.clone(),
+          index,
+        }),
+        endless: endless.clone(),
+      })
+      .collect()
+  }
+  // Splits the stream into the events matching the predicate and the rest,
+  // each keeping its original timing. Like tee, events are buffered until the
+  // stream they belong to consumes them, unless it has been dropped.
+  pub fn partition<P>(self, predicate: P) -> (Self, Self)
+  where
+    P: FnMut(&E) -> bool + 'a,
+  {
+    let endless = self.endless;
+    let shared = Rc::new(RefCell::new(Partition {
+      source: self.events,
+      predicate,
+      now: Duration::from_secs(0),
+      queues: [Some(VecDeque::new()), Some(VecDeque::new())],
+    }));
+    let branch = |side| Self {
+      events: Box::new(PartitionBranch {
+        shared: shared.clone(),
+        side,
+        last: Duration::from_secs(0),
+      }),
+      endless: endless.clone(),
+    };
+    (branch(0), branch(1))
+  }
+  // Tags each event with its time since the start of the stream, for code
+  // that is easier to write in absolute time.
+  pub fn timestamps(self) -> impl Iterator<Item = (Duration, E)> + 'a {
+    let mut now = Duration::from_secs(0);
+    self.into_iter().map(move |(d, e)| {
+      now += d;
+      (now, e)
+    })
+  }
+  // Time from the start of a finite stream to its last event.
+  pub fn total_duration(self) -> Duration {
+    self.into_iter().map(|(d, _)| d).sum()
+  }
+  // Groups every `size` consecutive events, emitting each group once it is
+  // complete, at the time of its last event. The last group is shorter if the
+  // events run out, and comes when they do. Nothing is read ahead, so a
+  // source that falls silent doesn't hold up take.
+  pub fn chunks(self, size: usize) -> Stream<'a, Vec<E>> {
+    assert!(size > 0, "chunk size must be positive");
+    self.adapt(|source| Chunks {
+      source,
+      size,
+      chunk: Vec::new(),
+    })
+  }
+  // Crossfades between two streams: at each instant when either has events,
+  // the other stream's events are played instead of this one's with
+  // probability `bias`. Moving the bias from 0 to 1 morphs one into the other.
+  // A NaN bias counts as 0. Ends once both streams have, however long the
+  // bias goes on.
+  pub fn blend(self, other: Self, bias: Var<'a, f64>, seed: Seed) -> Self {
+    enum Blend<E> {
+      Bias(f64),
+      This(E),
+      Other(E),
+      End,
+    }
+    let mut current = 0.0;
+    let mut index = 0u64;
+    let mut ended = 0;
+    bias
+      .updates()
+      .map(Blend::Bias)
+      .merge(self.map(Blend::This).chain(Stream::immediate(Blend::End)))
+      .merge(other.map(Blend::Other).chain(Stream::immediate(Blend::End)))
+      .take_while(move |event| {
+        if let Blend::End = event {
+          ended += 1;
+        }
+        ended < 2
+      })
+      .group_simultaneous()
+      .map(move |group| {
+        for event in &group {
+          if let Blend::Bias(bias) = event {
+            current = if bias.is_nan() {
+              0.0
+            } else {
+              bias.clamp(0.0, 1.0)
+            };
+          }
+        }
+        index += 1;
+        let use_other = seed.fork(index).rng().gen_bool(current);
+        group
+          .into_iter()
+          .filter_map(|event| match event {
+            Blend::This(e) if !use_other => Some(e),
+            Blend::Other(e) if use_other => Some(e),
+            _ => None,
+          })
+          .collect::<Vec<_>>()
+      })
+      .flatten()
+  }
+  // Removes events equal to an earlier event at the same instant, such as
+  // those left by merging a stream with a copy of itself.
+  pub fn normalize(self) -> Self
+  where
+    E: PartialEq,
+  {
+    self
+      .group_simultaneous()
+      .map(|group| {
+        let mut unique = Vec::new();
+        for e in group {
+          if !unique.contains(&e) {
+            unique.push(e);
+          }
+        }
+        unique
+      })
+      .flatten()
+  }
+  // Groups events that happen at the same instant, as separated by zero
+  // delays. The inverse of flatten.
+  pub fn group_simultaneous(self) -> Stream<'a, Vec<E>> {
+    self.adapt(|source| GroupSimultaneous(source.peekable()))
+  }
+  // Groups events into consecutive windows of the given length, emitting each
+  // group at the start of its window. Windows with no events produce empty
+  // groups, except after the last event.
+  pub fn windows(self, window: Duration) -> Stream<'a, Vec<E>> {
+    assert!(window > Duration::from_secs(0), "window must be positive");
+    self.adapt(|source| Windows {
+      source,
+      head: None,
+      window,
+      started: false,
+    })
+  }
+  // Like map, but with a state shared between successive calls.
+  pub fn scan<S, F, EE>(self, mut state: S, mut fun: F) -> Stream<'a, EE>
+  where
+    S: 'a,
+    F: FnMut(&mut S, E) -> EE + 'a,
+  {
+    self.map(move |e| fun(&mut state, e))
+  }
   pub fn take(self, duration: Duration) -> Self {
     self.chain_at(duration, Self::empty())
   }
+  // Ends the stream once it has had `count` events, without waiting for any
+  // more. If the events run out first, the stream is endless if this one is.
+  pub fn take_n(self, count: usize) -> Self {
+    let endless = Rc::new(Cell::new(false));
+    Self {
+      events: Box::new(TakeN {
+        source: Some((self.events, self.endless)),
+        remaining: count,
+        endless: endless.clone(),
+      }),
+      endless,
+    }
+  }
+  // Ends the stream at the first event not matching the predicate. If the
+  // events run out first, the stream is endless if this one is.
+  pub fn take_while<P>(self, predicate: P) -> Self
+  where
+    P: FnMut(&E) -> bool + 'a,
+  {
+    let endless = Rc::new(Cell::new(false));
+    Self {
+      events: Box::new(TakeWhile {
+        source: Some((self.events, self.endless)),
+        predicate,
+        endless: endless.clone(),
+      }),
+      endless,
+    }
+  }
+  // Drops the first events, keeping the time at which the rest occur.
+  pub fn skip_n(self, count: usize) -> Self {
+    let mut skipped = 0;
+    self.filter(move |_| {
+      skipped += 1;
+      skipped > count
+    })
+  }
+}
+
+// Schedulers producing bare event timings, to be given content with map or
+// scan.
+impl<'a> Stream<'a, ()> {
+  // Events at exponentially distributed intervals, averaging `rate` per
+  // second. Never ends.
+  pub fn poisson(rate: f64, seed: Seed) -> Self {
+    let distr = Exp::new(rate).unwrap();
+    Self::from_iter((0u64..).map(move |i| {
+      let delay = distr.sample(&mut seed.fork(i).rng());
+      (Duration::from_secs_f64(delay), ())
+    }))
+  }
+  // Clusters of events. The gaps between clusters are exponentially
+  // distributed with an average of `rate` clusters per second (not counting
+  // the time the clusters themselves take), and the events within a cluster
+  // form a Poisson process at `inner_rate`. Never ends.
+  pub fn bursts(rate: f64, inner_rate: f64, size: RangeInclusive<usize>, seed: Seed) -> Self {
+    assert!(*size.start() > 0, "bursts must contain at least one event");
+    let gap_distr = Exp::new(rate).unwrap();
+    Self::from_iter((0u64..).flat_map(move |i| {
+      let seed = seed.fork(i);
+      let count = seed.fork("size").rng().gen_range(size.clone());
+      let gap = gap_distr.sample(&mut seed.fork("gap").rng());
+      std::iter::once((Duration::from_secs_f64(gap), ()))
+        .chain(Stream::poisson(inner_rate, seed.fork("events")).take_n(count - 1))
+    }))
+  }
 }
 
 impl<'a, E: 'a> IntoIterator for Stream<'a, E> {
   type Item = (Duration, E);
   type IntoIter = Box<dyn Iterator<Item = (Duration, E)> + 'a>;
   fn into_iter(self) -> Self::IntoIter {
-    self.0
+    let endless = self.endless;
+    Box::new(settle(self.events).chain(std::iter::from_fn(move || {
+      if endless.get() {
+        block_forever()
+      }
+      None
+    })))
+  }
+}
+
+// The events of a stream without its rests, whose delays are carried on to
+// the next event.
+fn settle<'a, E: 'a>(events: Events<'a, E>) -> impl Iterator<Item = (Duration, E)> + 'a {
+  let mut carried = Duration::from_secs(0);
+  events.filter_map(move |(d, e)| {
+    carried += d;
+    e.map(|e| (std::mem::take(&mut carried), e))
+  })
+}
+
+// Turns times since the start into delays. Panics if the times ever go
+// backwards.
+fn to_delays<I, T>(events: I) -> impl Iterator<Item = (Duration, T)>
+where
+  I: Iterator<Item = (Duration, T)>,
+{
+  let mut prev = Duration::from_secs(0);
+  events.map(move |(time, e)| {
+    assert!(time >= prev, "timestamps must not decrease");
+    let delay = time - prev;
+    prev = time;
+    (delay, e)
+  })
+}
+
+fn block_forever() -> ! {
+  loop {
+    std::thread::park();
+  }
+}
+
+pub struct Peekable<'a, E> {
+  events: std::iter::Peekable<Box<dyn Iterator<Item = (Duration, E)> + 'a>>,
+  endless: Rc<Cell<bool>>,
+}
+
+impl<'a, E: 'a> Peekable<'a, E> {
+  // Like iterating, blocks forever if the stream is endless and its events
+  // have run out.
+  pub fn peek(&mut self) -> Option<&(Duration, E)> {
+    if self.events.peek().is_none() && self.endless.get() {
+      block_forever()
+    }
+    self.events.peek()
+  }
+  // Delay before the next event, without consuming it.
+  pub fn peek_delay(&mut self) -> Option<Duration> {
+    self.peek().map(|&(d, _)| d)
+  }
+  // Continues as an ordinary stream, starting with any peeked event.
+  pub fn stream(self) -> Stream<'a, E> {
+    Stream {
+      events: Box::new(self.events.map(|(d, e)| (d, Some(e)))),
+      endless: self.endless,
+    }
+  }
+}
+
+impl<'a, E: 'a> Iterator for Peekable<'a, E> {
+  type Item = (Duration, E);
+  fn next(&mut self) -> Option<(Duration, E)> {
+    self.peek()?;
+    self.events.next()
+  }
+}
+
+// Unboxed versions of some of Stream's combinators, for any iterator of
+// (delay, event) pairs. Unlike a chain of Stream combinators, which allocates
+// and adds a dynamic call for every step, a chain of these is one concrete
+// iterator. Box it into a Stream only where an API needs one. Only a few
+// combinators are covered, so it is left out of the prelude.
+pub trait TimedIterator<E>: Iterator<Item = (Duration, E)> + Sized {
+  fn boxed<'a>(self) -> Stream<'a, E>
+  where
+    Self: 'a,
+    E: 'a,
+  {
+    Stream::from_iter(self)
+  }
+  fn map_events<F, EE>(self, mut fun: F) -> impl Iterator<Item = (Duration, EE)>
+  where
+    F: FnMut(E) -> EE,
+  {
+    self.map(move |(d, e)| (d, fun(e)))
+  }
+  // Delays of dropped events are carried over, as with Stream::filter_map.
+  fn filter_map_events<F, EE>(self, fun: F) -> impl Iterator<Item = (Duration, EE)>
+  where
+    F: FnMut(E) -> Option<EE>,
+  {
+    FilterMap {
+      source: self,
+      fun,
+      pending: Duration::from_secs(0),
+    }
+  }
+  fn scale_time(self, factor: f64) -> impl Iterator<Item = (Duration, E)> {
+    assert!(factor >= 0.0, "time scale factor must not be negative");
+    self.map(move |(d, e)| (d.mul_f64(factor), e))
+  }
+}
+
+impl<E, I> TimedIterator<E> for I where I: Iterator<Item = (Duration, E)> {}
+
+struct Chain<'a, E> {
+  // Taken once its events run out.
+  source1: Option<(Events<'a, E>, Rc<Cell<bool>>)>,
+  source2: Events<'a, E>,
+  endless2: Rc<Cell<bool>>,
+  endless: Rc<Cell<bool>>,
+}
+impl<'a, E> Iterator for Chain<'a, E> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
+    if let Some((source1, endless1)) = &mut self.source1 {
+      if let Some(event) = source1.next() {
+        return Some(event);
+      }
+      if endless1.get() {
+        self.endless.set(true);
+        return None;
+      }
+      self.source1 = None;
+    }
+    let next = self.source2.next();
+    if next.is_none() {
+      self.endless.set(self.endless2.get());
+    }
+    next
+  }
+}
+
+struct TakeWhile<'a, E, P> {
+  // Taken once the stream ends.
+  source: Option<(Events<'a, E>, Rc<Cell<bool>>)>,
+  predicate: P,
+  endless: Rc<Cell<bool>>,
+}
+impl<'a, E, P> Iterator for TakeWhile<'a, E, P>
+where
+  P: FnMut(&E) -> bool,
+{
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
+    let (source, endless) = self.source.as_mut()?;
+    match source.next() {
+      Some((d, None)) => return Some((d, None)),
+      Some((d, Some(e))) if (self.predicate)(&e) => return Some((d, Some(e))),
+      Some(_) => (),
+      None => self.endless.set(endless.get()),
+    }
+    self.source = None;
+    None
+  }
+}
+
+struct TakeN<'a, E> {
+  // Taken once the stream ends.
+  source: Option<(Events<'a, E>, Rc<Cell<bool>>)>,
+  remaining: usize,
+  endless: Rc<Cell<bool>>,
+}
+impl<'a, E> Iterator for TakeN<'a, E> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
+    let (source, endless) = self.source.as_mut()?;
+    if self.remaining > 0 {
+      if let Some((d, e)) = source.next() {
+        if e.is_some() {
+          self.remaining -= 1;
+        }
+        return Some((d, e));
+      }
+      self.endless.set(endless.get());
+    }
+    self.source = None;
+    None
   }
 }
 
 struct ChainAt<'a, E> {
-  source1: Box<dyn Iterator<Item = (Duration, E)> + 'a>,
-  source2: Box<dyn Iterator<Item = (Duration, E)> + 'a>,
+  source1: Events<'a, E>,
+  source2: Events<'a, E>,
   threshold: Duration,
 }
 impl<'a, E: 'a> Iterator for ChainAt<'a, E> {
-  type Item = (Duration, E);
-  fn next(&mut self) -> Option<(Duration, E)> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
     match self.source1.next() {
       Some((d, e)) if d <= self.threshold => {
         self.threshold -= d;
@@ -151,19 +1120,23 @@ impl<'a, E: 'a> Iterator for ChainAt<'a, E> {
 }
 
 struct Coalesce<'a, E, F> {
-  source: Box<dyn Iterator<Item = (Duration, E)> + 'a>,
-  head: Option<(Duration, E)>,
+  source: Events<'a, E>,
+  head: Option<(Duration, Option<E>)>,
   reduce: F,
 }
 impl<'a, E, F> Iterator for Coalesce<'a, E, F>
 where
   F: Fn(E, E) -> E,
 {
-  type Item = (Duration, E);
-  fn next(&mut self) -> Option<(Duration, E)> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
     match (self.head.take(), self.source.next()) {
-      (Some((d1, e1)), Some((d2, e2))) if d2 == Duration::from_secs(0) => {
-        self.head = Some((d1, (self.reduce)(e1, e2)));
+      (Some((d1, Some(e1))), Some((d2, e2))) if d2 == Duration::from_secs(0) => {
+        let e = match e2 {
+          Some(e2) => (self.reduce)(e1, e2),
+          None => e1,
+        };
+        self.head = Some((d1, Some(e)));
         self.next()
       }
       (head, next) => {
@@ -174,13 +1147,33 @@ where
   }
 }
 
+struct Dedup<'a, E> {
+  source: Events<'a, E>,
+  last: Option<E>,
+}
+impl<'a, E: Clone + PartialEq> Iterator for Dedup<'a, E> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
+    // Each event is passed on as soon as it is read, so the stream never
+    // waits for the end of a run of repeats, and repeats become rests.
+    let (d, e) = self.source.next()?;
+    match e {
+      Some(e) if self.last.as_ref() != Some(&e) => {
+        self.last = Some(e.clone());
+        Some((d, Some(e)))
+      }
+      _ => Some((d, None)),
+    }
+  }
+}
+
 struct Drop<'a, E> {
-  source: Box<dyn Iterator<Item = (Duration, E)> + 'a>,
+  source: Events<'a, E>,
   duration: Duration,
 }
 impl<'a, E> Iterator for Drop<'a, E> {
-  type Item = (Duration, E);
-  fn next(&mut self) -> Option<(Duration, E)> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
     loop {
       match self.source.next() {
         Some((d, e)) if d >= self.duration => {
@@ -197,41 +1190,283 @@ impl<'a, E> Iterator for Drop<'a, E> {
   }
 }
 
-enum Lazy<'a, E, F> {
+struct FilterMap<I, F> {
+  source: I,
+  fun: F,
+  pending: Duration,
+}
+impl<I, E, EE, F> Iterator for FilterMap<I, F>
+where
+  I: Iterator<Item = (Duration, E)>,
+  F: FnMut(E) -> Option<EE>,
+{
+  type Item = (Duration, EE);
+  fn next(&mut self) -> Option<(Duration, EE)> {
+    loop {
+      let (d, e) = self.source.next()?;
+      self.pending += d;
+      if let Some(ee) = (self.fun)(e) {
+        return Some((std::mem::take(&mut self.pending), ee));
+      }
+    }
+  }
+}
+
+struct Tee<'a, E> {
+  source: Events<'a, E>,
+  // Events read from the source but not yet consumed by each branch, or None
+  // once the branch has been dropped.
+  queues: Vec<Option<VecDeque<Event<E>>>>,
+}
+struct TeeBranch<'a, E> {
+  shared: Rc<RefCell<Tee<'a, E>>>,
+  index: usize,
+}
+impl<'a, E: Clone> Iterator for TeeBranch<'a, E> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
+    let mut tee = self.shared.borrow_mut();
+    let queue = tee.queues[self.index].as_mut().unwrap();
+    if let Some(event) = queue.pop_front() {
+      return Some(event);
+    }
+    let event = tee.source.next()?;
+    for (i, queue) in tee.queues.iter_mut().enumerate() {
+      match queue {
+        Some(queue) if i != self.index => queue.push_back(event.clone()),
+        _ => (),
+      }
+    }
+    Some(event)
+  }
+}
+impl<'a, E> std::ops::Drop for TeeBranch<'a, E> {
+  fn drop(&mut self) {
+    self.shared.borrow_mut().queues[self.index] = None;
+  }
+}
+
+struct Partition<'a, E, P> {
+  source: Events<'a, E>,
+  predicate: P,
+  // Time of the last event read from the source.
+  now: Duration,
+  // Events read but not yet consumed, with their times, for the matching
+  // side and the other side, or None once that side has been dropped.
+  queues: [Option<VecDeque<(Duration, E)>>; 2],
+}
+struct PartitionBranch<'a, E, P> {
+  shared: Rc<RefCell<Partition<'a, E, P>>>,
+  side: usize,
+  // Time of the last event or rest this side returned.
+  last: Duration,
+}
+impl<'a, E, P> Iterator for PartitionBranch<'a, E, P>
+where
+  P: FnMut(&E) -> bool,
+{
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
+    let mut partition = self.shared.borrow_mut();
+    let queue = partition.queues[self.side].as_mut().unwrap();
+    let (time, e) = match queue.pop_front() {
+      Some((time, e)) => (time, Some(e)),
+      None => {
+        // Events read for the other side are rests for this one.
+        let (d, e) = partition.source.next()?;
+        partition.now += d;
+        let now = partition.now;
+        match e {
+          Some(e) if (partition.predicate)(&e) != (self.side == 0) => {
+            if let Some(queue) = &mut partition.queues[1 - self.side] {
+              queue.push_back((now, e));
+            }
+            (now, None)
+          }
+          e => (now, e),
+        }
+      }
+    };
+    let delay = time - self.last;
+    self.last = time;
+    Some((delay, e))
+  }
+}
+impl<'a, E, P> std::ops::Drop for PartitionBranch<'a, E, P> {
+  fn drop(&mut self) {
+    self.shared.borrow_mut().queues[self.side] = None;
+  }
+}
+
+struct Chunks<'a, E> {
+  source: Events<'a, E>,
+  size: usize,
+  // Events read towards the next group.
+  chunk: Vec<E>,
+}
+impl<'a, E> Iterator for Chunks<'a, E> {
+  type Item = (Duration, Option<Vec<E>>);
+  fn next(&mut self) -> Option<(Duration, Option<Vec<E>>)> {
+    // Each event read before its group is complete becomes a rest.
+    let Some((d, e)) = self.source.next() else {
+      if self.chunk.is_empty() {
+        return None;
+      }
+      return Some((
+        Duration::from_secs(0),
+        Some(std::mem::take(&mut self.chunk)),
+      ));
+    };
+    self.chunk.extend(e);
+    if self.chunk.len() < self.size {
+      return Some((d, None));
+    }
+    Some((d, Some(std::mem::take(&mut self.chunk))))
+  }
+}
+
+struct GroupSimultaneous<'a, E>(std::iter::Peekable<Events<'a, E>>);
+impl<'a, E> Iterator for GroupSimultaneous<'a, E> {
+  type Item = (Duration, Option<Vec<E>>);
+  fn next(&mut self) -> Option<(Duration, Option<Vec<E>>)> {
+    let (delay, e) = self.0.next()?;
+    let Some(e) = e else {
+      return Some((delay, None));
+    };
+    let mut group = vec![e];
+    while let Some((_, e)) = self.0.next_if(|(d, _)| d.is_zero()) {
+      group.extend(e);
+    }
+    Some((delay, Some(group)))
+  }
+}
+
+struct Windows<'a, E> {
+  source: Events<'a, E>,
+  // Next event or rest, with its time measured from the start of the next
+  // window.
+  head: Option<(Duration, Option<E>)>,
+  window: Duration,
+  started: bool,
+}
+impl<'a, E> Iterator for Windows<'a, E> {
+  type Item = (Duration, Option<Vec<E>>);
+  fn next(&mut self) -> Option<(Duration, Option<Vec<E>>)> {
+    let mut head = self.head.take().or_else(|| self.source.next())?;
+    let mut group = Vec::new();
+    loop {
+      let (time, e) = head;
+      if time >= self.window {
+        self.head = Some((time - self.window, e));
+        break;
+      }
+      group.extend(e);
+      match self.source.next() {
+        Some((d, e)) => head = (time + d, e),
+        None => break,
+      }
+    }
+    let delay = if self.started {
+      self.window
+    } else {
+      Duration::from_secs(0)
+    };
+    self.started = true;
+    Some((delay, Some(group)))
+  }
+}
+
+struct Lazy<'a, E, F> {
+  state: LazyState<'a, E, F>,
+  endless: Rc<Cell<bool>>,
+}
+enum LazyState<'a, E, F> {
   Before(Option<F>),
-  After(Box<dyn Iterator<Item = (Duration, E)> + 'a>),
+  After(Stream<'a, E>),
 }
 impl<'a, E: 'a, F> Iterator for Lazy<'a, E, F>
 where
   F: FnOnce() -> Stream<'a, E> + 'a,
 {
-  type Item = (Duration, E);
-  fn next(&mut self) -> Option<(Duration, E)> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
     loop {
-      match self {
-        Lazy::Before(opt) => {
-          *self = Lazy::After(opt.take().unwrap()().into_iter());
+      match &mut self.state {
+        LazyState::Before(opt) => {
+          self.state = LazyState::After(opt.take().unwrap()());
+        }
+        LazyState::After(stream) => {
+          let next = stream.events.next();
+          if next.is_none() {
+            self.endless.set(stream.endless.get());
+          }
+          return next;
         }
-        Lazy::After(iter) => return iter.next(),
       }
     }
   }
 }
 
-struct Merge<'a, E> {
-  head1: Option<(Duration, E)>,
-  head2: Option<(Duration, E)>,
-  source1: Box<dyn Iterator<Item = (Duration, E)> + 'a>,
-  source2: Box<dyn Iterator<Item = (Duration, E)> + 'a>,
+struct MergeAll<'a, E> {
+  sources: Vec<Events<'a, E>>,
+  // Next event or rest from each source, if it has one. Its time is in the
+  // heap.
+  heads: Vec<Option<Option<E>>>,
+  heap: BinaryHeap<Reverse<(Duration, usize)>>,
+  now: Duration,
+  ended: Vec<Rc<Cell<bool>>>,
+  endless: Rc<Cell<bool>>,
 }
-impl<'a, E: 'a> Iterator for Merge<'a, E> {
-  type Item = (Duration, E);
-  fn next(&mut self) -> Option<(Duration, E)> {
+impl<'a, E> Iterator for MergeAll<'a, E> {
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
+    let Some(Reverse((time, index))) = self.heap.pop() else {
+      self
+        .endless
+        .set(self.ended.iter().any(|endless| endless.get()));
+      return None;
+    };
+    let e = self.heads[index].take().unwrap();
+    if let Some((d, next)) = self.sources[index].next() {
+      self.heads[index] = Some(next);
+      self.heap.push(Reverse((time + d, index)));
+    }
+    let delay = time - self.now;
+    self.now = time;
+    Some((delay, e))
+  }
+}
+
+struct Merge<'a, E, F> {
+  head1: Option<(Duration, Option<E>)>,
+  head2: Option<(Duration, Option<E>)>,
+  source1: Events<'a, E>,
+  source2: Events<'a, E>,
+  first: F,
+  ended: [Rc<Cell<bool>>; 2],
+  endless: Rc<Cell<bool>>,
+}
+impl<'a, E: 'a, F> Iterator for Merge<'a, E, F>
+where
+  F: FnMut(&E, &E) -> bool,
+{
+  type Item = (Duration, Option<E>);
+  fn next(&mut self) -> Option<(Duration, Option<E>)> {
     match (self.head1.as_mut(), self.head2.as_mut()) {
+      (None, None) => {
+        self
+          .endless
+          .set(self.ended.iter().any(|endless| endless.get()));
+        None
+      }
       (_, None) => std::mem::replace(&mut self.head1, self.source1.next()),
       (None, _) => std::mem::replace(&mut self.head2, self.source2.next()),
-      (Some((d1, _)), Some((d2, _))) => {
-        if *d1 <= *d2 {
+      (Some((d1, e1)), Some((d2, e2))) => {
+        let self_first = match (e1, e2) {
+          (Some(e1), Some(e2)) => (self.first)(e1, e2),
+          _ => true,
+        };
+        if *d1 < *d2 || (*d1 == *d2 && self_first) {
           *d2 -= *d1;
           std::mem::replace(&mut self.head1, self.source1.next())
         } else {
@@ -242,3 +1477,818 @@ impl<'a, E: 'a> Iterator for Merge<'a, E> {
     }
   }
 }
+
+#[test]
+fn test_scan() {
+  let ms = Duration::from_millis;
+  let notes = Stream::from_iter(vec![(ms(0), 60), (ms(10), 64), (ms(10), 62)]);
+  assert_eq!(
+    notes
+      .scan(None, |prev: &mut Option<i64>, note| {
+        let interval = prev.map(|p| note - p);
+        *prev = Some(note);
+        interval
+      })
+      .into_iter()
+      .collect::<Vec<_>>(),
+    vec![(ms(0), None), (ms(10), Some(4)), (ms(10), Some(-2))]
+  );
+}
+
+#[test]
+fn test_quantize() {
+  let ms = Duration::from_millis;
+  let stream = Stream::from_iter(vec![
+    (ms(0), 1),
+    (ms(130), 2),
+    (ms(130), 3),
+    (ms(130), 4),
+    (ms(10), 5),
+  ]);
+  assert_eq!(
+    stream.quantize(ms(100)).collect_timed(),
+    vec![
+      (ms(0), 1),
+      (ms(100), 2),
+      (ms(200), 3),
+      (ms(100), 4),
+      (ms(0), 5)
+    ]
+  );
+  // More grid steps than fit in a u32.
+  let fine = Stream::from_iter(vec![(ms(4200), 1), (ms(200), 2)]);
+  assert_eq!(
+    fine.quantize(Duration::from_nanos(1)).collect_timed(),
+    vec![(ms(4200), 1), (ms(200), 2)]
+  );
+}
+
+#[test]
+fn test_swing() {
+  let ms = Duration::from_millis;
+  let eighths = || {
+    Stream::from_iter(vec![
+      (ms(0), 0),
+      (ms(100), 1),
+      (ms(100), 2),
+      (ms(100), 3),
+      (ms(100), 4),
+    ])
+  };
+  let times = |stream: Stream<'static, i32>| {
+    let mut now = ms(0);
+    stream
+      .into_iter()
+      .map(|(d, _)| {
+        now += d;
+        now.as_millis()
+      })
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    times(eighths().swing(ms(100), 0.5)),
+    vec![0, 100, 200, 300, 400]
+  );
+  assert_eq!(
+    times(eighths().swing(ms(100), 0.6)),
+    vec![0, 120, 200, 320, 400]
+  );
+  assert_eq!(
+    times(Stream::from_iter(vec![(ms(50), 0), (ms(100), 1)]).swing(ms(100), 0.6)),
+    vec![60, 160]
+  );
+}
+
+#[test]
+fn test_humanize() {
+  let ms = Duration::from_millis;
+  let stream = || Stream::from_iter((0..100).map(|i| (ms(100), i)));
+  let humanized = || {
+    stream()
+      .humanize(Seed::new("test"), ms(5))
+      .into_iter()
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(humanized(), humanized());
+  let mut now = ms(0);
+  let offsets: Vec<f64> = humanized()
+    .into_iter()
+    .enumerate()
+    .map(|(i, (d, _))| {
+      now += d;
+      now.as_secs_f64() - (i + 1) as f64 * 0.1
+    })
+    .collect();
+  assert!(offsets.iter().any(|&x| x != 0.0));
+  assert!(offsets.iter().all(|x| x.abs() < 0.03));
+}
+
+#[test]
+fn test_poisson() {
+  let delays = |stream: Stream<'static, ()>, n| {
+    stream
+      .into_iter()
+      .take(n)
+      .map(|(d, ())| d.as_secs_f64())
+      .collect::<Vec<_>>()
+  };
+  let poisson = delays(Stream::poisson(4.0, Seed::new("test")), 10_000);
+  assert_eq!(
+    poisson,
+    delays(Stream::poisson(4.0, Seed::new("test")), 10_000)
+  );
+  let mean = poisson.iter().sum::<f64>() / poisson.len() as f64;
+  assert!((mean - 0.25).abs() < 0.01, "mean delay {}", mean);
+
+  // Gaps between bursts average a second, while events within bursts are
+  // about a microsecond apart, so long delays mark the start of each burst.
+  let bursts = delays(Stream::bursts(1.0, 1e6, 2..=4, Seed::new("test")), 1_000);
+  let starts: Vec<usize> = (0..bursts.len()).filter(|&i| bursts[i] > 1e-3).collect();
+  let sizes: Vec<usize> = starts.windows(2).map(|w| w[1] - w[0]).collect();
+  assert!(sizes.iter().all(|size| (2..=4).contains(size)));
+  assert!(sizes.contains(&2));
+  assert!(sizes.contains(&4));
+}
+
+#[test]
+fn test_repeat_every() {
+  let ms = Duration::from_millis;
+  let phrase = || Stream::from_iter(vec![(ms(0), 'a'), (ms(30), 'b'), (ms(50), 'c')]);
+  assert_eq!(
+    phrase().repeat_every(ms(40)).take_n(5).collect_timed(),
+    vec![
+      (ms(0), 'a'),
+      (ms(30), 'b'),
+      (ms(10), 'a'),
+      (ms(30), 'b'),
+      (ms(10), 'a')
+    ]
+  );
+  // The source is read no further ahead than the events played.
+  let read = Rc::new(RefCell::new(0));
+  let counter = read.clone();
+  let mut looped = Stream::immediate(())
+    .repeat_every(ms(10))
+    .inspect_timed(move |_, _| *counter.borrow_mut() += 1)
+    .repeat_every(ms(1000))
+    .into_iter();
+  looped.next();
+  assert_eq!(*read.borrow(), 1);
+  // The first pass ends once an event past the interval is read.
+  assert_eq!(looped.take(199).count(), 199);
+  assert_eq!(*read.borrow(), 102);
+}
+
+#[test]
+fn test_repeat_n() {
+  let ms = Duration::from_millis;
+  let phrase = || Stream::from_iter(vec![(ms(0), 'a'), (ms(30), 'b'), (ms(50), 'c')]);
+  assert_eq!(
+    phrase().repeat_n(ms(40), 2).collect_timed(),
+    vec![(ms(0), 'a'), (ms(30), 'b'), (ms(10), 'a'), (ms(30), 'b')]
+  );
+  assert_eq!(phrase().repeat_n(ms(40), 0).count_events(), 0);
+  assert_eq!(
+    phrase().cycle().into_iter().take(5).collect::<Vec<_>>(),
+    vec![
+      (ms(0), 'a'),
+      (ms(30), 'b'),
+      (ms(50), 'c'),
+      (ms(0), 'a'),
+      (ms(30), 'b')
+    ]
+  );
+  assert_eq!(Stream::<()>::empty().cycle().count_events(), 0);
+}
+
+#[test]
+fn test_merge_by() {
+  let ms = Duration::from_millis;
+  let a = || Stream::from_iter(vec![(ms(0), 3), (ms(10), 5), (ms(0), 6)]);
+  let b = || Stream::from_iter(vec![(ms(0), 1), (ms(10), 4), (ms(5), 2)]);
+  assert_eq!(
+    a().merge(b()).collect_timed(),
+    vec![
+      (ms(0), 3),
+      (ms(0), 1),
+      (ms(10), 5),
+      (ms(0), 6),
+      (ms(0), 4),
+      (ms(5), 2)
+    ]
+  );
+  assert_eq!(
+    a()
+      .merge_by(b(), |x, y| x <= y)
+      .into_iter()
+      .collect::<Vec<_>>(),
+    vec![
+      (ms(0), 1),
+      (ms(0), 3),
+      (ms(10), 4),
+      (ms(0), 5),
+      (ms(0), 6),
+      (ms(5), 2)
+    ]
+  );
+  assert_eq!(
+    Stream::merge_all_by(vec![a(), b(), Stream::immediate(0)], |x, y| x <= y)
+      .into_iter()
+      .take(3)
+      .collect::<Vec<_>>(),
+    vec![(ms(0), 0), (ms(0), 1), (ms(0), 3)]
+  );
+}
+
+#[test]
+fn test_windows() {
+  let ms = Duration::from_millis;
+  let stream = Stream::from_iter(vec![
+    (ms(0), 1),
+    (ms(40), 2),
+    (ms(60), 3),
+    (ms(10), 4),
+    (ms(200), 5),
+  ]);
+  assert_eq!(
+    stream.windows(ms(100)).collect_timed(),
+    vec![
+      (ms(0), vec![1, 2]),
+      (ms(100), vec![3, 4]),
+      (ms(100), vec![]),
+      (ms(100), vec![5]),
+    ]
+  );
+  assert_eq!(Stream::<()>::empty().windows(ms(100)).count_events(), 0);
+}
+
+#[test]
+fn test_chunks() {
+  let ms = Duration::from_millis;
+  let stream = Stream::from_iter(vec![
+    (ms(10), 1),
+    (ms(40), 2),
+    (ms(60), 3),
+    (ms(10), 4),
+    (ms(200), 5),
+  ]);
+  assert_eq!(
+    stream.chunks(2).collect_timed(),
+    vec![
+      (ms(50), vec![1, 2]),
+      (ms(70), vec![3, 4]),
+      (ms(200), vec![5])
+    ]
+  );
+  assert_eq!(Stream::<()>::empty().chunks(4).count_events(), 0);
+  // A held value deduplicates to rests, which still let take end.
+  let held = Stream::from_iter(std::iter::repeat((ms(10), 64))).dedup();
+  assert_eq!(held.chunks(2).take(ms(50)).count_events(), 0);
+}
+
+#[test]
+fn test_group_simultaneous() {
+  let ms = Duration::from_millis;
+  let events = vec![
+    (ms(0), 1),
+    (ms(0), 2),
+    (ms(50), 3),
+    (ms(20), 4),
+    (ms(0), 5),
+    (ms(0), 6),
+  ];
+  let groups = Stream::from_iter(events.clone())
+    .group_simultaneous()
+    .collect_timed();
+  assert_eq!(
+    groups,
+    vec![
+      (ms(0), vec![1, 2]),
+      (ms(50), vec![3]),
+      (ms(20), vec![4, 5, 6])
+    ]
+  );
+  assert_eq!(Stream::from_iter(groups).flatten().collect_timed(), events);
+}
+
+#[test]
+fn test_dedup() {
+  let ms = Duration::from_millis;
+  let values = Stream::from_iter(vec![
+    (ms(0), 64),
+    (ms(10), 64),
+    (ms(10), 64),
+    (ms(10), 70),
+    (ms(10), 64),
+    (ms(10), 64),
+  ]);
+  assert_eq!(
+    values.dedup().collect_timed(),
+    vec![(ms(0), 64), (ms(30), 70), (ms(10), 64)]
+  );
+  assert_eq!(Stream::<u8>::empty().dedup().count_events(), 0);
+  let held = Var::constant(64u8).sample_every(ms(10)).dedup().take_n(1);
+  assert_eq!(held.collect_timed(), vec![(ms(0), 64)]);
+  let repeats = Stream::from_iter(std::iter::repeat((ms(10), 1))).dedup();
+  assert_eq!(repeats.take(ms(50)).collect_timed(), vec![(ms(10), 1)]);
+}
+
+#[test]
+fn test_intersperse() {
+  let ms = Duration::from_millis;
+  let hits = Stream::from_iter(vec![
+    (ms(0), "kick"),
+    (ms(500), "snare"),
+    (ms(0), "crash"),
+    (ms(250), "kick"),
+  ]);
+  assert_eq!(
+    hits.intersperse("hat").collect_timed(),
+    vec![
+      (ms(0), "kick"),
+      (ms(250), "hat"),
+      (ms(250), "snare"),
+      (ms(0), "crash"),
+      (ms(125), "hat"),
+      (ms(125), "kick"),
+    ]
+  );
+  let mut count = 0;
+  let numbered = Stream::immediate(0)
+    .repeat_n(ms(100), 3)
+    .intersperse_with(move || {
+      count += 1;
+      count
+    });
+  assert_eq!(
+    numbered.collect_timed(),
+    vec![
+      (ms(0), 0),
+      (ms(50), 1),
+      (ms(50), 0),
+      (ms(50), 2),
+      (ms(50), 0)
+    ]
+  );
+}
+
+#[test]
+fn test_metrics() {
+  let ms = Duration::from_millis;
+  let mut seen = Vec::new();
+  let stream = Stream::from_timestamps(vec![(ms(100), 'a'), (ms(200), 'b'), (ms(1700), 'c')]);
+  let events = stream
+    .inspect_timed(|time, &e| seen.push((time, e)))
+    .collect_timed();
+  assert_eq!(events.len(), 3);
+  assert_eq!(seen, vec![(ms(100), 'a'), (ms(200), 'b'), (ms(1700), 'c')]);
+
+  let reports = Rc::new(RefCell::new(Vec::new()));
+  let sink = reports.clone();
+  Stream::from_timestamps(vec![(ms(100), 'a'), (ms(200), 'b'), (ms(1700), 'c')])
+    .metrics(ms(500), move |metrics| sink.borrow_mut().push(*metrics))
+    .count_events();
+  let reports = reports.borrow();
+  assert_eq!(reports.len(), 3);
+  assert_eq!((reports[0].events, reports[0].largest_gap), (2, ms(300)));
+  assert_eq!(reports[0].rate(), 4.0);
+  assert_eq!((reports[1].events, reports[1].largest_gap), (0, ms(500)));
+  assert_eq!(reports[2].start, ms(1000));
+  assert_eq!(reports[2].largest_gap, ms(500));
+
+  // Reports come as time passes, not when the next event arrives.
+  let reports = Rc::new(RefCell::new(Vec::new()));
+  let sink = reports.clone();
+  let mut played = Stream::from_timestamps(vec![(ms(100), 'a'), (ms(1700), 'c')])
+    .metrics(ms(500), move |metrics| sink.borrow_mut().push(*metrics))
+    .merge(Stream::from_timestamps(vec![
+      (ms(1200), 'x'),
+      (ms(1300), 'y'),
+    ]))
+    .into_iter();
+  played.next();
+  assert_eq!(reports.borrow().len(), 0);
+  played.next();
+  assert_eq!(reports.borrow().len(), 2);
+  assert_eq!(reports.borrow()[1].start, ms(500));
+  played.count();
+  assert_eq!(reports.borrow().len(), 3);
+}
+
+#[test]
+fn test_normalize() {
+  let ms = Duration::from_millis;
+  let stream = || {
+    Stream::from_iter(vec![
+      (ms(0), 'a'),
+      (ms(10), 'b'),
+      (ms(20), 'c'),
+      (ms(0), 'd'),
+    ])
+  };
+  assert_eq!(
+    stream().merge(stream()).normalize().collect_timed(),
+    stream().collect_timed()
+  );
+  assert_eq!(
+    Stream::from_iter(vec![(ms(5), 'a'), (ms(0), 'b'), (ms(0), 'a'), (ms(0), 'a')])
+      .normalize()
+      .collect_timed(),
+    vec![(ms(5), 'a'), (ms(0), 'b')]
+  );
+  // Empty windows keep their time through flatten.
+  let sparse = Stream::from_iter(vec![(ms(0), 1), (ms(250), 2)]);
+  assert_eq!(
+    sparse.windows(ms(100)).flatten().collect_timed(),
+    vec![(ms(0), 1), (ms(200), 2)]
+  );
+}
+
+#[test]
+fn test_blend() {
+  let ms = Duration::from_millis;
+  let a = || Stream::immediate('a').repeat_every(ms(100));
+  let b = || Stream::immediate('b').repeat_every(ms(100));
+  let blended = |bias| {
+    a()
+      .blend(b(), bias, Seed::new("test"))
+      .take_n(110)
+      .collect_timed()
+  };
+  let only_a = blended(Var::constant(0.0));
+  assert!(only_a.iter().all(|&(_, e)| e == 'a'));
+  assert!(only_a.iter().skip(1).all(|&(d, _)| d == ms(100)));
+  assert!(blended(Var::constant(1.0)).iter().all(|&(_, e)| e == 'b'));
+
+  // Ramping the bias morphs from a to b.
+  let ramp = Var::from_updates(
+    0.0,
+    Stream::from_iter((1..=10).map(|i| (ms(1000), i as f64 / 10.0))),
+  );
+  let morph = blended(ramp);
+  let bs = |range: std::ops::Range<usize>| morph[range].iter().filter(|&&(_, e)| e == 'b').count();
+  assert_eq!(bs(0..10), 0);
+  assert!(bs(10..50) > 0 && bs(10..50) < 40);
+  assert_eq!(bs(100..110), 10);
+
+  let finite = Stream::from_iter(vec![(ms(0), 'a'), (ms(100), 'a')]);
+  let undefined = Var::from_fn(ms(10), |_| f64::NAN);
+  assert_eq!(
+    finite
+      .blend(
+        Stream::immediate('b').delay(ms(50)),
+        undefined,
+        Seed::new("test")
+      )
+      .collect_timed(),
+    vec![(ms(0), 'a'), (ms(100), 'a')]
+  );
+}
+
+#[test]
+fn test_partition() {
+  let ms = Duration::from_millis;
+  let stream = Stream::from_iter(vec![
+    (ms(0), 1),
+    (ms(10), 2),
+    (ms(20), 3),
+    (ms(30), 4),
+    (ms(0), 5),
+  ]);
+  let (odd, even) = stream.partition(|n| n % 2 == 1);
+  // Consuming one side first buffers the other.
+  assert_eq!(even.collect_timed(), vec![(ms(10), 2), (ms(50), 4)]);
+  assert_eq!(
+    odd.collect_timed(),
+    vec![(ms(0), 1), (ms(30), 3), (ms(30), 5)]
+  );
+
+  // Nothing is kept for a dropped side.
+  let shared = Rc::new(());
+  let source = shared.clone();
+  let stream = Stream::from_iter((0..100).map(move |i| (ms(1), (i, source.clone()))));
+  let (even, _) = stream.partition(|(i, _)| i % 2 == 0);
+  let mut even = even.into_iter();
+  assert_eq!(even.by_ref().take(49).count(), 49);
+  assert_eq!(Rc::strong_count(&shared), 2);
+}
+
+#[test]
+fn test_tee() {
+  let ms = Duration::from_millis;
+  let events = vec![(ms(0), 1), (ms(10), 2), (ms(20), 3)];
+  let mut streams = Stream::from_iter(events.clone()).tee(3);
+  let c = streams.pop().unwrap();
+  let mut b = streams.pop().unwrap();
+  let a = streams.pop().unwrap();
+  assert_eq!(b.next(), Some((ms(0), 1)));
+  assert_eq!(a.collect_timed(), events);
+  assert_eq!(b.collect_timed(), events[1..].to_vec());
+  assert_eq!(c.collect_timed(), events);
+
+  // Nothing is kept for a dropped stream.
+  let shared = Rc::new(());
+  let source = shared.clone();
+  let stream = Stream::from_iter((0..100).map(move |_| (ms(1), source.clone())));
+  let mut streams = stream.tee(2);
+  streams.pop();
+  let mut kept = streams.pop().unwrap().into_iter();
+  assert_eq!(kept.by_ref().take(99).count(), 99);
+  assert_eq!(Rc::strong_count(&shared), 2);
+}
+
+#[test]
+fn test_timestamps() {
+  let ms = Duration::from_millis;
+  let events = vec![(ms(0), 'a'), (ms(10), 'b'), (ms(10), 'c'), (ms(25), 'd')];
+  let times: Vec<_> = Stream::from_iter(events.clone()).timestamps().collect();
+  assert_eq!(
+    times,
+    vec![(ms(0), 'a'), (ms(10), 'b'), (ms(20), 'c'), (ms(45), 'd')]
+  );
+  let delays = Stream::from_timestamps(times).collect_timed();
+  assert_eq!(delays, events);
+}
+
+#[test]
+#[should_panic(expected = "timestamps must not decrease")]
+fn test_from_timestamps_decreasing() {
+  let ms = Duration::from_millis;
+  Stream::from_timestamps(vec![(ms(10), ()), (ms(5), ())])
+    .into_iter()
+    .for_each(drop);
+}
+
+#[test]
+fn test_peekable() {
+  let ms = Duration::from_millis;
+  let mut stream = Stream::from_iter(vec![(ms(5), 'a'), (ms(10), 'b')]).peekable();
+  assert_eq!(stream.peek_delay(), Some(ms(5)));
+  assert_eq!(stream.peek(), Some(&(ms(5), 'a')));
+  assert_eq!(stream.next(), Some((ms(5), 'a')));
+  assert_eq!(stream.peek_delay(), Some(ms(10)));
+  let rest = stream.stream().collect_timed();
+  assert_eq!(rest, vec![(ms(10), 'b')]);
+}
+
+#[test]
+fn test_collect_timed() {
+  let ms = Duration::from_millis;
+  let events = vec![(ms(5), 'a'), (ms(0), 'b'), (ms(20), 'c')];
+  let stream = || Stream::from_iter(events.clone());
+  assert_eq!(stream().collect_timed(), events);
+  assert_eq!(stream().count_events(), 3);
+  assert_eq!(stream().total_duration(), ms(25));
+  assert_eq!(Stream::<()>::empty().total_duration(), ms(0));
+}
+
+#[test]
+fn test_fold() {
+  let ms = Duration::from_millis;
+  let stream = || Stream::from_iter(vec![(ms(0), 60), (ms(10), 64), (ms(30), 62)]);
+  let (sum, _) = stream().fold((0, None), |(sum, prev): (i32, Option<i32>), note| {
+    (sum + prev.map_or(0, |p| (note - p).abs()), Some(note))
+  });
+  assert_eq!(sum, 6);
+  let last_high = stream().fold_timed(
+    None,
+    |last, time, note| if note > 61 { Some(time) } else { last },
+  );
+  assert_eq!(last_high, Some(ms(40)));
+}
+
+#[test]
+fn test_timed_iterator() {
+  let ms = Duration::from_millis;
+  let events = || (0..100).map(move |i| (ms(i % 7), i));
+  let boxed = Stream::from_iter(events())
+    .filter_map(|i| if i % 3 == 0 { Some(i * 2) } else { None })
+    .scale_time(0.5)
+    .map(|i| i + 1)
+    .collect_timed();
+  let unboxed = events()
+    .filter_map_events(|i| if i % 3 == 0 { Some(i * 2) } else { None })
+    .scale_time(0.5)
+    .map_events(|i| i + 1)
+    .boxed()
+    .collect_timed();
+  assert_eq!(unboxed, boxed);
+  assert_eq!(unboxed.len(), 34);
+}
+
+#[test]
+fn test_at() {
+  let ms = Duration::from_millis;
+  assert_eq!(Stream::at(ms(30), 'a').collect_timed(), vec![(ms(30), 'a')]);
+  assert_eq!(
+    Stream::at(ms(30), 'b')
+      .merge(Stream::at(ms(10), 'a'))
+      .collect_timed(),
+    vec![(ms(10), 'a'), (ms(20), 'b')]
+  );
+}
+
+#[test]
+fn test_merge_all() {
+  let ms = Duration::from_millis;
+  let voices: Vec<_> = (0..100u64)
+    .map(|i| Stream::at(ms(i % 7), i).repeat_n(ms(10), 3))
+    .collect();
+  let merged = Stream::merge_all(voices).timestamps().collect::<Vec<_>>();
+  assert_eq!(merged.len(), 300);
+  // In time order, and by voice at the same instant.
+  for pair in merged.windows(2) {
+    let ((t1, i1), (t2, i2)) = (pair[0], pair[1]);
+    assert!(t1 < t2 || (t1 == t2 && i1 < i2));
+  }
+  assert_eq!(merged[0], (ms(0), 0));
+  assert_eq!(merged[299], (ms(26), 97));
+  assert_eq!(Stream::<()>::merge_all(vec![]).count_events(), 0);
+}
+
+#[test]
+fn test_merge_labeled() {
+  let ms = Duration::from_millis;
+  let voice = |name| Stream::immediate(name).repeat_n(ms(100), 2);
+  assert_eq!(
+    voice("treble")
+      .merge_labeled(Stream::at(ms(50), "bass"))
+      .collect_timed(),
+    vec![
+      (ms(0), (SourceId(0), "treble")),
+      (ms(50), (SourceId(1), "bass")),
+      (ms(50), (SourceId(0), "treble")),
+    ]
+  );
+  let merged = Stream::merge_all_labeled(vec![voice("a"), voice("b"), voice("c")]);
+  let muted: Vec<_> = merged
+    .filter(|&(source, _)| source != SourceId(1))
+    .map(|(_, e)| e)
+    .collect_timed();
+  assert_eq!(
+    muted,
+    vec![(ms(0), "a"), (ms(0), "c"), (ms(100), "a"), (ms(0), "c")]
+  );
+}
+
+#[test]
+fn test_never() {
+  let ms = Duration::from_millis;
+  let voice = || Stream::from_iter(vec![(ms(10), 1), (ms(10), 2)]);
+  let merged = Stream::merge_all(vec![voice(), Stream::never(), voice().map(|n| n * 10)]);
+  assert_eq!(
+    merged.take(ms(50)).collect_timed(),
+    vec![(ms(10), 1), (ms(0), 10), (ms(10), 2), (ms(0), 20)]
+  );
+  let after = voice().pending().chain(voice()).take(ms(100));
+  assert_eq!(after.collect_timed(), voice().collect_timed());
+  let later = Stream::never().merge(Stream::empty().chain_at(ms(30), voice()));
+  assert_eq!(later.take(ms(45)).collect_timed(), vec![(ms(40), 1)]);
+  assert_eq!(Stream::<()>::never().take(ms(10)).count_events(), 0);
+}
+
+#[test]
+fn test_rests() {
+  let ms = Duration::from_millis;
+  // Dropped events still let time pass, so take ends on time rather than
+  // waiting for an event that is kept.
+  let none = || Stream::from_iter(std::iter::repeat((ms(10), 1))).filter(|_| false);
+  assert_eq!(none().take(ms(50)).count_events(), 0);
+  assert_eq!(none().filter_map(Some).take(ms(50)).count_events(), 0);
+  let shared = Var::from_updates(0, none()).share();
+  assert_eq!(shared.var().updates().take(ms(50)).count_events(), 1);
+  // They are left out when iterating, their delays carried on.
+  let kept = Stream::from_iter(vec![(ms(10), 1), (ms(10), 2), (ms(10), 3)]).filter(|&n| n != 2);
+  assert_eq!(kept.collect_timed(), vec![(ms(10), 1), (ms(20), 3)]);
+}
+
+#[test]
+fn test_never_through_combinators() {
+  let ms = Duration::from_millis;
+  let never = Stream::<u32>::never;
+  let events = |stream: Stream<u32>| stream.take(ms(10)).count_events();
+  let groups = |stream: Stream<Vec<u32>>| stream.take(ms(10)).count_events();
+  assert_eq!(events(never().scale_time(1.0)), 0);
+  assert_eq!(events(never().quantize(ms(5))), 0);
+  assert_eq!(events(never().swing(ms(5), 0.6)), 0);
+  assert_eq!(events(never().shift_each(ms(5))), 0);
+  assert_eq!(events(never().push(ms(5))), 0);
+  assert_eq!(events(never().advance(ms(5))), 0);
+  assert_eq!(events(never().humanize(Seed::new(1), ms(1))), 0);
+  assert_eq!(events(never().dedup()), 0);
+  assert_eq!(events(never().coalesce(|a, b| a + b)), 0);
+  assert_eq!(events(never().drop(ms(5))), 0);
+  assert_eq!(events(never().map(|n| vec![n]).flatten()), 0);
+  assert_eq!(events(never().intersperse(0)), 0);
+  assert_eq!(events(never().normalize()), 0);
+  assert_eq!(events(never().stretch_to(ms(5))), 0);
+  assert_eq!(events(never().repeat_every(ms(5))), 0);
+  assert_eq!(events(never().cycle()), 0);
+  assert_eq!(events(never().take_n(3)), 0);
+  assert_eq!(events(never().take_while(|_| true)), 0);
+  assert_eq!(events(never().chain(Stream::empty())), 0);
+  assert_eq!(events(never().peekable().stream()), 0);
+  assert_eq!(events(Stream::lazy(never)), 0);
+  assert_eq!(groups(never().chunks(2)), 0);
+  assert_eq!(groups(never().group_simultaneous()), 0);
+  assert_eq!(groups(never().windows(ms(5))), 0);
+  for branch in never().tee(2) {
+    assert_eq!(events(branch), 0);
+  }
+  let (even, odd) = never().partition(|n| n % 2 == 0);
+  assert_eq!(events(even) + events(odd), 0);
+  let shared = Var::from_updates(0, never()).share();
+  assert_eq!(events(shared.var().updates().skip_n(1)), 0);
+}
+
+#[test]
+fn test_shift_each() {
+  let ms = Duration::from_millis;
+  let stream = || Stream::from_iter(vec![(ms(0), 'a'), (ms(100), 'b'), (ms(100), 'c')]);
+  assert_eq!(
+    stream().shift_each(ms(20)).collect_timed(),
+    vec![(ms(20), 'a'), (ms(120), 'b'), (ms(120), 'c')]
+  );
+  assert_eq!(
+    stream().shift_each(ms(20)).push(ms(20)).collect_timed(),
+    stream().collect_timed()
+  );
+  assert_eq!(
+    stream().push(ms(150)).collect_timed(),
+    vec![(ms(0), 'a'), (ms(0), 'b'), (ms(0), 'c')]
+  );
+  assert_eq!(
+    stream().delay(ms(50)).advance(ms(20)).collect_timed(),
+    vec![(ms(30), 'a'), (ms(100), 'b'), (ms(100), 'c')]
+  );
+  assert_eq!(
+    stream().advance(ms(150)).collect_timed(),
+    vec![(ms(0), 'a'), (ms(0), 'b'), (ms(50), 'c')]
+  );
+}
+
+#[test]
+fn test_scale_time() {
+  let ms = Duration::from_millis;
+  let stream = || Stream::from_iter(vec![(ms(0), 1), (ms(100), 2), (ms(50), 3)]);
+  assert_eq!(
+    stream().scale_time(2.0).collect_timed(),
+    vec![(ms(0), 1), (ms(200), 2), (ms(100), 3)]
+  );
+  assert_eq!(
+    stream().scale_time(0.5).collect_timed(),
+    vec![(ms(0), 1), (ms(50), 2), (ms(25), 3)]
+  );
+}
+
+#[test]
+fn test_stretch_to() {
+  let ms = Duration::from_millis;
+  let stream = || Stream::from_iter(vec![(ms(10), 1), (ms(100), 2), (ms(50), 3)]);
+  assert_eq!(
+    stream().stretch_to(ms(320)).collect_timed(),
+    vec![(ms(20), 1), (ms(200), 2), (ms(100), 3)]
+  );
+  let thirds = stream().stretch_to(ms(100)).collect_timed();
+  assert_eq!(thirds.iter().map(|(d, _)| *d).sum::<Duration>(), ms(100));
+  assert_eq!(
+    Stream::from_iter(vec![(ms(0), 1), (ms(0), 2)])
+      .stretch_to(ms(100))
+      .collect_timed(),
+    vec![(ms(0), 1), (ms(0), 2)]
+  );
+}
+
+#[test]
+fn test_take_n() {
+  let ms = Duration::from_millis;
+  let stream = || Stream::from_iter(vec![(ms(5), 1), (ms(10), 2), (ms(20), 3), (ms(0), 4)]);
+  let collect = |stream: Stream<'static, i32>| stream.collect_timed();
+  assert_eq!(collect(stream().take_n(2)), vec![(ms(5), 1), (ms(10), 2)]);
+  assert_eq!(collect(stream().skip_n(2)), vec![(ms(35), 3), (ms(0), 4)]);
+  assert_eq!(collect(stream().skip_n(5)), vec![]);
+  assert_eq!(
+    collect(stream().take_while(|&n| n < 3)),
+    vec![(ms(5), 1), (ms(10), 2)]
+  );
+}
+
+#[test]
+fn test_filter() {
+  let ms = Duration::from_millis;
+  let stream = || Stream::from_iter(vec![(ms(5), 1), (ms(10), 2), (ms(20), 3), (ms(0), 4)]);
+  assert_eq!(
+    stream()
+      .filter(|&n| n % 2 == 1)
+      .into_iter()
+      .collect::<Vec<_>>(),
+    vec![(ms(5), 1), (ms(30), 3)]
+  );
+  assert_eq!(
+    stream()
+      .filter_map(|n| if n > 1 { Some(n * 10) } else { None })
+      .into_iter()
+      .collect::<Vec<_>>(),
+    vec![(ms(15), 20), (ms(20), 30), (ms(0), 40)]
+  );
+}
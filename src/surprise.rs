@@ -0,0 +1,155 @@
+use crate::arrange::{Arrangement, Density, Register, Section};
+use crate::generator::GenCtx;
+use crate::melody::{Durations, Params};
+use crate::seed::Seed;
+use crate::template::{Part, Role, Template};
+use crate::theory::{Key, Note, PitchClass};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+// What a surprise is not allowed to change. Anything left as None is chosen
+// at random.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Locks {
+  pub key: Option<Key>,
+  // Beats per minute.
+  pub tempo: RangeInclusive<f64>,
+  pub tracks: Option<usize>,
+}
+
+impl Default for Locks {
+  fn default() -> Self {
+    Self {
+      key: None,
+      tempo: 70.0..=140.0,
+      tracks: None,
+    }
+  }
+}
+
+// Everything a surprise chose. It is kept in full, so a result worth keeping
+// can be printed, saved and refined by hand rather than rerolled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resolved {
+  pub key: Key,
+  pub tempo: f64,
+  pub tracks: Vec<Template>,
+  pub arrangement: Arrangement,
+}
+
+impl Resolved {
+  pub fn beat(&self) -> Duration {
+    Duration::from_secs_f64(60.0 / self.tempo)
+  }
+  pub fn ctx(&self) -> GenCtx {
+    GenCtx::new(self.key.clone(), self.beat())
+  }
+}
+
+// Chooses a piece's parameters at random, within the locks. The same locks
+// and seed always give the same result, and each choice has its own seed, so
+// locking one parameter leaves the others as they were.
+pub fn surprise(locks: &Locks, seed: Seed) -> Resolved {
+  let key = locks.key.clone().unwrap_or_else(|| {
+    let mut rng = seed.fork("key").rng();
+    let tonic = Note::new(PitchClass::from_ordinal(rng.gen_range(0..12)), 4);
+    let scales: [fn(Note) -> Key; 3] = [Key::major, Key::minor, Key::pentatonic];
+    scales.choose(&mut rng).unwrap()(tonic)
+  });
+  let tempo = seed.fork("tempo").rng().gen_range(locks.tempo.clone());
+  let beat = Duration::from_secs_f64(60.0 / tempo);
+
+  let count = locks
+    .tracks
+    .unwrap_or_else(|| seed.fork("count").rng().gen_range(2..=5));
+  let mut roles: Vec<Role> = Role::ALL.to_vec();
+  roles.shuffle(&mut seed.fork("roles").rng());
+  let tracks = (0..count)
+    .map(|i| track(roles[i % roles.len()], beat, seed.fork("track").fork(i)))
+    .collect();
+
+  let mut rng = seed.fork("arrangement").rng();
+  let densities = [Density::Sparse, Density::Moderate, Density::Dense];
+  let registers = [
+    Register::Low,
+    Register::Middle,
+    Register::High,
+    Register::Full,
+  ];
+  let bar = 8;
+  let sections = (0..rng.gen_range(2..=5))
+    .map(|_| Section {
+      length: beat * 4 * *[4, 8, 16].choose(&mut rng).unwrap(),
+      density: *densities.choose(&mut rng).unwrap(),
+      register: *registers.choose(&mut rng).unwrap(),
+      voices: rng.gen_range(1..=3),
+    })
+    .collect();
+  let arrangement = Arrangement {
+    params: Params {
+      durations: Durations::common_time(),
+      ..Params::new(beat / 2)
+    },
+    bar,
+    sections,
+  };
+
+  Resolved {
+    key,
+    tempo,
+    tracks,
+    arrangement,
+  }
+}
+
+// A template for the role with its feel varied and its timing fitted to the
+// beat.
+fn track(role: Role, beat: Duration, seed: Seed) -> Template {
+  let mut rng = seed.rng();
+  let mut template = Template::new(role);
+  template.gate = (template.gate * rng.gen_range(0.8..1.1)).min(1.0);
+  template.velocity_curve *= rng.gen_range(0.8..1.25);
+  match &mut template.part {
+    Part::Phrases(phrases) => {
+      let quantum = beat / *[1, 2, 4].choose(&mut rng).unwrap();
+      phrases.params.quantum = quantum;
+      phrases.params.repetition.probability = rng.gen_range(0.0..0.4);
+      phrases.phrasing.length = quantum * 16 * rng.gen_range(1..=2);
+      phrases.phrasing.repetitions = rng.gen_range(1..=4);
+      phrases.phrasing.home = rng.gen_range(-3..=4);
+    }
+    Part::HiHat(hi_hat) => {
+      hi_hat.steps_per_beat = *[2, 4].choose(&mut rng).unwrap();
+      hi_hat.open = rng.gen_range(0.0..0.2);
+      hi_hat.variation = rng.gen_range(0.0..0.15);
+    }
+  }
+  template
+}
+
+#[test]
+fn test_surprise() {
+  let key = Key::minor(Note::new(PitchClass::E, 4));
+  let locks = Locks {
+    key: Some(key.clone()),
+    tempo: 90.0..=100.0,
+    tracks: Some(3),
+  };
+  for i in 0..20 {
+    let resolved = surprise(&locks, Seed::new(i));
+    assert_eq!(resolved.key, key);
+    assert!(locks.tempo.contains(&resolved.tempo));
+    assert_eq!(resolved.tracks.len(), 3);
+    assert!(resolved.arrangement.sections.len() >= 2);
+    assert_eq!(resolved, surprise(&locks, Seed::new(i)));
+  }
+  let free = |i| surprise(&Locks::default(), Seed::new(i));
+  assert!((0..20).any(|i| free(i).key != free(0).key));
+  assert!((0..20).any(|i| free(i).tracks.len() != free(0).tracks.len()));
+  let locked = surprise(&locks, Seed::new(0));
+  let unlocked = surprise(&Locks { key: None, ..locks }, Seed::new(0));
+  assert_eq!(locked.tempo, unlocked.tempo);
+  assert_eq!(locked.arrangement, unlocked.arrangement);
+}
@@ -0,0 +1,252 @@
+use crate::drums::HiHat;
+use crate::event::{self, NoteEvent};
+use crate::generator::{GenCtx, Generator, Phrases};
+use crate::melody::{Durations, FirstNote, Params, Phrasing, RepeatMode, Repetition};
+use crate::midi::gm::GmProgram;
+use crate::midi::{Channel, Message};
+use crate::seed::Seed;
+use crate::stream::Stream;
+use crate::theory::{Note, PitchClass, Range};
+use std::time::Duration;
+
+// The part a track plays in a piece.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Role {
+  Pad,
+  Pluck,
+  Bass,
+  Lead,
+  Drums,
+}
+
+impl Role {
+  pub const ALL: [Role; 5] = [Role::Pad, Role::Pluck, Role::Bass, Role::Lead, Role::Drums];
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Pad => "pad",
+      Self::Pluck => "pluck",
+      Self::Bass => "bass",
+      Self::Lead => "lead",
+      Self::Drums => "drums",
+    }
+  }
+  pub fn from_name(name: &str) -> Option<Self> {
+    Self::ALL.iter().copied().find(|role| role.name() == name)
+  }
+}
+
+// The generators a template can be built on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Part {
+  Phrases(Phrases),
+  HiHat(HiHat),
+}
+
+impl Generator for Part {
+  fn generate<'a>(&'a self, ctx: &'a GenCtx, seed: Seed) -> Stream<'a, NoteEvent> {
+    match self {
+      Self::Phrases(phrases) => phrases.generate(ctx, seed),
+      Self::HiHat(hi_hat) => hi_hat.generate(ctx, seed),
+    }
+  }
+}
+
+// Sensible defaults for a track in a given role, ready to play as is or to
+// adjust field by field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+  pub role: Role,
+  // None for drums, which are selected by channel instead.
+  pub program: Option<GmProgram>,
+  // Notes are folded into the range, unless it is None.
+  pub range: Option<Range>,
+  // Fraction of each note's written duration for which it sounds.
+  pub gate: f64,
+  // Exponent applied to velocities on a 0 to 1 scale: above 1 exaggerates
+  // the difference between loud and soft notes, below 1 evens it out.
+  pub velocity_curve: f64,
+  // Controller values sent before the first note, as (controller, value).
+  pub controllers: Vec<(u8, u8)>,
+  pub part: Part,
+}
+
+impl Template {
+  pub fn new(role: Role) -> Self {
+    let range = |lowest, highest| {
+      Some(Range::new(
+        Note::new(PitchClass::C, lowest),
+        Note::new(PitchClass::C, highest),
+      ))
+    };
+    let phrases = |quantum: Duration, durations, bars: u32| {
+      Part::Phrases(Phrases {
+        params: Params {
+          durations,
+          repetition: Repetition {
+            probability: 0.2,
+            max_consecutive: 1,
+            mode: RepeatMode::Rearticulate,
+          },
+          ..Params::new(quantum)
+        },
+        phrasing: Phrasing {
+          length: quantum * 16 * bars,
+          repetitions: 2,
+          home: 0,
+          first_note: FirstNote::Tonic,
+          stitch: None,
+        },
+      })
+    };
+    match role {
+      Role::Pad => Self {
+        role,
+        program: Some(GmProgram::Pad2Warm),
+        range: range(3, 5),
+        gate: 1.0,
+        velocity_curve: 0.5,
+        controllers: vec![(7, 90), (91, 80)],
+        part: phrases(
+          Duration::from_millis(125),
+          Durations::Bars {
+            values: vec![(8, 1.0), (16, 2.0)],
+            bar: 16,
+          },
+          2,
+        ),
+      },
+      Role::Pluck => Self {
+        role,
+        program: Some(GmProgram::OrchestralHarp),
+        range: range(4, 6),
+        gate: 0.4,
+        velocity_curve: 1.2,
+        controllers: vec![(7, 100), (91, 40)],
+        part: phrases(
+          Duration::from_millis(125),
+          Durations::Weighted {
+            values: vec![(1, 4.0), (2, 3.0), (3, 1.0)],
+            bar: 16,
+          },
+          1,
+        ),
+      },
+      Role::Bass => Self {
+        role,
+        program: Some(GmProgram::ElectricBassFinger),
+        range: range(1, 3),
+        gate: 0.8,
+        velocity_curve: 1.0,
+        controllers: vec![(7, 110), (91, 10)],
+        part: phrases(
+          Duration::from_millis(250),
+          Durations::Bars {
+            values: vec![(1, 2.0), (2, 4.0), (4, 2.0)],
+            bar: 8,
+          },
+          1,
+        ),
+      },
+      Role::Lead => Self {
+        role,
+        program: Some(GmProgram::Lead2Sawtooth),
+        range: range(4, 6),
+        gate: 0.9,
+        velocity_curve: 1.0,
+        controllers: vec![(7, 100), (91, 50)],
+        part: phrases(Duration::from_millis(250), Durations::common_time(), 1),
+      },
+      Role::Drums => Self {
+        role,
+        program: None,
+        range: None,
+        gate: 1.0,
+        velocity_curve: 1.3,
+        controllers: vec![(7, 110), (91, 20)],
+        part: Part::HiHat(HiHat::default()),
+      },
+    }
+  }
+  // The template for a role given by name, such as in configuration.
+  pub fn named(name: &str) -> Option<Self> {
+    Role::from_name(name).map(Self::new)
+  }
+  // Messages for the whole track on one channel: the program and controllers,
+  // then the part's notes shaped by the template. Drums should be played on
+  // channel 10.
+  pub fn track<'a>(&'a self, ctx: &'a GenCtx, channel: Channel, seed: Seed) -> Stream<'a, Message> {
+    let mut setup = Vec::new();
+    if let Some(program) = self.program {
+      setup.push((
+        Duration::from_secs(0),
+        Message::ProgramChange(channel, program.into()),
+      ));
+    }
+    for &(controller, value) in &self.controllers {
+      setup.push((
+        Duration::from_secs(0),
+        Message::ControlChange(channel, controller, value),
+      ));
+    }
+    let events = self.part.generate(ctx, seed).map(move |mut event| {
+      if let Some(range) = self.range {
+        event.note = range.fold(event.note);
+      }
+      event.duration = event.duration.mul_f64(self.gate);
+      let velocity = (event.velocity as f64 / 127.0).powf(self.velocity_curve);
+      event.velocity = (velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+      event
+    });
+    Stream::from_iter(setup).chain(event::lower(channel, events))
+  }
+}
+
+#[test]
+fn test_templates() {
+  use crate::midi::gm::drums;
+  use crate::theory::Key;
+  assert_eq!(Template::named("bass").unwrap().role, Role::Bass);
+  assert!(Template::named("kazoo").is_none());
+  for role in Role::ALL {
+    assert_eq!(Role::from_name(role.name()), Some(role));
+  }
+
+  let ctx = GenCtx::new(
+    Key::major(Note::new(PitchClass::D, 4)),
+    Duration::from_millis(500),
+  );
+  let bass = Template::new(Role::Bass);
+  let messages = bass
+    .track(&ctx, Channel::Ch2, Seed::new("test"))
+    .take_n(40)
+    .collect_timed();
+  assert_eq!(
+    messages[0],
+    (
+      Duration::from_secs(0),
+      Message::ProgramChange(Channel::Ch2, GmProgram::ElectricBassFinger.into())
+    )
+  );
+  let range = bass.range.unwrap();
+  for (_, message) in &messages[3..] {
+    match *message {
+      Message::NoteOn(Channel::Ch2, note, _) | Message::NoteOff(Channel::Ch2, note, _) => {
+        assert!(range.contains(Note::from_midi(note)))
+      }
+      ref other => panic!("unexpected message {:?}", other),
+    }
+  }
+
+  let kit = Template::named("drums").unwrap();
+  let messages = kit
+    .track(&ctx, Channel::Ch10, Seed::new("test"))
+    .take_n(20)
+    .collect_timed();
+  assert!(!messages
+    .iter()
+    .any(|(_, message)| matches!(message, Message::ProgramChange(..))));
+  assert!(messages.iter().any(|(_, message)| matches!(
+    message,
+    Message::NoteOn(Channel::Ch10, drums::CLOSED_HI_HAT, _)
+  )));
+}
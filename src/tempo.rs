@@ -0,0 +1,442 @@
+use crate::stream::Stream;
+use std::cell::Cell;
+use std::ops::{Add, AddAssign, Mul, Sub};
+use std::rc::Rc;
+use std::time::Duration;
+
+// Streams in musical time are BeatStreams rather than Streams, so one can't be
+// played by mistake as if its beats were seconds. Each only takes on a tempo
+// when it is converted to a Stream for playing, so changing the tempo doesn't
+// mean generating it again.
+
+// A length of musical time, in beats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Beats(pub f64);
+
+impl Beats {
+  pub fn bars(bars: u32, beats_per_bar: u32) -> Self {
+    Self((bars * beats_per_bar) as f64)
+  }
+  // The delay standing for this many beats inside a BeatStream, one beat to
+  // the second. Negative lengths give no delay, as a Duration can't be
+  // negative.
+  pub fn as_delay(self) -> Duration {
+    Duration::from_secs_f64(self.0.max(0.0))
+  }
+  pub fn from_delay(delay: Duration) -> Self {
+    Self(delay.as_secs_f64())
+  }
+  // How long this many beats last at a fixed tempo, or no time at all if
+  // negative.
+  pub fn at(self, beat: Duration) -> Duration {
+    beat.mul_f64(self.0.max(0.0))
+  }
+}
+
+impl Add for Beats {
+  type Output = Self;
+  fn add(self, other: Self) -> Self {
+    Self(self.0 + other.0)
+  }
+}
+
+impl AddAssign for Beats {
+  fn add_assign(&mut self, other: Self) {
+    self.0 += other.0;
+  }
+}
+
+impl Sub for Beats {
+  type Output = Self;
+  fn sub(self, other: Self) -> Self {
+    Self(self.0 - other.0)
+  }
+}
+
+impl Mul<f64> for Beats {
+  type Output = Self;
+  fn mul(self, factor: f64) -> Self {
+    Self(self.0 * factor)
+  }
+}
+
+// A change of tempo in a TempoMap, in beats per minute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TempoChange {
+  Step { at: Beats, bpm: f64 },
+  // The tempo moves from whatever it was at the start to the new one, changing
+  // by the same amount each beat.
+  Ramp { start: Beats, end: Beats, bpm: f64 },
+}
+
+impl TempoChange {
+  fn start(&self) -> Beats {
+    match *self {
+      Self::Step { at, .. } => at,
+      Self::Ramp { start, .. } => start,
+    }
+  }
+  fn end(&self) -> Beats {
+    match *self {
+      Self::Step { at, .. } => at,
+      Self::Ramp { end, .. } => end,
+    }
+  }
+}
+
+// The tempo over the course of a piece, for playing streams in musical time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TempoMap {
+  pub initial: f64,
+  // In order, and not overlapping.
+  pub changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+  pub fn new(bpm: f64) -> Self {
+    assert!(bpm > 0.0, "tempo must be positive");
+    Self {
+      initial: bpm,
+      changes: Vec::new(),
+    }
+  }
+  // Jumps to the new tempo at the given beat.
+  pub fn step(self, at: Beats, bpm: f64) -> Self {
+    self.then(TempoChange::Step { at, bpm })
+  }
+  // Moves gradually to the new tempo between the given beats: an accelerando
+  // if it is faster, a ritardando if it is slower.
+  pub fn ramp(self, start: Beats, end: Beats, bpm: f64) -> Self {
+    assert!(end > start, "tempo ramp must end after it starts");
+    self.then(TempoChange::Ramp { start, end, bpm })
+  }
+  fn then(mut self, change: TempoChange) -> Self {
+    let bpm = match change {
+      TempoChange::Step { bpm, .. } | TempoChange::Ramp { bpm, .. } => bpm,
+    };
+    assert!(bpm > 0.0, "tempo must be positive");
+    assert!(
+      self
+        .changes
+        .last()
+        .is_none_or(|last| change.start() >= last.end()),
+      "tempo changes must be added in order without overlapping"
+    );
+    self.changes.push(change);
+    self
+  }
+  // Beats per minute at the given position.
+  pub fn bpm_at(&self, position: Beats) -> f64 {
+    let mut bpm = self.initial;
+    for change in &self.changes {
+      match *change {
+        TempoChange::Step { at, bpm: next } if at <= position => bpm = next,
+        TempoChange::Ramp {
+          start,
+          end,
+          bpm: next,
+        } if start < position => {
+          if position >= end {
+            bpm = next;
+          } else {
+            bpm += (next - bpm) * (position - start).0 / (end - start).0;
+          }
+        }
+        _ => break,
+      }
+    }
+    bpm
+  }
+  // Time from the start of the piece to the given position.
+  pub fn time_at(&self, position: Beats) -> Duration {
+    let mut seconds = 0.0;
+    let mut bpm = self.initial;
+    let mut now = Beats(0.0);
+    for change in &self.changes {
+      let start = change.start();
+      if position <= start {
+        break;
+      }
+      seconds += 60.0 * (start - now).0 / bpm;
+      now = change.end();
+      match *change {
+        TempoChange::Step { bpm: next, .. } => bpm = next,
+        TempoChange::Ramp { end, bpm: next, .. } => {
+          // Integrating 60 / bpm over a linear change in bpm.
+          let slope = (next - bpm) / (end - start).0;
+          let through = if position < end { position } else { end };
+          let length = (through - start).0;
+          seconds += if slope == 0.0 {
+            60.0 * length / bpm
+          } else {
+            60.0 / slope * ((bpm + slope * length) / bpm).ln()
+          };
+          if position < end {
+            return Duration::from_secs_f64(seconds);
+          }
+          bpm = next;
+        }
+      }
+    }
+    seconds += 60.0 * (position - now).0 / bpm;
+    Duration::from_secs_f64(seconds)
+  }
+}
+
+// A stream in musical time. It holds its events in a Stream whose delays
+// stand for beats, one beat to the second (see Beats::as_delay), so that
+// every Stream combinator works on it through map_stream.
+pub struct BeatStream<'a, E: 'a> {
+  stream: Stream<'a, E>,
+}
+
+impl<'a, E: 'a> BeatStream<'a, E> {
+  // A stream in musical time from events and their delays in beats.
+  pub fn from_iter<I>(events: I) -> Self
+  where
+    I: IntoIterator<Item = (Beats, E)>,
+    I::IntoIter: 'a,
+  {
+    Self {
+      stream: Stream::from_iter(events.into_iter().map(|(beats, e)| (beats.as_delay(), e))),
+    }
+  }
+  pub fn empty() -> Self {
+    Self {
+      stream: Stream::empty(),
+    }
+  }
+  // Applies Stream combinators in musical time: any delay they are given or
+  // see is a number of beats as given by Beats::as_delay.
+  pub fn map_stream<F, EE>(self, fun: F) -> BeatStream<'a, EE>
+  where
+    F: FnOnce(Stream<'a, E>) -> Stream<'a, EE>,
+  {
+    BeatStream {
+      stream: fun(self.stream),
+    }
+  }
+  pub fn chain(self, other: Self) -> Self {
+    self.map_stream(|stream| stream.chain(other.stream))
+  }
+  // Simultaneous events are taken from self first.
+  pub fn merge(self, other: Self) -> Self {
+    self.map_stream(|stream| stream.merge(other.stream))
+  }
+  pub fn collect_beats(self) -> Vec<(Beats, E)> {
+    self
+      .stream
+      .into_iter()
+      .map(|(delay, e)| (Beats::from_delay(delay), e))
+      .collect()
+  }
+  // Converts to wall-clock time at a fixed tempo, given as the length of a
+  // beat. Event times rather than delays are converted, so rounding errors
+  // don't accumulate. Rests are converted too, so padding from
+  // pad_to_bar_end still holds back whatever is chained after.
+  pub fn at_tempo(self, beat: Duration) -> Stream<'a, E> {
+    self
+      .stream
+      .retime(move |time| Beats::from_delay(time).at(beat))
+  }
+  // Converts to wall-clock time following the tempo map.
+  pub fn with_tempo_map(self, tempo: TempoMap) -> Stream<'a, E> {
+    self
+      .stream
+      .retime(move |time| tempo.time_at(Beats::from_delay(time)))
+  }
+  // Snaps to the nearest 1/subdivision of a beat, such as 4 for sixteenths in
+  // 4/4 or 3 for triplets.
+  pub fn quantize_to_beat(self, subdivision: u32) -> Self {
+    assert!(subdivision > 0, "beat subdivision must be positive");
+    self.map_stream(|stream| stream.quantize(Beats(1.0 / subdivision as f64).as_delay()))
+  }
+  // Delays a phrase that would start at the given position so that it starts
+  // on the next bar line instead. A phrase already starting on a bar line is
+  // left as it is.
+  pub fn align_to_next_bar(self, position: Beats, beats_per_bar: u32) -> Self {
+    let bar = beats_per_bar as f64;
+    let into_bar = position.0.rem_euclid(bar);
+    if into_bar == 0.0 {
+      return self;
+    }
+    self.map_stream(|stream| stream.delay(Beats(bar - into_bar).as_delay()))
+  }
+  // Follows a finite phrase with a rest lasting until the end of the bar its
+  // last event is in, so that whatever is chained after it starts on a bar
+  // line. Never returns if the stream is infinite.
+  pub fn pad_to_bar_end(self, beats_per_bar: u32) -> Self {
+    let events = self.stream.collect_timed();
+    if events.is_empty() {
+      return Self::empty();
+    }
+    let bar = Beats::bars(1, beats_per_bar).as_delay().as_nanos();
+    let last = events.iter().map(|(d, _)| *d).sum::<Duration>().as_nanos();
+    let rest = Duration::from_nanos(((last / bar + 1) * bar - last) as u64);
+    let events = events
+      .into_iter()
+      .map(|(d, e)| (d, Some(e)))
+      .chain(std::iter::once((rest, None)));
+    Self {
+      stream: Stream::from_parts(Box::new(events), Rc::new(Cell::new(false))),
+    }
+  }
+}
+
+#[test]
+fn test_beats() {
+  use crate::testing::ms;
+  assert_eq!(Beats::bars(2, 3), Beats(6.0));
+  assert_eq!(Beats(1.5).at(ms(500)), ms(750));
+  assert_eq!(Beats::from_delay(Beats(0.25).as_delay()), Beats(0.25));
+  assert_eq!((Beats(1.0) + Beats(0.5)) * 2.0 - Beats(1.0), Beats(2.0));
+  assert_eq!((Beats(1.0) - Beats(2.0)).as_delay(), ms(0));
+  assert_eq!(Beats(-1.0).at(ms(500)), ms(0));
+
+  let beats = || {
+    BeatStream::from_iter(vec![
+      (Beats(0.0), 'a'),
+      (Beats(1.0 / 3.0), 'b'),
+      (Beats(1.0 / 3.0), 'c'),
+    ])
+    .map_stream(|stream| stream.repeat_n(Beats(1.0).as_delay(), 3))
+  };
+  crate::assert_stream_eq!(
+    beats().at_tempo(ms(600)),
+    crate::testing::times(vec![
+      (0, 'a'),
+      (200, 'b'),
+      (400, 'c'),
+      (600, 'a'),
+      (800, 'b'),
+      (1000, 'c'),
+      (1200, 'a'),
+      (1400, 'b'),
+      (1600, 'c'),
+    ]),
+    ms(1),
+  );
+  assert_eq!(beats().at_tempo(ms(300)).total_duration(), ms(800));
+}
+
+#[test]
+fn test_tempo_map() {
+  use crate::testing::ms;
+  let map = TempoMap::new(120.0)
+    .step(Beats(4.0), 60.0)
+    .ramp(Beats(8.0), Beats(12.0), 120.0);
+  assert_eq!(map.bpm_at(Beats(3.0)), 120.0);
+  assert_eq!(map.bpm_at(Beats(4.0)), 60.0);
+  assert_eq!(map.bpm_at(Beats(10.0)), 90.0);
+  assert_eq!(map.bpm_at(Beats(20.0)), 120.0);
+  assert_eq!(map.time_at(Beats(4.0)), ms(2000));
+  assert_eq!(map.time_at(Beats(8.0)), ms(6000));
+  // 60 / 15 * ln 2 seconds for the ramp.
+  let ramped = map.time_at(Beats(12.0)).as_secs_f64() - 6.0;
+  assert!((ramped - 4.0 * 2f64.ln()).abs() < 1e-6);
+  assert!((map.time_at(Beats(13.0)).as_secs_f64() - 6.5 - ramped).abs() < 1e-6);
+
+  let quarters = || BeatStream::from_iter((0..16).map(|_| (Beats(1.0), ())));
+  crate::assert_stream_eq!(
+    quarters().with_tempo_map(TempoMap::new(100.0)),
+    quarters().at_tempo(ms(600)),
+    ms(1),
+  );
+  // A ritardando: each beat is longer than the one before.
+  let ritardando = TempoMap::new(120.0).ramp(Beats(8.0), Beats(16.0), 60.0);
+  let delays: Vec<Duration> = quarters()
+    .with_tempo_map(ritardando)
+    .collect_timed()
+    .into_iter()
+    .map(|(d, _)| d)
+    .collect();
+  assert!(delays[..8].iter().all(|&d| d == ms(500)));
+  assert!(delays[8..].windows(2).all(|pair| pair[1] > pair[0]));
+  assert!(delays[15] < ms(1000));
+}
+
+#[test]
+#[should_panic(expected = "tempo must be positive")]
+fn test_tempo_map_stopped() {
+  TempoMap::new(120.0).step(Beats(4.0), 0.0);
+}
+
+#[test]
+fn test_bars() {
+  use crate::testing::ms;
+  let phrase = || {
+    BeatStream::from_iter(vec![
+      (Beats(0.0), 'a'),
+      (Beats(0.9), 'b'),
+      (Beats(0.35), 'c'),
+      (Beats(1.6), 'd'),
+    ])
+  };
+  crate::assert_stream_eq!(
+    phrase().quantize_to_beat(2),
+    BeatStream::from_iter(vec![
+      (Beats(0.0), 'a'),
+      (Beats(1.0), 'b'),
+      (Beats(0.5), 'c'),
+      (Beats(1.5), 'd'),
+    ]),
+  );
+  crate::assert_stream_eq!(
+    phrase().quantize_to_beat(3),
+    BeatStream::from_iter(vec![
+      (Beats(0.0), 'a'),
+      (Beats(1.0), 'b'),
+      (Beats(1.0 / 3.0), 'c'),
+      (Beats(5.0 / 3.0), 'd'),
+    ]),
+    ms(1),
+  );
+
+  let aligned = phrase().align_to_next_bar(Beats(5.5), 4).collect_beats();
+  assert_eq!(aligned[0], (Beats(2.5), 'a'));
+  assert_eq!(aligned[1..], phrase().collect_beats()[1..]);
+  assert_eq!(
+    phrase().align_to_next_bar(Beats(8.0), 4).collect_beats(),
+    phrase().collect_beats()
+  );
+
+  let next_bar = |phrase: BeatStream<'static, char>, beats_per_bar| {
+    phrase
+      .pad_to_bar_end(beats_per_bar)
+      .chain(BeatStream::from_iter(vec![(Beats(0.0), 'z')]))
+      .collect_beats()
+      .into_iter()
+      .map(|(beats, _)| beats.0)
+      .sum::<f64>()
+  };
+  assert!((next_bar(phrase(), 4) - 4.0).abs() < 1e-6);
+  assert!((next_bar(phrase(), 2) - 4.0).abs() < 1e-6);
+  let on_bar_line = BeatStream::from_iter(vec![(Beats(3.0), 'a')]);
+  assert_eq!(next_bar(on_bar_line, 3), 6.0);
+  assert_eq!(next_bar(BeatStream::empty(), 4), 0.0);
+  // The padding survives conversion to wall-clock time.
+  let converted = |tempo: Stream<'static, char>| {
+    let (time, last) = tempo
+      .chain(Stream::immediate('z'))
+      .timestamps()
+      .last()
+      .unwrap();
+    assert_eq!(last, 'z');
+    time
+  };
+  let beat = ms(500);
+  assert!(crate::testing::approx_eq(
+    converted(phrase().pad_to_bar_end(4).at_tempo(beat)),
+    ms(2000),
+    ms(1)
+  ));
+  assert!(crate::testing::approx_eq(
+    converted(
+      phrase()
+        .pad_to_bar_end(4)
+        .with_tempo_map(TempoMap::new(120.0))
+    ),
+    ms(2000),
+    ms(1)
+  ));
+}
@@ -0,0 +1,140 @@
+use crate::stream::Stream;
+use crate::tempo::BeatStream;
+use std::fmt::Debug;
+use std::time::Duration;
+
+// Helpers for avril's own tests of streams.
+
+pub fn ms(millis: u64) -> Duration {
+  Duration::from_millis(millis)
+}
+
+// A stream from (delay in milliseconds, event) pairs.
+pub fn delays<E>(events: Vec<(u64, E)>) -> Stream<'static, E>
+where
+  E: 'static,
+{
+  Stream::from_iter(events.into_iter().map(|(delay, e)| (ms(delay), e)))
+}
+
+// A stream from (milliseconds since the start, event) pairs.
+pub fn times<E>(events: Vec<(u64, E)>) -> Stream<'static, E>
+where
+  E: 'static,
+{
+  Stream::from_timestamps(events.into_iter().map(|(time, e)| (ms(time), e)))
+}
+
+pub fn approx_eq(a: Duration, b: Duration, tolerance: Duration) -> bool {
+  a.max(b) - a.min(b) <= tolerance
+}
+
+// Anything that can be compared as a finite sequence of timed events.
+pub trait Timed<E> {
+  fn into_timed(self) -> Vec<(Duration, E)>;
+}
+
+impl<'a, E: 'a> Timed<E> for Stream<'a, E> {
+  fn into_timed(self) -> Vec<(Duration, E)> {
+    self.collect_timed()
+  }
+}
+
+// Compared in musical time, one beat to the second.
+impl<'a, E: 'a> Timed<E> for BeatStream<'a, E> {
+  fn into_timed(self) -> Vec<(Duration, E)> {
+    self.at_tempo(Duration::from_secs(1)).collect_timed()
+  }
+}
+
+impl<E> Timed<E> for Vec<(Duration, E)> {
+  fn into_timed(self) -> Vec<(Duration, E)> {
+    self
+  }
+}
+
+// Compares two finite streams event by event, allowing each event's time
+// since the start to be off by the tolerance, so rounding doesn't fail a
+// test. Describes the first difference found.
+pub fn compare<E, A, B>(actual: A, expected: B, tolerance: Duration) -> Result<(), String>
+where
+  E: PartialEq + Debug,
+  A: Timed<E>,
+  B: Timed<E>,
+{
+  let with_times = |events: Vec<(Duration, E)>| {
+    let mut now = Duration::from_secs(0);
+    events
+      .into_iter()
+      .map(|(d, e)| {
+        now += d;
+        (now, e)
+      })
+      .collect::<Vec<_>>()
+  };
+  let actual = with_times(actual.into_timed());
+  let expected = with_times(expected.into_timed());
+  for (i, (a, b)) in actual.iter().zip(&expected).enumerate() {
+    if a.1 != b.1 || !approx_eq(a.0, b.0, tolerance) {
+      return Err(format!(
+        "event {} differs: got {:?} at {:?}, expected {:?} at {:?}",
+        i, a.1, a.0, b.1, b.0
+      ));
+    }
+  }
+  if actual.len() != expected.len() {
+    return Err(format!(
+      "got {} events, expected {}: {:?} vs {:?}",
+      actual.len(),
+      expected.len(),
+      actual.get(expected.len()),
+      expected.get(actual.len())
+    ));
+  }
+  Ok(())
+}
+
+// Asserts that a stream (or a Vec of (delay, event) pairs) has the expected
+// events at the expected times, optionally within a tolerance.
+#[macro_export]
+macro_rules! assert_stream_eq {
+  ($actual:expr, $expected:expr $(,)?) => {
+    $crate::assert_stream_eq!($actual, $expected, ::std::time::Duration::from_secs(0))
+  };
+  ($actual:expr, $expected:expr, $tolerance:expr $(,)?) => {
+    if let Err(message) = $crate::testing::compare($actual, $expected, $tolerance) {
+      panic!("streams differ: {}", message);
+    }
+  };
+}
+
+#[test]
+fn test_assert_stream_eq() {
+  crate::assert_stream_eq!(
+    delays(vec![(0, 'a'), (10, 'b'), (5, 'c')]),
+    times(vec![(0, 'a'), (10, 'b'), (15, 'c')]),
+  );
+  crate::assert_stream_eq!(
+    delays(vec![(0, 'a'), (10, 'b')]).scale_time(1.0 / 3.0),
+    vec![(ms(0), 'a'), (ms(3), 'b')],
+    ms(1),
+  );
+  assert_eq!(
+    compare(
+      delays(vec![(0, 'a'), (10, 'b')]),
+      times(vec![(0, 'a'), (12, 'b')]),
+      ms(1)
+    ),
+    Err("event 1 differs: got 'b' at 10ms, expected 'b' at 12ms".to_string())
+  );
+  assert!(compare(
+    delays(vec![(0, 'a')]),
+    times(vec![(0, 'a'), (1, 'b')]),
+    ms(0)
+  )
+  .is_err());
+  let panicked = std::panic::catch_unwind(|| {
+    crate::assert_stream_eq!(delays(vec![(0, 1)]), delays(vec![(0, 2)]));
+  });
+  assert!(panicked.is_err());
+}
@@ -15,7 +15,7 @@ pub enum PitchClass {
 }
 
 impl PitchClass {
-  fn ordinal(self) -> i64 {
+  pub fn ordinal(self) -> i64 {
     match self {
       Self::C => 0,
       Self::CSharp => 1,
@@ -31,7 +31,7 @@ impl PitchClass {
       Self::B => 11,
     }
   }
-  fn from_ordinal(ordinal: i64) -> Self {
+  pub fn from_ordinal(ordinal: i64) -> Self {
     match ordinal.rem_euclid(12) {
       0 => Self::C,
       1 => Self::CSharp,
@@ -69,6 +69,78 @@ impl std::fmt::Display for PitchClass {
   }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Letter {
+  C,
+  D,
+  E,
+  F,
+  G,
+  A,
+  B,
+}
+
+impl Letter {
+  const ALL: [Letter; 7] = [
+    Self::C,
+    Self::D,
+    Self::E,
+    Self::F,
+    Self::G,
+    Self::A,
+    Self::B,
+  ];
+  fn index(self) -> i64 {
+    Self::ALL.iter().position(|&l| l == self).unwrap() as i64
+  }
+  fn offset(self, letters: i64) -> Self {
+    Self::ALL[(self.index() + letters).rem_euclid(7) as usize]
+  }
+  // Semitones above C of the natural note with this letter.
+  fn semitones(self) -> i64 {
+    [0, 2, 4, 5, 7, 9, 11][self.index() as usize]
+  }
+}
+
+impl std::fmt::Display for Letter {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    std::fmt::Debug::fmt(self, f)
+  }
+}
+
+// A note written as a letter name plus accidentals.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Spelling {
+  pub letter: Letter,
+  // Positive for sharps, negative for flats.
+  pub accidental: i64,
+  pub octave: i64,
+}
+
+impl Spelling {
+  pub fn new(note: Note, letter: Letter) -> Self {
+    let accidental = (note.semitones - letter.semitones() + 6).rem_euclid(12) - 6;
+    Self {
+      letter,
+      accidental,
+      octave: (note.semitones - accidental - letter.semitones()).div_euclid(12) + 4,
+    }
+  }
+}
+
+impl std::fmt::Display for Spelling {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let accidental = if self.accidental >= 0 { "#" } else { "b" };
+    write!(
+      f,
+      "{}{}{}",
+      self.letter,
+      accidental.repeat(self.accidental.unsigned_abs() as usize),
+      self.octave
+    )
+  }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Note {
   // Relative to middle C (C above 440 Hz).
@@ -91,9 +163,14 @@ impl Note {
       semitones: self.semitones + semitones,
     }
   }
+  pub fn from_midi(value: u8) -> Self {
+    Note {
+      semitones: value as i64 - 60,
+    }
+  }
   pub fn midi(self) -> u8 {
     let value = self.semitones + 60;
-    if value < 0 || value > 127 {
+    if !(0..=127).contains(&value) {
       panic!("midi note out of range");
     }
     value as u8
@@ -126,14 +203,108 @@ fn test_note() {
   assert_eq!(Note::new(D, 4).offset(-12), Note::new(D, 3));
 }
 
-// Semitone intervals.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Range {
+  pub lowest: Note,
+  pub highest: Note,
+}
+
+impl Range {
+  pub fn new(lowest: Note, highest: Note) -> Self {
+    assert!(
+      highest.semitones - lowest.semitones >= 11,
+      "range must span at least an octave"
+    );
+    Self { lowest, highest }
+  }
+  // Every note representable as a MIDI note number.
+  pub fn midi() -> Self {
+    Self::new(Note { semitones: -60 }, Note { semitones: 67 })
+  }
+  pub fn contains(&self, note: Note) -> bool {
+    (self.lowest..=self.highest).contains(&note)
+  }
+  // Number of octaves the note must be displaced by to lie within the range.
+  fn octaves_to_fold(&self, note: Note) -> i64 {
+    if note < self.lowest {
+      (self.lowest.semitones - note.semitones + 11).div_euclid(12)
+    } else if note > self.highest {
+      -(note.semitones - self.highest.semitones + 11).div_euclid(12)
+    } else {
+      0
+    }
+  }
+  pub fn fold(&self, note: Note) -> Note {
+    note.offset(self.octaves_to_fold(note) * 12)
+  }
+  pub fn fold_in_key<'k>(&self, note: NoteInKey<'k>) -> NoteInKey<'k> {
+    let octaves = self.octaves_to_fold(note.note);
+    NoteInKey {
+      key: note.key,
+      note: note.note.offset(octaves * 12),
+      scale_steps: note.scale_steps + octaves * (note.key.scale.num_intervals() as i64),
+    }
+  }
+}
+
+#[test]
+fn test_range() {
+  use PitchClass::*;
+  let range = Range::new(Note::new(E, 2), Note::new(G, 4));
+  assert!(range.contains(Note::new(E, 2)));
+  assert!(range.contains(Note::new(G, 4)));
+  assert!(!range.contains(Note::new(GSharp, 4)));
+  assert_eq!(range.fold(Note::new(C, 3)), Note::new(C, 3));
+  assert_eq!(range.fold(Note::new(DSharp, 2)), Note::new(DSharp, 3));
+  assert_eq!(range.fold(Note::new(D, -1)), Note::new(D, 3));
+  assert_eq!(range.fold(Note::new(GSharp, 4)), Note::new(GSharp, 3));
+  assert_eq!(range.fold(Note::new(A, 8)), Note::new(A, 3));
+
+  let key = Key::major(Note::new(C, 4));
+  let folded = range.fold_in_key(key.at(-20));
+  assert_eq!(folded.note, Note::new(D, 3));
+  assert_eq!(folded, key.at(-6));
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+  Ascending,
+  Descending,
+}
+
+impl Direction {
+  pub fn of(scale_steps: i64) -> Self {
+    if scale_steps >= 0 {
+      Self::Ascending
+    } else {
+      Self::Descending
+    }
+  }
+}
+
+// Semitone intervals, listed upwards from the tonic. Scales whose descending
+// form differs from the ascending one (e.g. melodic minor) carry a second list.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Scale(Vec<i64>);
+pub struct Scale {
+  ascending: Vec<i64>,
+  descending: Option<Vec<i64>>,
+}
 
 impl Scale {
   pub fn from_intervals(intervals: Vec<i64>) -> Self {
     assert_eq!(intervals.iter().sum::<i64>(), 12);
-    Self(intervals)
+    Self {
+      ascending: intervals,
+      descending: None,
+    }
+  }
+  pub fn from_directional_intervals(ascending: Vec<i64>, descending: Vec<i64>) -> Self {
+    assert_eq!(ascending.len(), descending.len());
+    assert_eq!(descending.iter().sum::<i64>(), 12);
+    Self {
+      descending: Some(descending),
+      ..Self::from_intervals(ascending)
+    }
   }
   pub fn major() -> Self {
     Self::from_intervals(vec![2, 2, 1, 2, 2, 2, 1])
@@ -141,17 +312,39 @@ impl Scale {
   pub fn minor() -> Self {
     Self::from_intervals(vec![2, 1, 2, 2, 1, 2, 2])
   }
+  pub fn melodic_minor() -> Self {
+    Self::from_directional_intervals(vec![2, 1, 2, 2, 2, 2, 1], vec![2, 1, 2, 2, 1, 2, 2])
+  }
   pub fn pentatonic() -> Self {
     Self::from_intervals(vec![4, 1, 2, 4, 1])
   }
   pub fn num_intervals(&self) -> usize {
-    self.0.len()
+    self.ascending.len()
+  }
+  pub fn intervals(&self, direction: Direction) -> &[i64] {
+    match direction {
+      Direction::Ascending => &self.ascending,
+      Direction::Descending => self.descending.as_ref().unwrap_or(&self.ascending),
+    }
   }
   pub fn intervals_ascending<'a>(&'a self) -> impl Iterator<Item = i64> + 'a {
-    self.0.iter().copied().cycle()
+    self.intervals(Direction::Ascending).iter().copied().cycle()
   }
   pub fn intervals_descending<'a>(&'a self) -> impl Iterator<Item = i64> + 'a {
-    self.0.iter().rev().map(|x| -x).cycle()
+    self
+      .intervals(Direction::Descending)
+      .iter()
+      .rev()
+      .map(|x| -x)
+      .cycle()
+  }
+  // Semitones from the tonic to the given scale step, using the form of the
+  // scale that applies when moving in the given direction.
+  pub fn semitones(&self, scale_steps: i64, direction: Direction) -> i64 {
+    let intervals = self.intervals(direction);
+    let len = intervals.len() as i64;
+    let degree = scale_steps.rem_euclid(len) as usize;
+    scale_steps.div_euclid(len) * 12 + intervals[..degree].iter().sum::<i64>()
   }
 }
 
@@ -171,6 +364,20 @@ fn test_scale() {
       .collect::<Vec<_>>(),
     vec![-1, -2, -2, -2, -1, -2, -2, -1, -2, -2]
   );
+  assert_eq!(
+    Scale::melodic_minor()
+      .intervals_ascending()
+      .take(7)
+      .collect::<Vec<_>>(),
+    vec![2, 1, 2, 2, 2, 2, 1]
+  );
+  assert_eq!(
+    Scale::melodic_minor()
+      .intervals_descending()
+      .take(7)
+      .collect::<Vec<_>>(),
+    vec![-2, -2, -1, -2, -2, -1, -2]
+  );
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -192,6 +399,12 @@ impl Key {
       scale: Scale::minor(),
     }
   }
+  pub fn melodic_minor(tonic: Note) -> Self {
+    Self {
+      tonic,
+      scale: Scale::melodic_minor(),
+    }
+  }
   pub fn pentatonic(tonic: Note) -> Self {
     Self {
       tonic,
@@ -204,9 +417,127 @@ impl Key {
       scale: self.scale,
     }
   }
+  pub fn tonic(&self) -> Note {
+    self.tonic
+  }
   pub fn scale(&self) -> &Scale {
     &self.scale
   }
+  // The letter name for the tonic that gives the simplest key signature.
+  // Scales that don't have seven notes are spelled as if they were major.
+  pub fn tonic_letter(&self) -> Letter {
+    let major = Scale::major();
+    let scale = if self.scale.num_intervals() == 7 {
+      &self.scale
+    } else {
+      &major
+    };
+    let cost = |tonic_letter: Letter| -> i64 {
+      (0..7)
+        .map(|step| {
+          let note = self
+            .tonic
+            .offset(scale.semitones(step, Direction::Ascending));
+          Spelling::new(note, tonic_letter.offset(step))
+            .accidental
+            .abs()
+        })
+        .sum()
+    };
+    Letter::ALL
+      .iter()
+      .copied()
+      .filter(|&letter| Spelling::new(self.tonic, letter).accidental.abs() <= 2)
+      .min_by_key(|&letter| {
+        (
+          cost(letter),
+          Spelling::new(self.tonic, letter).accidental.abs(),
+        )
+      })
+      .unwrap()
+  }
+  pub fn spell(&self, note: &NoteInKey) -> Spelling {
+    let tonic_letter = self.tonic_letter();
+    if self.scale.num_intervals() == 7 {
+      return Spelling::new(note.note, tonic_letter.offset(note.scale_steps));
+    }
+    let major = Key::major(self.tonic);
+    let diatonic =
+      (0..7).find(|&step| major.at(step).note.pitch_class() == note.note.pitch_class());
+    if let Some(step) = diatonic {
+      return Spelling::new(note.note, tonic_letter.offset(step));
+    }
+    let flats = (0..7).any(|step| major.spell(&major.at(step)).accidental < 0);
+    Letter::ALL
+      .iter()
+      .map(|&letter| Spelling::new(note.note, letter))
+      .filter(|spelling| {
+        spelling.accidental == 0 || spelling.accidental == if flats { -1 } else { 1 }
+      })
+      .min_by_key(|spelling| spelling.accidental.abs())
+      .unwrap()
+  }
+  // Distinct pitch classes found in the key in either direction, in ascending
+  // order from the tonic.
+  pub fn pitch_classes(&self) -> Vec<PitchClass> {
+    let mut pitch_classes: Vec<PitchClass> = Vec::new();
+    for direction in [Direction::Ascending, Direction::Descending] {
+      for step in 0..self.scale.num_intervals() as i64 {
+        let pitch_class = self.at_in_direction(step, direction).note.pitch_class();
+        if !pitch_classes.contains(&pitch_class) {
+          pitch_classes.push(pitch_class);
+        }
+      }
+    }
+    let tonic = self.tonic.pitch_class().ordinal();
+    pitch_classes.sort_by_key(|pc| (pc.ordinal() - tonic).rem_euclid(12));
+    pitch_classes
+  }
+  // The major or minor key on the same tonic, for major and natural minor
+  // keys.
+  pub fn parallel(&self) -> Option<Key> {
+    if self.scale == Scale::major() {
+      Some(Key::minor(self.tonic))
+    } else if self.scale == Scale::minor() {
+      Some(Key::major(self.tonic))
+    } else {
+      None
+    }
+  }
+  pub fn common_tones(&self, other: &Key) -> usize {
+    let theirs = other.pitch_classes();
+    self
+      .pitch_classes()
+      .iter()
+      .filter(|pc| theirs.contains(pc))
+      .count()
+  }
+  // Number of sharps (positive) or flats (negative) in the key signature.
+  // The descending form of the scale is used, so melodic minor keys share the
+  // signature of their natural minor.
+  pub fn signature(&self) -> i64 {
+    let major = Scale::major();
+    let scale = if self.scale.num_intervals() == 7 {
+      &self.scale
+    } else {
+      &major
+    };
+    let tonic_letter = self.tonic_letter();
+    (0..7)
+      .map(|step| {
+        let note = self
+          .tonic
+          .offset(scale.semitones(step, Direction::Descending));
+        Spelling::new(note, tonic_letter.offset(step)).accidental
+      })
+      .sum()
+  }
+  // Steps around the circle of fifths between the two key signatures, treating
+  // enharmonically equivalent signatures as the same.
+  pub fn fifths_distance(&self, other: &Key) -> i64 {
+    let distance = (self.signature() - other.signature()).rem_euclid(12);
+    distance.min(12 - distance)
+  }
   pub fn notes_ascending<'a>(&'a self) -> impl Iterator<Item = NoteInKey<'a>> + 'a {
     self.notes_from_intervals(self.scale.intervals_ascending(), 1)
   }
@@ -230,21 +561,80 @@ impl Key {
         })
       })
   }
+  // The note of the key nearest to the given note, preferring the lower one
+  // when the note lies halfway between two.
+  pub fn quantize<'a>(&'a self, note: Note) -> NoteInKey<'a> {
+    let len = self.scale.num_intervals() as i64;
+    let octave = (note.semitones - self.tonic.semitones).div_euclid(12);
+    ((octave - 1) * len..=(octave + 2) * len)
+      .map(|step| self.at(step))
+      .min_by_key(|nk| (nk.note.semitones - note.semitones).abs())
+      .unwrap()
+  }
+  // Moves a note diatonically from another key into this one: the note is
+  // quantized to the other key, and the same scale degree is taken in this
+  // key, in whichever octave is nearest to the original note.
+  pub fn transpose_from(&self, from: &Key, note: Note) -> Note {
+    let len = self.scale.num_intervals() as i64;
+    let steps = from.quantize(note).scale_steps;
+    let octave = (note.semitones - self.at(steps).note.semitones).div_euclid(12);
+    (octave..=octave + 1)
+      .map(|octaves| self.at(steps + octaves * len).note)
+      .min_by_key(|n| (n.semitones - note.semitones).abs())
+      .unwrap()
+  }
   pub fn at<'a>(&'a self, scale_steps_from_tonic: i64) -> NoteInKey<'a> {
-    if scale_steps_from_tonic >= 0 {
-      self
-        .notes_ascending()
-        .nth(scale_steps_from_tonic as usize)
-        .unwrap()
-    } else {
-      self
-        .notes_descending()
-        .nth(-scale_steps_from_tonic as usize)
-        .unwrap()
+    self.at_in_direction(
+      scale_steps_from_tonic,
+      Direction::of(scale_steps_from_tonic),
+    )
+  }
+  pub fn at_in_direction<'a>(
+    &'a self,
+    scale_steps_from_tonic: i64,
+    direction: Direction,
+  ) -> NoteInKey<'a> {
+    NoteInKey {
+      key: self,
+      note: self
+        .tonic
+        .offset(self.scale.semitones(scale_steps_from_tonic, direction)),
+      scale_steps: scale_steps_from_tonic,
     }
   }
 }
 
+#[test]
+fn test_key_relationships() {
+  use PitchClass::*;
+  let c_major = Key::major(Note::new(C, 4));
+  assert_eq!(c_major.signature(), 0);
+  assert_eq!(Key::major(Note::new(G, 4)).signature(), 1);
+  assert_eq!(Key::minor(Note::new(C, 4)).signature(), -3);
+  assert_eq!(Key::melodic_minor(Note::new(E, 4)).signature(), 1);
+  assert_eq!(Key::major(Note::new(CSharp, 4)).signature(), -5);
+  assert_eq!(Key::pentatonic(Note::new(D, 4)).signature(), 2);
+
+  let distance = |key: Key| (c_major.fifths_distance(&key), c_major.common_tones(&key));
+  assert_eq!(distance(c_major.clone()), (0, 7));
+  assert_eq!(distance(Key::minor(Note::new(A, 3))), (0, 7));
+  assert_eq!(distance(Key::major(Note::new(F, 4))), (1, 6));
+  assert_eq!(distance(Key::major(Note::new(D, 4))), (2, 5));
+  assert_eq!(distance(Key::major(Note::new(FSharp, 4))), (6, 2));
+  assert_eq!(distance(Key::melodic_minor(Note::new(A, 3))), (0, 7));
+  assert_eq!(distance(Key::pentatonic(Note::new(C, 4))), (0, 5));
+  assert_eq!(c_major.parallel(), Some(Key::minor(Note::new(C, 4))));
+  assert_eq!(
+    Key::minor(Note::new(A, 3)).parallel(),
+    Some(Key::major(Note::new(A, 3)))
+  );
+  assert_eq!(Key::pentatonic(Note::new(C, 4)).parallel(), None);
+  assert_eq!(
+    Key::major(Note::new(CSharp, 4)).fifths_distance(&Key::major(Note::new(B, 4))),
+    2
+  );
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct NoteInKey<'k> {
   key: &'k Key,
@@ -254,7 +644,12 @@ pub struct NoteInKey<'k> {
 
 impl<'k> NoteInKey<'k> {
   pub fn offset(&self, scale_steps: i64) -> NoteInKey<'k> {
-    self.key.at(self.scale_steps + scale_steps)
+    if scale_steps == 0 {
+      return *self;
+    }
+    self
+      .key
+      .at_in_direction(self.scale_steps + scale_steps, Direction::of(scale_steps))
   }
   pub fn note(&self) -> Note {
     self.note
@@ -262,14 +657,87 @@ impl<'k> NoteInKey<'k> {
   pub fn scale_steps_from_tonic(&self) -> i64 {
     self.scale_steps
   }
+  // Display adaptor showing the scale degree, e.g. "^5".
+  pub fn degree(&self) -> Degree<'k> {
+    Degree(*self)
+  }
+  // Display adaptor showing the movable-do solfege syllable, e.g. "sol".
+  pub fn solfege(&self) -> Solfege<'k> {
+    Solfege(*self)
+  }
 }
 
 impl<'k> std::fmt::Display for NoteInKey<'k> {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-    std::fmt::Display::fmt(&self.note, f)
+    std::fmt::Display::fmt(&self.key.spell(self), f)
+  }
+}
+
+pub struct Degree<'k>(NoteInKey<'k>);
+
+impl<'k> std::fmt::Display for Degree<'k> {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let len = self.0.key.scale.num_intervals() as i64;
+    write!(f, "^{}", self.0.scale_steps.rem_euclid(len) + 1)
+  }
+}
+
+pub struct Solfege<'k>(NoteInKey<'k>);
+
+impl<'k> std::fmt::Display for Solfege<'k> {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    const CHROMATIC: [&str; 12] = [
+      "do", "di", "re", "ri", "mi", "fa", "fi", "sol", "si", "la", "li", "ti",
+    ];
+    let NoteInKey { key, note, .. } = self.0;
+    let semitones = (note.semitones - key.tonic.semitones).rem_euclid(12);
+    let letters = (key.spell(&self.0).letter.index() - key.tonic_letter().index()).rem_euclid(7);
+    let alteration = semitones - Scale::major().semitones(letters, Direction::Ascending);
+    f.write_str(match (letters, alteration) {
+      (0, -1) => "de",
+      (1, -1) => "ra",
+      (2, -1) => "me",
+      (4, -1) => "se",
+      (5, -1) => "le",
+      (6, -1) => "te",
+      _ => CHROMATIC[semitones as usize],
+    })
   }
 }
 
+#[test]
+fn test_degree_display() {
+  use PitchClass::*;
+  let key = Key::minor(Note::new(C, 4));
+  let show = |steps: std::ops::Range<i64>, f: &dyn Fn(NoteInKey) -> String| {
+    steps.map(|step| f(key.at(step))).collect::<Vec<_>>()
+  };
+  assert_eq!(
+    show(-1..8, &|n| n.degree().to_string()),
+    vec!["^7", "^1", "^2", "^3", "^4", "^5", "^6", "^7", "^1"]
+  );
+  assert_eq!(
+    show(0..7, &|n| n.solfege().to_string()),
+    vec!["do", "re", "me", "fa", "sol", "le", "te"]
+  );
+  let key = Key::melodic_minor(Note::new(A, 3));
+  assert_eq!(key.at(5).solfege().to_string(), "la");
+  assert_eq!(
+    key
+      .at_in_direction(5, Direction::Descending)
+      .solfege()
+      .to_string(),
+    "le"
+  );
+  let key = Key::pentatonic(Note::new(D, 4));
+  assert_eq!(
+    (0..5)
+      .map(|step| key.at(step).solfege().to_string())
+      .collect::<Vec<_>>(),
+    vec!["do", "mi", "fa", "sol", "ti"]
+  );
+}
+
 #[test]
 fn test_key() {
   use PitchClass::*;
@@ -313,3 +781,78 @@ fn test_key() {
     ]
   );
 }
+
+#[test]
+fn test_key_transpose() {
+  use PitchClass::*;
+  let c_major = Key::major(Note::new(C, 4));
+  assert_eq!(c_major.quantize(Note::new(E, 5)).scale_steps, 9);
+  assert_eq!(c_major.quantize(Note::new(CSharp, 3)).note, Note::new(C, 3));
+  assert_eq!(c_major.quantize(Note::new(B, 2)).scale_steps, -8);
+
+  let transpose = |key: Key, note| key.transpose_from(&c_major, note);
+  let g_major = Key::major(Note::new(G, 4));
+  assert_eq!(transpose(g_major.clone(), Note::new(C, 4)), Note::new(G, 3));
+  assert_eq!(transpose(g_major.clone(), Note::new(F, 5)), Note::new(C, 5));
+  assert_eq!(transpose(g_major, Note::new(FSharp, 5)), Note::new(C, 5));
+  let a_minor = Key::minor(Note::new(A, 3));
+  assert_eq!(transpose(a_minor.clone(), Note::new(E, 4)), Note::new(C, 4));
+  let c_minor = Key::minor(Note::new(C, 2));
+  assert_eq!(
+    transpose(c_minor.clone(), Note::new(E, 4)),
+    Note::new(DSharp, 4)
+  );
+  assert_eq!(transpose(c_minor, Note::new(G, 1)), Note::new(G, 1));
+}
+
+#[test]
+fn test_key_direction() {
+  use PitchClass::*;
+  let key = Key::melodic_minor(Note::new(C, 4));
+  assert_eq!(key.at(5).note, Note::new(A, 4));
+  assert_eq!(key.at(-2).note, Note::new(GSharp, 3));
+  assert_eq!(
+    key.at_in_direction(5, Direction::Descending).note,
+    Note::new(GSharp, 4)
+  );
+  assert_eq!(key.at(7).offset(-1).note, Note::new(ASharp, 4));
+  assert_eq!(key.at(7).offset(-2).note, Note::new(GSharp, 4));
+  assert_eq!(key.at(4).offset(1).note, Note::new(A, 4));
+  assert_eq!(key.at(4).offset(2).note, Note::new(B, 4));
+}
+
+#[test]
+fn test_spelling() {
+  use PitchClass::*;
+  let spelled = |key: &Key, steps: std::ops::Range<i64>| {
+    steps
+      .map(|step| key.at(step).to_string())
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    spelled(&Key::minor(Note::new(C, 4)), 0..8),
+    vec!["C4", "D4", "Eb4", "F4", "G4", "Ab4", "Bb4", "C5"]
+  );
+  assert_eq!(
+    spelled(&Key::major(Note::new(DSharp, 4)), 0..3),
+    vec!["Eb4", "F4", "G4"]
+  );
+  assert_eq!(
+    spelled(&Key::minor(Note::new(GSharp, 3)), 0..8),
+    vec!["G#3", "A#3", "B3", "C#4", "D#4", "E4", "F#4", "G#4"]
+  );
+  assert_eq!(
+    spelled(&Key::major(Note::new(FSharp, 4)), 5..8),
+    vec!["D#5", "E#5", "F#5"]
+  );
+  assert_eq!(
+    spelled(&Key::pentatonic(Note::new(D, 4)), 0..6),
+    vec!["D4", "F#4", "G4", "A4", "C#5", "D5"]
+  );
+  assert_eq!(
+    spelled(&Key::pentatonic(Note::new(F, 4)), 0..5),
+    vec!["F4", "A4", "Bb4", "C5", "E5"]
+  );
+  assert_eq!(Spelling::new(Note::new(B, 3), Letter::C).to_string(), "Cb4");
+  assert_eq!(Spelling::new(Note::new(C, 4), Letter::B).to_string(), "B#3");
+}
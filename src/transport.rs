@@ -0,0 +1,117 @@
+use crate::midi::state::ChannelState;
+use crate::midi::{Message, MessageExt, CHANNELS};
+use crate::stream::Stream;
+use midir::{MidiOutput, MidiOutputConnection};
+use std::time::Duration;
+
+// Real time playback to an output port.
+pub struct Output {
+  connection: MidiOutputConnection,
+}
+
+impl Output {
+  // Connects to the first port whose name starts with the prefix.
+  pub fn connect(port_prefix: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    let output = MidiOutput::new("avril")?;
+    let ports = output.ports();
+    let port = ports
+      .iter()
+      .find(|port| {
+        output
+          .port_name(port)
+          .unwrap_or_default()
+          .starts_with(port_prefix)
+      })
+      .ok_or("no matching MIDI output port")?;
+    let connection = output.connect(port, "avril_port")?;
+    Ok(Self { connection })
+  }
+  pub fn send(&mut self, message: &Message) -> Result<(), Box<dyn std::error::Error>> {
+    self.connection.send(&message.encode())?;
+    Ok(())
+  }
+  // Plays the stream in real time, returning once it ends.
+  pub fn play(&mut self, messages: Stream<Message>) -> Result<(), Box<dyn std::error::Error>> {
+    for (delay, message) in messages {
+      std::thread::sleep(delay);
+      self.send(&message)?;
+    }
+    Ok(())
+  }
+}
+
+// Silences every channel.
+pub fn panic() -> Vec<Message> {
+  CHANNELS
+    .iter()
+    .map(|&ch| Message::AllNotesOff(ch))
+    .collect()
+}
+
+// Starts playback part way through a stream. Notes still sounding from
+// before the jump are silenced, and the programs, controllers and pitch bends
+// that would be in effect at the new position are sent before playback
+// resumes.
+pub fn seek<'a>(messages: Stream<'a, Message>, position: Duration) -> Stream<'a, Message> {
+  Stream::lazy(move || {
+    let mut state = ChannelState::new();
+    let mut now = Duration::from_secs(0);
+    let mut rest = messages.into_iter();
+    let first = loop {
+      match rest.next() {
+        Some((d, message)) if now + d < position => {
+          now += d;
+          state.update(&message);
+        }
+        Some((d, message)) => break Some((now + d - position, message)),
+        None => break None,
+      }
+    };
+    let resync: Vec<_> = panic()
+      .into_iter()
+      .chain(state.restore())
+      .map(|message| (Duration::from_secs(0), message))
+      .collect();
+    Stream::from_iter(resync)
+      .chain(Stream::from_iter(first))
+      .chain(Stream::from_iter(rest))
+  })
+}
+
+#[test]
+fn test_seek() {
+  use crate::midi::Channel;
+  let ms = Duration::from_millis;
+  let (ch1, ch2) = (Channel::Ch1, Channel::Ch2);
+  let messages = || {
+    Stream::from_iter(vec![
+      (ms(0), Message::ProgramChange(ch1, 5)),
+      (ms(0), Message::ControlChange(ch1, 7, 100)),
+      (ms(0), Message::ControlChange(ch1, 0, 1)),
+      (ms(0), Message::NoteOn(ch1, 60, 80)),
+      (ms(10), Message::ControlChange(ch1, 7, 90)),
+      (ms(10), Message::PitchBend(ch2, 9000)),
+      (ms(10), Message::NoteOff(ch1, 60, 0x40)),
+    ])
+  };
+  let seeked = seek(messages(), ms(25)).collect_timed();
+  let resync = &seeked[..seeked.len() - 1];
+  assert_eq!(
+    &resync[..16],
+    &panic().into_iter().map(|m| (ms(0), m)).collect::<Vec<_>>()[..]
+  );
+  assert_eq!(
+    &resync[16..],
+    &[
+      (ms(0), Message::ControlChange(ch1, 0, 1)),
+      (ms(0), Message::ProgramChange(ch1, 5)),
+      (ms(0), Message::ControlChange(ch1, 7, 90)),
+      (ms(0), Message::PitchBend(ch2, 9000)),
+    ]
+  );
+  assert_eq!(
+    seeked.last(),
+    Some(&(ms(5), Message::NoteOff(ch1, 60, 0x40)))
+  );
+  assert_eq!(seek(messages(), ms(100)).count_events(), 16 + 4);
+}
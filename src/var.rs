@@ -1,4 +1,11 @@
-use crate::stream::Stream;
+use crate::seed::Seed;
+use crate::stream::{Events, SourceId, Stream};
+use itertools::Itertools;
+use rand::Rng;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::rc::Rc;
 use std::time::Duration;
 
 pub struct Var<'a, T> {
@@ -13,6 +20,25 @@ impl<'a, T> Var<'a, T> {
       future: Stream::empty(),
     }
   }
+  // Samples a function of the time since the start every `resolution`, for
+  // parameters that are easier to write as curves than as updates. Never
+  // ends.
+  pub fn from_fn<F>(resolution: Duration, mut func: F) -> Self
+  where
+    F: FnMut(Duration) -> T + 'a,
+  {
+    assert!(
+      resolution > Duration::from_secs(0),
+      "resolution must be positive"
+    );
+    let present = func(Duration::from_secs(0));
+    let mut now = Duration::from_secs(0);
+    let future = Stream::from_iter(std::iter::repeat_with(move || {
+      now += resolution;
+      (resolution, func(now))
+    }));
+    Self { present, future }
+  }
   pub fn from_updates(initial_value: T, updates: Stream<'a, T>) -> Self {
     Self {
       present: initial_value,
@@ -31,6 +57,173 @@ impl<'a, T> Var<'a, T> {
       future: self.future.map(func),
     }
   }
+  // Like map, but with a state shared between successive calls, starting
+  // with the present value, so a Var can follow its own history.
+  pub fn scan<S, F, U>(self, mut state: S, mut func: F) -> Var<'a, U>
+  where
+    S: 'a,
+    F: FnMut(&mut S, T) -> U + 'a,
+  {
+    let present = func(&mut state, self.present);
+    Var {
+      present,
+      future: self.future.scan(state, func),
+    }
+  }
+  // The value held at every multiple of the interval, for turning a
+  // continuously varying parameter into periodic messages. An update falling
+  // exactly on a sample time is included in that sample. Never ends.
+  pub fn sample_every(self, interval: Duration) -> Stream<'a, T>
+  where
+    T: Clone,
+  {
+    assert!(
+      interval > Duration::from_secs(0),
+      "sample interval must be positive"
+    );
+    enum Sample<T> {
+      Update(T),
+      Tick,
+    }
+    let mut current = None;
+    self
+      .updates()
+      .map(Sample::Update)
+      .merge(
+        Stream::immediate(())
+          .repeat_every(interval)
+          .map(|()| Sample::Tick),
+      )
+      .filter_map(move |sample| match sample {
+        Sample::Update(value) => {
+          current = Some(value);
+          None
+        }
+        Sample::Tick => current.clone(),
+      })
+  }
+  // Logs the value and each update with its time since the start, at debug
+  // level, to find out why a parameter holds the value it does.
+  pub fn inspect(self, label: &'a str) -> Self
+  where
+    T: Debug,
+  {
+    log::debug!(
+      "{} at {:?}: {:?}",
+      label,
+      Duration::from_secs(0),
+      self.present
+    );
+    Self {
+      present: self.present,
+      future: self.future.inspect_timed(move |now, value| {
+        log::debug!("{} at {:?}: {:?}", label, now, value);
+      }),
+    }
+  }
+  // Keeps the present value but makes every later update `duration` late,
+  // such as for one voice to echo another's control curve.
+  pub fn delay(self, duration: Duration) -> Self {
+    Self {
+      present: self.present,
+      future: self.future.delay(duration),
+    }
+  }
+  // Drops updates that don't change the value, such as after mapping a
+  // continuous Var to a few coarse steps.
+  pub fn dedup(self) -> Self
+  where
+    T: Clone + PartialEq,
+  {
+    Self {
+      present: self.present.clone(),
+      future: self.updates().dedup().skip_n(1),
+    }
+  }
+  // The value held at the given time since the start, including any update
+  // made at exactly that time.
+  pub fn value_at(self, time: Duration) -> T {
+    let (_, value) = self.updates().take(time).into_iter().last().unwrap();
+    value
+  }
+  // Makes the Var usable as the input to several consumers, such as one
+  // intensity curve driving both velocity and density.
+  pub fn share(self) -> SharedVar<'a, T> {
+    let (source, endless) = self.future.into_parts();
+    SharedVar {
+      present: self.present,
+      endless,
+      broadcast: Rc::new(RefCell::new(Broadcast {
+        source,
+        buffer: VecDeque::new(),
+        start: 0,
+        readers: vec![Some(0)],
+      })),
+      reader: 0,
+    }
+  }
+  // Pairs the values of two Vars, updating whenever either does. Updates to
+  // both at the same time give a single update.
+  pub fn zip<U>(self, other: Var<'a, U>) -> Var<'a, (T, U)>
+  where
+    T: Clone + 'a,
+    U: Clone + 'a,
+  {
+    enum Side<T, U> {
+      Left(T),
+      Right(U),
+    }
+    let mut left = self.present.clone();
+    let mut right = other.present.clone();
+    let future = self
+      .future
+      .map(Side::Left)
+      .merge(other.future.map(Side::Right))
+      .group_simultaneous()
+      .map(move |sides| {
+        for side in sides {
+          match side {
+            Side::Left(value) => left = value,
+            Side::Right(value) => right = value,
+          }
+        }
+        (left.clone(), right.clone())
+      });
+    Var {
+      present: (self.present, other.present),
+      future,
+    }
+  }
+  // The values of many Vars in order, updating whenever any of them does.
+  // Updates to several at the same time give a single update.
+  pub fn combine<I>(vars: I) -> Var<'a, Vec<T>>
+  where
+    I: IntoIterator<Item = Self>,
+    T: Clone + 'a,
+  {
+    let (present, futures): (Vec<T>, Vec<_>) = vars
+      .into_iter()
+      .map(|var| (var.present, var.future))
+      .unzip();
+    let mut values = present.clone();
+    let future = Stream::merge_all_labeled(futures)
+      .group_simultaneous()
+      .map(move |updates| {
+        for (SourceId(i), value) in updates {
+          values[i] = value;
+        }
+        values.clone()
+      });
+    Var { present, future }
+  }
+  pub fn map2<U, F, V>(self, other: Var<'a, U>, mut func: F) -> Var<'a, V>
+  where
+    T: Clone + 'a,
+    U: Clone + 'a,
+    F: FnMut(T, U) -> V + 'a,
+  {
+    self.zip(other).map(move |(t, u)| func(t, u))
+  }
   pub fn repeat_every(self, interval: Duration) -> Self
   where
     T: Clone,
@@ -40,6 +233,141 @@ impl<'a, T> Var<'a, T> {
       future: self.updates().repeat_every(interval),
     }
   }
+  // Like repeat_every, but when the value held at the end of the interval is
+  // the same as the one the loop restarts with, it is tied across the seam
+  // instead of being restated as a fresh update.
+  pub fn repeat_every_tied(self, interval: Duration) -> Self
+  where
+    T: Clone + PartialEq,
+  {
+    let mut sample: Vec<_> = self.updates().take(interval).into_iter().collect();
+    let (_, present) = sample.remove(0);
+    let tied = sample.last().is_none_or(|(_, last)| *last == present);
+    let mut looped = sample.clone();
+    if !tied {
+      looped.insert(0, (Duration::from_secs(0), present.clone()));
+    }
+    let future = if looped.is_empty() {
+      Stream::empty()
+    } else {
+      Stream::from_iter(sample).chain_at(interval, Stream::from_iter(looped).repeat_every(interval))
+    };
+    Self { present, future }
+  }
+}
+
+impl<'a> Var<'a, f64> {
+  // Moves away from this Var's values to the target over `duration`, in
+  // updates every `resolution` shaped by the easing, then holds the target.
+  pub fn ease_to(
+    self,
+    target: f64,
+    duration: Duration,
+    easing: Easing,
+    resolution: Duration,
+  ) -> Self {
+    assert!(
+      resolution > Duration::from_secs(0),
+      "resolution must be positive"
+    );
+    let times = std::iter::successors(Some(resolution), move |&time| Some(time + resolution))
+      .take_while(move |&time| time < duration)
+      .chain(std::iter::once(duration));
+    let progress = Var::from_updates(
+      0.0,
+      Stream::from_timestamps(
+        times.map(move |time| (time, time.as_secs_f64() / duration.as_secs_f64())),
+      ),
+    );
+    self.map2(progress, move |value, progress| {
+      value + (target - value) * easing.apply(progress.min(1.0))
+    })
+  }
+  // Glides from each value to the next instead of stepping, in updates every
+  // `resolution` that reach the next value at the time it was due. Stepped
+  // changes to a controller or pitch bend can be heard as zipper noise.
+  pub fn lerp_updates(self, resolution: Duration) -> Self {
+    assert!(
+      resolution > Duration::from_secs(0),
+      "resolution must be positive"
+    );
+    let present = self.present;
+    let future =
+      self
+        .updates()
+        .timestamps()
+        .tuple_windows()
+        .flat_map(move |((start, from), (end, to))| {
+          let length = (end - start).as_secs_f64();
+          let steps =
+            std::iter::successors(Some(start + resolution), move |&now| Some(now + resolution))
+              .take_while(move |&now| now < end)
+              .map(move |now| {
+                let progress = (now - start).as_secs_f64() / length;
+                (now, from + (to - from) * progress)
+              });
+          steps.chain(std::iter::once((end, to)))
+        });
+    Self {
+      present,
+      future: Stream::from_timestamps(future),
+    }
+  }
+}
+
+impl<'a, T: Clone + 'a> Var<'a, Option<T>> {
+  // None whenever the switch is off, such as to mute a voice's pitch.
+  pub fn gate(self, on: Var<'a, bool>) -> Self {
+    self.map2_changes(
+      on,
+      |value, on| value.filter(|_| on),
+      |value, &was, &now| value.is_some() && was != now,
+    )
+  }
+  // The fallback's value whenever this one is None.
+  pub fn or(self, fallback: Self) -> Self {
+    self.map2_changes(fallback, Option::or, |value, _, _| value.is_none())
+  }
+  // Like map2, but an update to `other` only passes through if `changes`,
+  // given this Var's value and the other's values before and after, says it
+  // changes the result. Each update to this Var still passes through, so a
+  // held note isn't struck again by updates to the other.
+  fn map2_changes<U, F, C>(self, other: Var<'a, U>, mut func: F, mut changes: C) -> Self
+  where
+    U: Clone + 'a,
+    F: FnMut(Option<T>, U) -> Option<T> + 'a,
+    C: FnMut(&Option<T>, &U, &U) -> bool + 'a,
+  {
+    enum Side<T, U> {
+      Left(T),
+      Right(U),
+    }
+    let mut value = self.present.clone();
+    let mut other_value = other.present.clone();
+    let present = func(self.present, other.present);
+    let future = self
+      .future
+      .map(Side::Left)
+      .merge(other.future.map(Side::Right))
+      .group_simultaneous()
+      .filter_map(move |sides| {
+        let mut update = false;
+        for side in sides {
+          match side {
+            Side::Left(next) => {
+              value = next;
+              update = true;
+            }
+            Side::Right(next) => {
+              update |= changes(&value, &other_value, &next);
+              other_value = next;
+            }
+          }
+        }
+        update.then(|| func(value.clone(), other_value.clone()))
+      });
+    Var { present, future }
+  }
 }
 
 impl<'a, T> Var<'a, Stream<'a, T>> {
@@ -67,8 +395,642 @@ impl<'a, T> Var<'a, Var<'a, T>> {
   }
 }
 
+// An attack, decay, sustain, release envelope, from silent at 0 to the peak
+// at 1, for shaping velocity, a controller or anything else over a note.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+  pub attack: Duration,
+  pub decay: Duration,
+  // Level held after the decay until the note is released.
+  pub sustain: f64,
+  pub release: Duration,
+}
+
+impl Envelope {
+  // Level at the given time after the trigger, for a note held for `held`.
+  // A note released before reaching the sustain level releases from wherever
+  // it got to.
+  pub fn level(&self, time: Duration, held: Duration) -> f64 {
+    if time >= held {
+      let released = (time - held).as_secs_f64();
+      let release = self.release.as_secs_f64();
+      if released >= release {
+        return 0.0;
+      }
+      return self.level(held, Duration::MAX) * (1.0 - released / release);
+    }
+    if time < self.attack {
+      return time.as_secs_f64() / self.attack.as_secs_f64();
+    }
+    let decayed = time - self.attack;
+    if decayed < self.decay {
+      return 1.0 - (1.0 - self.sustain) * decayed.as_secs_f64() / self.decay.as_secs_f64();
+    }
+    self.sustain
+  }
+  // The envelope of a note triggered at `trigger` and held for `held`,
+  // updated every `resolution` until it has fully released.
+  pub fn var<'a>(self, trigger: Duration, held: Duration, resolution: Duration) -> Var<'a, f64> {
+    assert!(
+      resolution > Duration::from_secs(0),
+      "resolution must be positive"
+    );
+    let end = held + self.release;
+    let times = std::iter::successors(Some(resolution), move |&time| Some(time + resolution))
+      .take_while(move |&time| time < end)
+      .chain(std::iter::once(end));
+    let future = Stream::from_timestamps(times.map(move |time| (time, self.level(time, held))));
+    Var::from_updates(0.0, future.delay(trigger).dedup())
+  }
+}
+
+// Shapes of transition for Var::ease_to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Easing {
+  Linear,
+  // Slow to start and quickening, as the ear hears a steady crescendo.
+  Exponential,
+  // Slow at both ends, as in a filter sweep that settles.
+  Sigmoid,
+}
+
+impl Easing {
+  // How far through the transition to be, from 0 to 1, at the given
+  // proportion of its time.
+  pub fn apply(self, progress: f64) -> f64 {
+    match self {
+      Self::Linear => progress,
+      Self::Exponential => (f64::exp(5.0 * progress) - 1.0) / (f64::exp(5.0) - 1.0),
+      Self::Sigmoid => {
+        let logistic = |x: f64| 1.0 / (1.0 + f64::exp(-10.0 * (x - 0.5)));
+        (logistic(progress) - logistic(0.0)) / (logistic(1.0) - logistic(0.0))
+      }
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Waveform {
+  Sine,
+  Triangle,
+  Square,
+  // A new random level at the start of each cycle, held until the next.
+  RandomStep,
+}
+
+impl Waveform {
+  // Level between -1 and 1 after the given number of cycles.
+  fn level(self, cycles: f64, seed: &Seed) -> f64 {
+    let phase = cycles.fract();
+    match self {
+      Self::Sine => (phase * std::f64::consts::TAU).sin(),
+      Self::Triangle => 4.0 * ((phase - 0.25).rem_euclid(1.0) - 0.5).abs() - 1.0,
+      Self::Square => {
+        if phase < 0.5 {
+          1.0
+        } else {
+          -1.0
+        }
+      }
+      Self::RandomStep => seed.fork(cycles.floor() as u64).rng().gen_range(-1.0..=1.0),
+    }
+  }
+}
+
+// A low-frequency oscillator, swinging between -depth and depth `frequency`
+// times a second and updated every `resolution`. Add an offset and map it to
+// modulate a velocity or controller. Never ends.
+pub fn lfo<'a>(
+  waveform: Waveform,
+  frequency: f64,
+  depth: f64,
+  resolution: Duration,
+  seed: Seed,
+) -> Var<'a, f64> {
+  assert!(frequency > 0.0, "frequency must be positive");
+  Var::from_fn(resolution, move |now| {
+    depth * waveform.level(now.as_secs_f64() * frequency, &seed)
+  })
+}
+
+// A Var shared by any number of consumers, each made with var and each given
+// every update. Updates are buffered until every consumer has passed them, and
+// the SharedVar itself holds on to all of them so that later consumers start
+// from the beginning, so drop it once the consumers have all been made.
+pub struct SharedVar<'a, T> {
+  present: T,
+  endless: Rc<Cell<bool>>,
+  broadcast: Rc<RefCell<Broadcast<'a, T>>>,
+  reader: usize,
+}
+
+impl<'a, T: Clone + 'a> SharedVar<'a, T> {
+  pub fn var(&self) -> Var<'a, T> {
+    let reader = self.broadcast.borrow_mut().join();
+    Var {
+      present: self.present.clone(),
+      future: Stream::from_parts(
+        Box::new(BroadcastReader {
+          broadcast: self.broadcast.clone(),
+          reader,
+        }),
+        self.endless.clone(),
+      ),
+    }
+  }
+}
+
+impl<'a, T: Clone> Clone for SharedVar<'a, T> {
+  fn clone(&self) -> Self {
+    Self {
+      present: self.present.clone(),
+      endless: self.endless.clone(),
+      broadcast: self.broadcast.clone(),
+      reader: self.broadcast.borrow_mut().join(),
+    }
+  }
+}
+
+impl<'a, T> Drop for SharedVar<'a, T> {
+  fn drop(&mut self) {
+    self.broadcast.borrow_mut().leave(self.reader);
+  }
+}
+
+struct Broadcast<'a, T> {
+  source: Events<'a, T>,
+  // Updates (or rests) read from the source that not every reader has passed,
+  // and the position in the source of the first of them.
+  buffer: VecDeque<(Duration, Option<T>)>,
+  start: usize,
+  // Position in the source of each reader, or None once it has been dropped.
+  readers: Vec<Option<usize>>,
+}
+impl<'a, T> Broadcast<'a, T> {
+  // Readers start from the beginning, which is still buffered while the
+  // SharedVar they are made from exists.
+  fn join(&mut self) -> usize {
+    self.readers.push(Some(self.start));
+    self.readers.len() - 1
+  }
+  fn leave(&mut self, reader: usize) {
+    self.readers[reader] = None;
+    self.trim();
+  }
+  fn read(&mut self, reader: usize) -> Option<(Duration, Option<T>)>
+  where
+    T: Clone,
+  {
+    let position = self.readers[reader].unwrap();
+    if position == self.start + self.buffer.len() {
+      let update = self.source.next()?;
+      self.buffer.push_back(update);
+    }
+    let update = self.buffer[position - self.start].clone();
+    self.readers[reader] = Some(position + 1);
+    self.trim();
+    Some(update)
+  }
+  fn trim(&mut self) {
+    let end = self.start + self.buffer.len();
+    let oldest = self.readers.iter().flatten().min().map_or(end, |&p| p);
+    while self.start < oldest {
+      self.buffer.pop_front();
+      self.start += 1;
+    }
+  }
+}
+struct BroadcastReader<'a, T> {
+  broadcast: Rc<RefCell<Broadcast<'a, T>>>,
+  reader: usize,
+}
+impl<'a, T: Clone> Iterator for BroadcastReader<'a, T> {
+  type Item = (Duration, Option<T>);
+  fn next(&mut self) -> Option<(Duration, Option<T>)> {
+    self.broadcast.borrow_mut().read(self.reader)
+  }
+}
+impl<'a, T> Drop for BroadcastReader<'a, T> {
+  fn drop(&mut self) {
+    self.broadcast.borrow_mut().leave(self.reader);
+  }
+}
+
 impl<'a, T> AsRef<T> for Var<'a, T> {
   fn as_ref(&self) -> &T {
     &self.present
   }
 }
+
+#[test]
+fn test_repeat_every_tied() {
+  let ms = Duration::from_millis;
+  let updates = |var: Var<u32>| var.updates().into_iter().take(6).collect::<Vec<_>>();
+  let var = Var::from_updates(1, Stream::from_iter(vec![(ms(10), 2), (ms(10), 1)]));
+  assert_eq!(
+    updates(var.repeat_every_tied(ms(30))),
+    vec![
+      (ms(0), 1),
+      (ms(10), 2),
+      (ms(10), 1),
+      (ms(20), 2),
+      (ms(10), 1),
+      (ms(20), 2),
+    ]
+  );
+  let var = Var::from_updates(1, Stream::from_iter(vec![(ms(10), 2)]));
+  assert_eq!(
+    updates(var.repeat_every_tied(ms(30))),
+    vec![
+      (ms(0), 1),
+      (ms(10), 2),
+      (ms(20), 1),
+      (ms(10), 2),
+      (ms(20), 1),
+      (ms(10), 2),
+    ]
+  );
+  let var = Var::constant(1);
+  assert_eq!(updates(var.repeat_every_tied(ms(30))), vec![(ms(0), 1)]);
+}
+
+#[test]
+fn test_sample_every() {
+  let ms = Duration::from_millis;
+  let var = Var::from_updates(1, Stream::from_iter(vec![(ms(25), 2), (ms(5), 3)]));
+  let samples = var.sample_every(ms(10)).take_n(5).collect_timed();
+  assert_eq!(
+    samples,
+    vec![
+      (ms(0), 1),
+      (ms(10), 1),
+      (ms(10), 1),
+      (ms(10), 3),
+      (ms(10), 3)
+    ]
+  );
+}
+
+#[test]
+fn test_zip() {
+  use crate::event::NoteEvent;
+  use crate::theory::{Note, PitchClass};
+  let ms = Duration::from_millis;
+  let pitch = Var::from_updates(
+    Note::new(PitchClass::C, 4),
+    Stream::from_iter(vec![
+      (ms(100), Note::new(PitchClass::E, 4)),
+      (ms(100), Note::new(PitchClass::G, 4)),
+    ]),
+  );
+  let velocity = Var::from_updates(80, Stream::from_iter(vec![(ms(150), 100), (ms(50), 60)]));
+  let events = pitch
+    .map2(velocity, |note, velocity| {
+      NoteEvent::new(note, velocity, ms(50))
+    })
+    .updates()
+    .map(|event| (event.note, event.velocity))
+    .collect_timed();
+  assert_eq!(
+    events,
+    vec![
+      (ms(0), (Note::new(PitchClass::C, 4), 80)),
+      (ms(100), (Note::new(PitchClass::E, 4), 80)),
+      (ms(50), (Note::new(PitchClass::E, 4), 100)),
+      (ms(50), (Note::new(PitchClass::G, 4), 60)),
+    ]
+  );
+  let zipped = Var::constant('a').zip(Var::from_updates(1, Stream::from_iter(vec![(ms(10), 2)])));
+  assert_eq!(
+    zipped.updates().collect_timed(),
+    vec![(ms(0), ('a', 1)), (ms(10), ('a', 2))]
+  );
+}
+
+#[test]
+fn test_combine() {
+  let ms = Duration::from_millis;
+  let levels = Var::combine(vec![
+    Var::from_updates(0.5, Stream::from_iter(vec![(ms(10), 0.25), (ms(10), 1.0)])),
+    Var::constant(1.0),
+    Var::from_updates(0.0, Stream::from_iter(vec![(ms(20), 0.5)])),
+  ]);
+  assert_eq!(
+    levels.updates().collect_timed(),
+    vec![
+      (ms(0), vec![0.5, 1.0, 0.0]),
+      (ms(10), vec![0.25, 1.0, 0.0]),
+      (ms(10), vec![1.0, 1.0, 0.5]),
+    ]
+  );
+  let none: Vec<Var<u8>> = Vec::new();
+  assert_eq!(
+    Var::combine(none).updates().collect_timed(),
+    vec![(ms(0), vec![])]
+  );
+}
+
+#[test]
+fn test_value_at() {
+  let ms = Duration::from_millis;
+  let var = || Var::from_updates('a', Stream::from_iter(vec![(ms(10), 'b'), (ms(10), 'c')]));
+  assert_eq!(var().value_at(ms(0)), 'a');
+  assert_eq!(var().value_at(ms(9)), 'a');
+  assert_eq!(var().value_at(ms(10)), 'b');
+  assert_eq!(var().value_at(ms(1000)), 'c');
+  assert_eq!(var().repeat_every(ms(30)).value_at(ms(1005)), 'b');
+}
+
+#[test]
+fn test_from_fn() {
+  let ms = Duration::from_millis;
+  let density = Var::from_fn(ms(250), |now| now.as_secs_f64() / 2.0);
+  assert_eq!(
+    density.updates().take_n(4).collect_timed(),
+    vec![
+      (ms(0), 0.0),
+      (ms(250), 0.125),
+      (ms(250), 0.25),
+      (ms(250), 0.375)
+    ]
+  );
+  let minutes = Var::from_fn(Duration::from_secs(1), |now| now.as_secs() / 60);
+  assert_eq!(minutes.value_at(Duration::from_secs(600)), 10);
+}
+
+#[test]
+fn test_lerp_updates() {
+  let ms = Duration::from_millis;
+  let var = Var::from_updates(
+    0.0,
+    Stream::from_iter(vec![(ms(40), 1.0), (ms(0), 0.5), (ms(16), 0.0)]),
+  );
+  crate::assert_stream_eq!(
+    var.lerp_updates(ms(10)).updates(),
+    vec![
+      (ms(0), 0.0),
+      (ms(10), 0.25),
+      (ms(10), 0.5),
+      (ms(10), 0.75),
+      (ms(10), 1.0),
+      (ms(0), 0.5),
+      (ms(10), 0.1875),
+      (ms(6), 0.0),
+    ],
+  );
+  assert_eq!(
+    Var::constant(2.0)
+      .lerp_updates(ms(10))
+      .updates()
+      .count_events(),
+    1
+  );
+}
+
+#[test]
+fn test_envelope() {
+  let ms = Duration::from_millis;
+  let envelope = Envelope {
+    attack: ms(20),
+    decay: ms(40),
+    sustain: 0.5,
+    release: ms(100),
+  };
+  assert_eq!(envelope.level(ms(10), ms(500)), 0.5);
+  assert_eq!(envelope.level(ms(20), ms(500)), 1.0);
+  assert_eq!(envelope.level(ms(40), ms(500)), 0.75);
+  assert_eq!(envelope.level(ms(300), ms(500)), 0.5);
+  assert_eq!(envelope.level(ms(550), ms(500)), 0.25);
+  assert_eq!(envelope.level(ms(600), ms(500)), 0.0);
+  // Released halfway through the attack.
+  assert_eq!(envelope.level(ms(10), ms(10)), 0.5);
+  assert_eq!(envelope.level(ms(60), ms(10)), 0.25);
+
+  let updates = envelope
+    .var(ms(1000), ms(200), ms(20))
+    .updates()
+    .timestamps()
+    .collect::<Vec<_>>();
+  assert_eq!(updates[0], (ms(0), 0.0));
+  assert_eq!(updates[1], (ms(1020), 1.0));
+  assert_eq!(updates[2], (ms(1040), 0.75));
+  assert_eq!(updates[3], (ms(1060), 0.5));
+  assert_eq!(updates[4], (ms(1220), 0.4));
+  assert_eq!(*updates.last().unwrap(), (ms(1300), 0.0));
+}
+
+#[test]
+fn test_lfo() {
+  let ms = Duration::from_millis;
+  let levels = |waveform| {
+    lfo(waveform, 2.0, 10.0, ms(125), Seed::new("lfo"))
+      .updates()
+      .take_n(9)
+      .map(|level| (level * 1000.0).round() / 1000.0)
+      .collect_timed()
+      .into_iter()
+      .map(|(_, level)| level)
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    levels(Waveform::Sine),
+    vec![0.0, 10.0, 0.0, -10.0, 0.0, 10.0, 0.0, -10.0, 0.0]
+  );
+  assert_eq!(
+    levels(Waveform::Triangle),
+    vec![0.0, 10.0, 0.0, -10.0, 0.0, 10.0, 0.0, -10.0, 0.0]
+  );
+  assert_eq!(
+    levels(Waveform::Square),
+    vec![10.0, 10.0, -10.0, -10.0, 10.0, 10.0, -10.0, -10.0, 10.0]
+  );
+  let steps = levels(Waveform::RandomStep);
+  assert!(steps
+    .chunks(4)
+    .all(|cycle| cycle.iter().all(|&level| level == cycle[0])));
+  assert!(steps.iter().all(|level| level.abs() <= 10.0));
+  assert_ne!(steps[0], steps[4]);
+  assert_eq!(steps, levels(Waveform::RandomStep));
+}
+
+#[test]
+fn test_dedup() {
+  let ms = Duration::from_millis;
+  let steps = Var::from_fn(ms(10), |now| now.as_millis() / 25)
+    .dedup()
+    .updates()
+    .take_n(4)
+    .collect_timed();
+  assert_eq!(
+    steps,
+    vec![(ms(0), 0), (ms(30), 1), (ms(20), 2), (ms(30), 3)]
+  );
+  let var = Var::from_updates(
+    1,
+    Stream::from_iter(vec![(ms(5), 1), (ms(5), 2), (ms(5), 2)]),
+  );
+  assert_eq!(
+    var.dedup().updates().collect_timed(),
+    vec![(ms(0), 1), (ms(10), 2)]
+  );
+  // Updates stop changing the value, but time still passes.
+  let steps = Var::from_fn(ms(10), |t| t.min(ms(50)).as_millis() / 25)
+    .dedup()
+    .updates()
+    .take(ms(200));
+  assert_eq!(
+    steps.collect_timed(),
+    vec![(ms(0), 0), (ms(30), 1), (ms(20), 2)]
+  );
+}
+
+#[test]
+fn test_scan() {
+  let ms = Duration::from_millis;
+  let level = Var::from_updates(0.0, Stream::from_iter(vec![(ms(10), 1.0); 3]));
+  let smoothed = level.scan(0.0, |smoothed, level| {
+    *smoothed += (level - *smoothed) / 2.0;
+    *smoothed
+  });
+  assert_eq!(
+    smoothed.updates().collect_timed(),
+    vec![(ms(0), 0.0), (ms(10), 0.5), (ms(10), 0.75), (ms(10), 0.875)]
+  );
+}
+
+#[test]
+fn test_delay() {
+  let ms = Duration::from_millis;
+  let var = Var::from_updates(1, Stream::from_iter(vec![(ms(10), 2), (ms(10), 3)]));
+  assert_eq!(
+    var.delay(ms(5)).updates().collect_timed(),
+    vec![(ms(0), 1), (ms(15), 2), (ms(10), 3)]
+  );
+}
+
+#[test]
+fn test_gate() {
+  let ms = Duration::from_millis;
+  let pitch = || {
+    Var::from_updates(
+      Some('c'),
+      Stream::from_iter(vec![(ms(10), None), (ms(10), Some('e'))]),
+    )
+  };
+  let on = Var::from_updates(
+    true,
+    Stream::from_iter(vec![(ms(5), false), (ms(20), true)]),
+  );
+  assert_eq!(
+    pitch().gate(on).updates().collect_timed(),
+    vec![
+      (ms(0), Some('c')),
+      (ms(5), None),
+      (ms(5), None),
+      (ms(10), None),
+      (ms(5), Some('e')),
+    ]
+  );
+  assert_eq!(
+    pitch()
+      .or(Var::constant(Some('g')))
+      .updates()
+      .collect_timed(),
+    vec![(ms(0), Some('c')), (ms(10), Some('g')), (ms(10), Some('e'))]
+  );
+  // Updates that don't change the result don't strike the note again.
+  let fallback = || {
+    Var::from_updates(
+      Some('g'),
+      Stream::from_iter(vec![(ms(10), Some('a')), (ms(10), None)]),
+    )
+  };
+  assert_eq!(
+    Var::constant(Some('c'))
+      .or(fallback())
+      .updates()
+      .collect_timed(),
+    vec![(ms(0), Some('c'))]
+  );
+  let toggles = Var::from_updates(true, Stream::from_iter(vec![(ms(5), true), (ms(5), false)]));
+  assert_eq!(
+    Var::constant(Some('c'))
+      .gate(toggles)
+      .updates()
+      .collect_timed(),
+    vec![(ms(0), Some('c')), (ms(10), None)]
+  );
+}
+
+#[test]
+fn test_inspect() {
+  let ms = Duration::from_millis;
+  let var = Var::from_updates(1, Stream::from_iter(vec![(ms(10), 2)]));
+  assert_eq!(
+    var.inspect("level").updates().collect_timed(),
+    vec![(ms(0), 1), (ms(10), 2)]
+  );
+}
+
+#[test]
+fn test_share() {
+  let ms = Duration::from_millis;
+  let intensity =
+    Var::from_updates(0, Stream::from_iter((1..=5).map(move |i| (ms(10), i)))).share();
+  let velocity = intensity.var().map(|i| 60 + i * 10);
+  let density = intensity.var();
+  let updates = |var: Var<u32>| var.updates().collect_timed();
+  let expected_density = updates(Var::from_updates(
+    0,
+    Stream::from_iter((1..=5).map(move |i| (ms(10), i))),
+  ));
+  assert_eq!(updates(density), expected_density);
+  assert_eq!(updates(velocity)[5], (ms(10), 110));
+  assert_eq!(intensity.broadcast.borrow().buffer.len(), 5);
+  let late = intensity.clone();
+  drop(intensity);
+  assert_eq!(updates(late.var()), expected_density);
+  let broadcast = late.broadcast.clone();
+  drop(late);
+  assert!(broadcast.borrow().buffer.is_empty());
+}
+
+#[test]
+fn test_ease_to() {
+  let ms = Duration::from_millis;
+  for easing in [Easing::Linear, Easing::Exponential, Easing::Sigmoid] {
+    assert!(easing.apply(0.0).abs() < 1e-9);
+    assert!((easing.apply(1.0) - 1.0).abs() < 1e-9);
+    let samples: Vec<f64> = (0..=10).map(|i| easing.apply(i as f64 / 10.0)).collect();
+    assert!(samples.windows(2).all(|pair| pair[1] > pair[0]));
+  }
+  assert!(Easing::Exponential.apply(0.5) < 0.5);
+  assert!(Easing::Sigmoid.apply(0.2) < 0.2);
+  assert!((Easing::Sigmoid.apply(0.5) - 0.5).abs() < 1e-9);
+
+  let crescendo = Var::constant(40.0).ease_to(100.0, ms(100), Easing::Linear, ms(25));
+  crate::assert_stream_eq!(
+    crescendo.updates(),
+    vec![
+      (ms(0), 40.0),
+      (ms(25), 55.0),
+      (ms(25), 70.0),
+      (ms(25), 85.0),
+      (ms(25), 100.0),
+    ],
+  );
+  let moving = Var::from_updates(
+    0.0,
+    Stream::from_iter(vec![(ms(50), 10.0), (ms(100), 20.0)]),
+  );
+  let eased = moving.ease_to(100.0, ms(100), Easing::Linear, ms(50));
+  assert_eq!(
+    eased.updates().collect_timed(),
+    vec![
+      (ms(0), 0.0),
+      (ms(50), 55.0),
+      (ms(50), 100.0),
+      (ms(50), 100.0)
+    ]
+  );
+}
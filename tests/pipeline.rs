@@ -0,0 +1,77 @@
+use avril::event;
+use avril::generator::Phrases;
+use avril::midi::gm::drums;
+use avril::midi::smf::{Event, Smf};
+use avril::midi::validate::validate;
+use avril::prelude::*;
+use std::time::Duration;
+
+// Absolute times keep the comparison exact, as every event falls on a tick.
+fn messages(channel: Channel, notes: Stream<NoteEvent>) -> Vec<(Duration, Message)> {
+  event::lower(channel, notes).timestamps().collect()
+}
+
+#[test]
+fn test_pipeline() {
+  let quarter = Duration::from_millis(500);
+  let length = quarter * 16;
+  let ctx = GenCtx::new(Key::minor(Note::new(PitchClass::A, 3)), quarter / 2);
+  let phrases = Phrases {
+    params: Params {
+      range: Range::new(Note::new(PitchClass::A, 3), Note::new(PitchClass::A, 5)),
+      ..Params::new(ctx.beat)
+    },
+    ..Phrases::default()
+  };
+  let melody = || phrases.generate(&ctx, Seed::new("pipeline")).take(length);
+  let step = quarter / 4;
+  let hits = (0..16).map(|i| {
+    let drum = if i % 4 == 0 {
+      drums::BASS_DRUM_1
+    } else {
+      drums::CLOSED_HI_HAT
+    };
+    (
+      step * i,
+      NoteEvent::new(Note::from_midi(drum), 90, step / 2),
+    )
+  });
+  let beat = Pattern::from_stream(Stream::from_timestamps(hits), step * 16);
+  let drums = || beat.looped().take(length);
+
+  let tracks = vec![
+    messages(Channel::Ch1, melody()),
+    messages(Channel::Ch10, drums()),
+  ];
+  assert!(tracks.iter().all(|track| !track.is_empty()));
+  let mixed = Stream::merge_all_by(
+    vec![
+      event::lower(Channel::Ch1, melody()),
+      event::lower(Channel::Ch10, drums()),
+    ],
+    |a, b| a <= b,
+  );
+  assert_eq!(validate(mixed), vec![]);
+
+  let smf = Smf::new(quarter);
+  let file = smf.write(
+    tracks
+      .iter()
+      .map(|track| Stream::from_timestamps(track.clone()).map(Event::Message)),
+  );
+  let (read, read_tracks) = Smf::read(&file).unwrap();
+  assert_eq!(read, smf);
+  let read_tracks: Vec<Vec<_>> = read_tracks
+    .into_iter()
+    .map(|track| {
+      track
+        .filter_map(|event| match event {
+          Event::Message(message) => Some(message),
+          _ => None,
+        })
+        .timestamps()
+        .collect()
+    })
+    .collect();
+  assert_eq!(read_tracks, tracks);
+}